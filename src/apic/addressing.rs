@@ -0,0 +1,353 @@
+use super::*;
+
+/// Max count of processors in flat logical model.
+pub const FLAT_MAX_CPUS: u32 = 8;
+
+/// Count of processors in a single xAPIC cluster.
+pub const XAPIC_CLUSTER_SIZE: u32 = 4;
+
+/// Max count of xAPIC clusters. Cluster 0xF addresses all clusters.
+pub const XAPIC_MAX_CLUSTERS: u32 = 15;
+
+/// Count of processors in a single x2APIC cluster.
+pub const X2APIC_CLUSTER_SIZE: u32 = 16;
+
+/// Logical destination model.
+#[derive(PartialEq, Clone, Copy)]
+pub enum LogicalModel {
+
+    /// xAPIC flat model. Each of up to 8 processors gets its own bit
+    /// in the logical ID.
+    Flat,
+
+    /// xAPIC cluster model. Upper four bits of the logical ID hold
+    /// the cluster and lower four bits select up to 4 processors in it.
+    Cluster,
+
+    /// x2APIC cluster model. Logical ID is derived by hardware from the
+    /// x2APIC ID: bits 31:16 hold the cluster and bits 15:0 select up to
+    /// 16 processors in it.
+    X2ApicCluster,
+}
+
+/// Logical destination that addresses a set of processors.
+#[derive(PartialEq, Clone, Copy)]
+pub struct LogicalDestination {
+    model   : LogicalModel,
+    dest    : u32,
+}
+
+impl LogicalModel {
+
+    /// Model that matches current mode of given local APIC. Flat model
+    /// is used for xAPIC if all processors fit into it.
+    pub fn for_apic(apic: &LocalApic, cpu_count: u32) -> Self {
+        use self::LogicalModel::*;
+
+        match apic.mode() {
+            LocalApicMode::X2Apic           => X2ApicCluster,
+            _ if cpu_count <= FLAT_MAX_CPUS => Flat,
+            _                               => Cluster,
+        }
+    }
+
+    /// Max count of processors that can be addressed in this model.
+    pub fn max_cpus(&self) -> u32 {
+        use self::LogicalModel::*;
+        match *self {
+            Flat            => FLAT_MAX_CPUS,
+            Cluster         => XAPIC_CLUSTER_SIZE * XAPIC_MAX_CLUSTERS,
+            X2ApicCluster   => !0,
+        }
+    }
+
+    /// Logical ID of given processor. For xAPIC models the processor is
+    /// given by its sequential number which is assigned by software. For
+    /// x2APIC the processor is given by its x2APIC ID. None is returned if
+    /// the processor can't be addressed in this model.
+    pub fn logical_id(&self, cpu: u32) -> Option<u32> {
+        use self::LogicalModel::*;
+
+        match *self {
+            Flat            => {
+                if cpu >= FLAT_MAX_CPUS {
+                    return None;
+                }
+                Some(1 << cpu)
+            },
+            Cluster         => {
+                let cluster = cpu / XAPIC_CLUSTER_SIZE;
+                if cluster >= XAPIC_MAX_CLUSTERS {
+                    return None;
+                }
+                let member = 1 << (cpu % XAPIC_CLUSTER_SIZE);
+                Some((cluster << 4) | member)
+            },
+            X2ApicCluster   => {
+                let cluster = cpu >> 4;
+                let member = 1 << (cpu & 0xF);
+                Some((cluster << 16) | member)
+            },
+        }
+    }
+
+    /// Split logical ID into cluster and member bits. Flat model has
+    /// a single cluster 0.
+    fn split(&self, id: u32) -> (u32, u32) {
+        use self::LogicalModel::*;
+        match *self {
+            Flat            => (0, id & 0xFF),
+            Cluster         => (id >> 4, id & 0xF),
+            X2ApicCluster   => (id >> 16, id & 0xFFFF),
+        }
+    }
+
+    /// Logical destination that addresses all given processors. See
+    /// `logical_id` for how processors are given. None is returned if
+    /// any processor can't be addressed or if the processors are in
+    /// different clusters, as single destination can address only one
+    /// cluster.
+    pub fn destination<I>(&self, cpus: I) -> Option<LogicalDestination>
+            where I: IntoIterator<Item = u32> {
+        let mut cluster = None;
+        let mut members = 0;
+
+        for cpu in cpus {
+            let (c, m) = self.split(self.logical_id(cpu)?);
+            match cluster {
+                Some(prev) if prev != c => return None,
+                _                       => cluster = Some(c),
+            }
+            members |= m;
+        }
+
+        let cluster = cluster?;
+        let dest = match *self {
+            LogicalModel::Flat          => members,
+            LogicalModel::Cluster       => (cluster << 4) | members,
+            LogicalModel::X2ApicCluster => (cluster << 16) | members,
+        };
+
+        Some(LogicalDestination {
+            model   : *self,
+            dest,
+        })
+    }
+
+    /// Destination that addresses all processors. In cluster models this
+    /// is the broadcast to all clusters.
+    pub fn broadcast(&self) -> LogicalDestination {
+        let dest = match *self {
+            LogicalModel::X2ApicCluster => !0,
+            _                           => 0xFF,
+        };
+
+        LogicalDestination {
+            model   : *self,
+            dest,
+        }
+    }
+
+    /// Set DFR and LDR of given local APIC so it accepts logical
+    /// destinations that include given processor. All processors must be
+    /// configured with the same model. In x2APIC mode LDR is read-only
+    /// and nothing is written. Returns false if the model does not match
+    /// the mode of the local APIC or the processor can't be addressed.
+    pub fn configure(&self, apic: &mut LocalApic, cpu: u32) -> bool {
+        let x2apic = apic.mode() == LocalApicMode::X2Apic;
+        if x2apic != (*self == LogicalModel::X2ApicCluster) {
+            return false;
+        }
+
+        let id = match self.logical_id(cpu) {
+            Some(id) => id,
+            None     => return false,
+        };
+
+        if x2apic {
+            return apic.ldr().x2apic_logical_id() == id;
+        }
+
+        let model = match *self {
+            LogicalModel::Flat  => MdaModel::Flat,
+            _                   => MdaModel::Cluster,
+        };
+
+        apic.dfr_mut().set_model(model);
+        apic.ldr_mut().set_logical_apic_id(id as u8);
+        true
+    }
+}
+
+impl LogicalDestination {
+
+    /// Logical model this destination is defined for.
+    pub fn model(&self) -> LogicalModel {
+        self.model
+    }
+
+    /// Raw destination field value.
+    pub fn raw(&self) -> u32 {
+        self.dest
+    }
+
+    /// Destination to be used for IPIs.
+    pub fn ipi_destination(&self) -> IpiDestination {
+        IpiDestination::Logical(self.dest)
+    }
+
+    /// Destination field of I/O APIC redirection entry. None is returned
+    /// for x2APIC destinations as they do not fit into 8 bits and require
+    /// interrupt remapping.
+    pub fn ioapic_destination(&self) -> Option<u8> {
+        match self.model {
+            LogicalModel::X2ApicCluster => None,
+            _                           => Some(self.dest as u8),
+        }
+    }
+}
+
+impl<'a> Icr<'a> {
+
+    /// Set logical destination mode and the destination field.
+    pub fn set_logical_destination(&mut self, dest: &LogicalDestination) {
+        self.set_destination_mode(DestinationMode::Logical);
+        match dest.model {
+            LogicalModel::X2ApicCluster => {
+                self.set_x2apic_destination(dest.dest)
+            },
+            _ => self.set_destination(dest.dest as u8),
+        }
+    }
+}
+
+impl RedirectionEntry {
+
+    /// Set logical destination mode and the destination field. Returns
+    /// false and leaves the entry unchanged if the destination can't be
+    /// used in I/O APIC.
+    pub fn set_logical_destination(&mut self, dest: &LogicalDestination)
+            -> bool {
+        match dest.ioapic_destination() {
+            Some(d) => {
+                self.set_destination_mode(DestinationMode::Logical);
+                self.set_destination(d);
+                true
+            },
+            None    => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+    use super::LogicalModel::*;
+
+    const APIC_BASE_MSR : u32 = 0x1B;
+
+    #[test]
+    fn logical_ids() {
+        let table: [(LogicalModel, u32, Option<u32>); 12] = [
+            (Flat,          0,      Some(0x01)),
+            (Flat,          3,      Some(0x08)),
+            (Flat,          7,      Some(0x80)),
+            (Flat,          8,      None),
+            (Cluster,       0,      Some(0x01)),
+            (Cluster,       5,      Some(0x12)),
+            (Cluster,       59,     Some(0xE8)),
+            (Cluster,       60,     None),
+            (X2ApicCluster, 0x00,   Some(0x0000_0001)),
+            (X2ApicCluster, 0x0F,   Some(0x0000_8000)),
+            (X2ApicCluster, 0x23,   Some(0x0002_0008)),
+            (X2ApicCluster, 0x1_0010, Some(0x1001_0001)),
+        ];
+
+        for &(model, cpu, id) in table.iter() {
+            assert_eq!(model.logical_id(cpu), id, "cpu {}", cpu);
+        }
+    }
+
+    #[test]
+    fn destinations() {
+        let table: [(LogicalModel, &[u32], Option<u32>); 9] = [
+            (Flat,          &[0, 3],            Some(0x09)),
+            (Flat,          &[3, 8],            None),
+            (Flat,          &[],                None),
+            (Cluster,       &[4, 5, 7],         Some(0x1B)),
+            (Cluster,       &[3, 4],            None),
+            (Cluster,       &[56],              Some(0xE1)),
+            (X2ApicCluster, &[0x20, 0x23],      Some(0x0002_0009)),
+            (X2ApicCluster, &[0x0F, 0x10],      None),
+            (X2ApicCluster, &[0x2F],            Some(0x0002_8000)),
+        ];
+
+        for &(model, cpus, dest) in table.iter() {
+            let d = model.destination(cpus.iter().cloned());
+            assert_eq!(d.map(|d| d.raw()), dest, "cpus {:?}", cpus);
+        }
+
+        let d = Cluster.destination([4, 5].iter().cloned()).unwrap();
+        assert_eq!(d.ioapic_destination(), Some(0x13));
+        let d = X2ApicCluster.destination(Some(0x23)).unwrap();
+        assert_eq!(d.ioapic_destination(), None);
+
+        assert_eq!(Flat.broadcast().raw(), 0xFF);
+        assert_eq!(Cluster.broadcast().raw(), 0xFF);
+        assert_eq!(X2ApicCluster.broadcast().raw(), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn dfr_model_round_trip() {
+        let mut dfr = Dfr::from_raw(0);
+        assert!(dfr.model() == MdaModel::Cluster);
+
+        dfr.set_model(MdaModel::Flat);
+        assert!(dfr.model() == MdaModel::Flat);
+        assert_eq!(dfr.raw(), 0xFFFF_FFFF);
+
+        dfr.set_model(MdaModel::Cluster);
+        assert!(dfr.model() == MdaModel::Cluster);
+        assert_eq!(dfr.raw(), 0x0FFF_FFFF);
+    }
+
+    #[test]
+    fn configure_xapic_flat() {
+        let mock = Mock::new();
+        mock.set_msr(APIC_BASE_MSR, 0xFEE0_0900);
+
+        with_backend(&mock, || {
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            assert!(!X2ApicCluster.configure(&mut apic, 3));
+            assert!(!Flat.configure(&mut apic, 8));
+            assert!(Flat.configure(&mut apic, 3));
+        });
+
+        assert_eq!(mock.mmio(0xFEE0_00E0), Some(0xFFFF_FFFF));
+        assert_eq!(mock.mmio(0xFEE0_00D0), Some(0x08 << 24));
+    }
+
+    #[test]
+    fn configure_x2apic_checks_ldr() {
+        let mock = Mock::new();
+        mock.set_msr(APIC_BASE_MSR, 0xFEE0_0D00);
+        mock.set_msr(0x80D, 0x0002_0008);
+
+        with_backend(&mock, || {
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            assert!(!Flat.configure(&mut apic, 3));
+            assert!(!X2ApicCluster.configure(&mut apic, 0x22));
+            assert!(X2ApicCluster.configure(&mut apic, 0x23));
+        });
+
+        // LDR is read-only in x2APIC mode.
+        assert!(mock.log_eq(&[
+            MsrRead (0x1B,  0xFEE0_0D00),
+            MsrRead (0x80D, 0x0002_0008),
+            MsrRead (0x80D, 0x0002_0008),
+        ]), "{:?}", &*mock.log());
+    }
+}
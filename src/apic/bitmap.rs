@@ -0,0 +1,171 @@
+/// 256-bit set of interrupt vectors as stored in ISR, TMR and IRR
+/// registers. Each register holds 32 vectors and bit N of the register
+/// with index I corresponds to vector I * 32 + N.
+#[derive(PartialEq, Clone, Copy)]
+pub struct InterruptBitmap {
+    words   : [u32; 8],
+}
+
+/// Iterator over vectors set in interrupt bitmap. Vectors are returned
+/// in ascending order.
+pub struct InterruptBitmapIter {
+    words   : [u32; 8],
+    index   : usize,
+}
+
+impl InterruptBitmap {
+
+    /// Create bitmap from eight register values, starting from
+    /// the register that holds vectors 0-31.
+    pub fn from_words(words: [u32; 8]) -> Self {
+        InterruptBitmap { words }
+    }
+
+    /// Raw register values of this bitmap.
+    pub fn words(&self) -> [u32; 8] {
+        self.words
+    }
+
+    /// Whether bit of given vector is set.
+    pub fn is_set(&self, vector: u8) -> bool {
+        let word = self.words[vector as usize / 32];
+        word & (1 << (vector % 32)) != 0
+    }
+
+    /// Whether no vector is set.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|w| *w == 0)
+    }
+
+    /// Count of set vectors.
+    pub fn count(&self) -> u32 {
+        self.words.iter().map(|w| w.count_ones()).sum()
+    }
+
+    /// Highest vector that is set. As priority of the vector is given
+    /// by its upper four bits this is the vector of highest priority.
+    pub fn highest(&self) -> Option<u8> {
+        for (i, w) in self.words.iter().enumerate().rev() {
+            if *w != 0 {
+                let bit = 31 - w.leading_zeros() as usize;
+                return Some((i * 32 + bit) as u8);
+            }
+        }
+        None
+    }
+
+    /// Lowest vector that is set.
+    pub fn lowest(&self) -> Option<u8> {
+        for (i, w) in self.words.iter().enumerate() {
+            if *w != 0 {
+                let bit = w.trailing_zeros() as usize;
+                return Some((i * 32 + bit) as u8);
+            }
+        }
+        None
+    }
+
+    /// Iterator over set vectors in ascending order.
+    pub fn iter(&self) -> InterruptBitmapIter {
+        InterruptBitmapIter {
+            words   : self.words,
+            index   : 0,
+        }
+    }
+}
+
+impl Iterator for InterruptBitmapIter {
+
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        while self.index < self.words.len() {
+            let w = self.words[self.index];
+            if w == 0 {
+                self.index += 1;
+                continue;
+            }
+
+            let bit = w.trailing_zeros() as usize;
+            // Clear lowest set bit so it is not returned again.
+            self.words[self.index] = w & (w - 1);
+            return Some((self.index * 32 + bit) as u8);
+        }
+        None
+    }
+}
+
+impl IntoIterator for InterruptBitmap {
+
+    type Item = u8;
+    type IntoIter = InterruptBitmapIter;
+
+    fn into_iter(self) -> InterruptBitmapIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bitmap with given vectors set.
+    fn bitmap(vectors: &[u8]) -> InterruptBitmap {
+        let mut words = [0; 8];
+        for &v in vectors {
+            words[v as usize / 32] |= 1 << (v % 32);
+        }
+        InterruptBitmap::from_words(words)
+    }
+
+    #[test]
+    fn empty_bitmap() {
+        let b = InterruptBitmap::from_words([0; 8]);
+
+        assert!(b.is_empty());
+        assert_eq!(b.count(), 0);
+        assert_eq!(b.highest(), None);
+        assert_eq!(b.lowest(), None);
+        assert_eq!(b.iter().next(), None);
+        assert!(!b.is_set(0) && !b.is_set(255));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let b = bitmap(&[31, 32, 255]);
+        assert_eq!(b.words(), [1 << 31, 1, 0, 0, 0, 0, 0, 1 << 31]);
+
+        assert!(b.is_set(31) && b.is_set(32) && b.is_set(255));
+        assert!(!b.is_set(0) && !b.is_set(30) && !b.is_set(33)
+                && !b.is_set(63) && !b.is_set(224) && !b.is_set(254));
+        assert!(!b.is_empty());
+        assert_eq!(b.count(), 3);
+        assert_eq!(b.highest(), Some(255));
+        assert_eq!(b.lowest(), Some(31));
+
+        let b = bitmap(&[32]);
+        assert_eq!(b.highest(), Some(32));
+        assert_eq!(b.lowest(), Some(32));
+
+        let b = bitmap(&[0, 31]);
+        assert_eq!(b.highest(), Some(31));
+        assert_eq!(b.lowest(), Some(0));
+    }
+
+    #[test]
+    fn iterator_is_ascending() {
+        let b = bitmap(&[255, 0x41, 32, 31, 0x40, 0, 0xE0]);
+        let expected = [0, 31, 32, 0x40, 0x41, 0xE0, 255];
+
+        let mut it = b.iter();
+        for &v in expected.iter() {
+            assert_eq!(it.next(), Some(v));
+        }
+        assert_eq!(it.next(), None);
+        assert_eq!(it.next(), None);
+
+        assert!(b.into_iter().eq(expected.iter().cloned()));
+        // Iteration does not consume the bitmap.
+        assert_eq!(b.count(), expected.len() as u32);
+    }
+}
@@ -0,0 +1,579 @@
+use super::{DeliveryMode, DeliveryStatus, DestinationMode, PinPolarity,
+        TriggerMode};
+use hw::Backend;
+
+/// Default physical address of the first I/O APIC registers.
+pub const IOAPIC_DEFAULT_BASE: usize = 0xFEC0_0000;
+
+/// Offset of I/O register select register from the I/O APIC base.
+const IOREGSEL: usize = 0x00;
+
+/// Offset of I/O window register from the I/O APIC base.
+const IOWIN: usize = 0x10;
+
+/// Offset of EOI register from the I/O APIC base. Available since
+/// I/O APIC version 0x20.
+const EOI: usize = 0x40;
+
+/// First I/O APIC version with EOI register.
+const EOI_MIN_VERSION: u8 = 0x20;
+
+/// Count of redirection table entries that fit into 8-bit register
+/// index space after the first redirection table register.
+const MAX_ADDRESSABLE_ENTRIES: u16 = (0x100 - 0x10) / 2;
+
+/// I/O APIC handle.
+pub struct IoApic {
+    base    : usize,
+}
+
+/// List of I/O APIC registers and their indices. The registers are
+/// accessed indirectly: the index is written into IOREGSEL and the value
+/// is then read from or written to IOWIN.
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum IoApicReg {
+    Id                  = 0x00, // RW
+    Version             = 0x01, // RO
+    Arbitration         = 0x02, // RO
+
+    /// First redirection table register. Each entry takes two registers:
+    /// low dword at 0x10 + 2 * n and high dword at 0x11 + 2 * n.
+    RedirectionTable    = 0x10, // RW
+}
+
+/// I/O APIC identification register.
+#[derive(Clone, Copy)]
+pub struct IoApicId {
+    reg     : u32,
+}
+
+/// I/O APIC version register.
+#[derive(Clone, Copy)]
+pub struct IoApicVersion {
+    reg     : u32,
+}
+
+/// I/O APIC arbitration register.
+#[derive(Clone, Copy)]
+pub struct IoApicArbitration {
+    reg     : u32,
+}
+
+/// Entry of the I/O APIC redirection table.
+#[derive(Clone, Copy)]
+pub struct RedirectionEntry {
+    reg     : u64,
+}
+
+impl IoApic {
+
+    /// Create I/O APIC handle for registers mapped at given address.
+    ///
+    /// # Safety
+    /// Caller must ensure that I/O APIC registers are actually mapped
+    /// at given address and that no other handle accesses the same
+    /// I/O APIC concurrently, as IOREGSEL/IOWIN access is not atomic.
+    pub unsafe fn new(base: usize) -> IoApic {
+        IoApic { base }
+    }
+
+    /// Base address of I/O APIC registers mapped to RAM.
+    pub fn base_addr(&self) -> usize {
+        self.base
+    }
+
+    /// Read register with given index.
+    pub fn read_reg(&self, index: u8) -> u32 {
+        let hw = ::hw::backend();
+
+        unsafe {
+            hw.mmio_write32(self.base + IOREGSEL, index as u32);
+            hw.mmio_read32(self.base + IOWIN)
+        }
+    }
+
+    /// Write register with given index.
+    ///
+    /// # Safety
+    /// Value is written as is and may break interrupt routing.
+    pub unsafe fn write_reg(&mut self, index: u8, val: u32) {
+        let hw = ::hw::backend();
+
+        hw.mmio_write32(self.base + IOREGSEL, index as u32);
+        hw.mmio_write32(self.base + IOWIN, val);
+    }
+
+    /// I/O APIC identification register.
+    pub fn id(&self) -> IoApicId {
+        IoApicId { reg : self.read_reg(IoApicReg::Id as _) }
+    }
+
+    /// Overwrite I/O APIC identification register.
+    ///
+    /// # Safety
+    /// ID must be unique among APIC agents on the APIC bus.
+    pub unsafe fn set_id(&mut self, id: IoApicId) {
+        self.write_reg(IoApicReg::Id as _, id.reg);
+    }
+
+    /// I/O APIC version register.
+    pub fn version(&self) -> IoApicVersion {
+        IoApicVersion { reg : self.read_reg(IoApicReg::Version as _) }
+    }
+
+    /// I/O APIC arbitration register.
+    pub fn arbitration(&self) -> IoApicArbitration {
+        IoApicArbitration { reg : self.read_reg(IoApicReg::Arbitration as _) }
+    }
+
+    /// Count of redirection table entries of this I/O APIC. Entries that
+    /// can't be addressed with 8-bit register index are not counted.
+    pub fn redirection_entry_count(&self) -> u16 {
+        self.version().redirection_entry_count().min(MAX_ADDRESSABLE_ENTRIES)
+    }
+
+    /// Index of low dword register of given redirection table entry.
+    fn redirection_reg(index: u8) -> u8 {
+        IoApicReg::RedirectionTable as u8 + index * 2
+    }
+
+    /// Redirection table entry for given input pin. None is returned
+    /// if the index exceeds max redirection entry of this I/O APIC.
+    pub fn redirection_entry(&self, index: u8) -> Option<RedirectionEntry> {
+        if index as u16 >= self.redirection_entry_count() {
+            return None;
+        }
+
+        let reg = Self::redirection_reg(index);
+        let lo = self.read_reg(reg) as u64;
+        let hi = self.read_reg(reg + 1) as u64;
+
+        Some(RedirectionEntry { reg : lo | (hi << 32) })
+    }
+
+    /// Store redirection table entry for given input pin. Entry is masked
+    /// while its destination is changed so no interrupt is delivered with
+    /// half-written value. Returns false if the index exceeds max
+    /// redirection entry of this I/O APIC and nothing was written.
+    ///
+    /// # Safety
+    /// Entry must contain valid vector and delivery settings.
+    pub unsafe fn set_redirection_entry(&mut self, index: u8,
+            entry: RedirectionEntry) -> bool {
+        if index as u16 >= self.redirection_entry_count() {
+            return false;
+        }
+
+        let reg = Self::redirection_reg(index);
+        let lo = entry.reg as u32;
+        let hi = (entry.reg >> 32) as u32;
+
+        let old = self.read_reg(reg);
+        self.write_reg(reg, old | RedirectionEntry::MASK as u32);
+        self.write_reg(reg + 1, hi);
+        self.write_reg(reg, lo);
+        true
+    }
+
+    /// Mask input pin with given index. Returns false if there is no
+    /// such pin.
+    pub fn mask(&mut self, index: u8) -> bool {
+        if index as u16 >= self.redirection_entry_count() {
+            return false;
+        }
+
+        let reg = Self::redirection_reg(index);
+        let val = self.read_reg(reg) | RedirectionEntry::MASK as u32;
+        unsafe { self.write_reg(reg, val); }
+        true
+    }
+
+    /// Unmask input pin with given index. Returns false if there is no
+    /// such pin.
+    ///
+    /// # Safety
+    /// Redirection entry must be configured before unmasking.
+    pub unsafe fn unmask(&mut self, index: u8) -> bool {
+        if index as u16 >= self.redirection_entry_count() {
+            return false;
+        }
+
+        let reg = Self::redirection_reg(index);
+        let val = self.read_reg(reg) & !(RedirectionEntry::MASK as u32);
+        self.write_reg(reg, val);
+        true
+    }
+
+    /// Mask all input pins of this I/O APIC.
+    pub fn mask_all(&mut self) {
+        for i in 0..self.redirection_entry_count() {
+            self.mask(i as u8);
+        }
+    }
+
+    /// Whether this I/O APIC has EOI register.
+    pub fn has_eoi_register(&self) -> bool {
+        self.version().version() >= EOI_MIN_VERSION
+    }
+
+    /// Send directed EOI for level triggered interrupt with given vector.
+    /// This clears remote IRR of the entries with this vector so the
+    /// interrupt can be delivered again. Is needed when local APIC
+    /// EOI broadcast suppression is enabled.
+    ///
+    /// Older I/O APICs have no EOI register. Remote IRR is then cleared
+    /// by switching the entry to edge trigger mode and back.
+    pub fn eoi(&mut self, vector: u8) {
+        if self.has_eoi_register() {
+            unsafe {
+                ::hw::backend().mmio_write32(self.base + EOI, vector as u32);
+            }
+            return;
+        }
+
+        for i in 0..self.redirection_entry_count() {
+            let reg = Self::redirection_reg(i as u8);
+            let lo = self.read_reg(reg);
+            let entry = RedirectionEntry { reg : lo as u64 };
+
+            if entry.vector() != vector || !entry.remote_irr() {
+                continue;
+            }
+
+            let edge = (lo | RedirectionEntry::MASK as u32)
+                    & !(RedirectionEntry::TRIGGER_MODE as u32);
+            unsafe {
+                self.write_reg(reg, edge);
+                self.write_reg(reg, lo);
+            }
+        }
+    }
+}
+
+impl IoApicId {
+
+    /// I/O APIC ID.
+    pub fn id(&self) -> u8 {
+        ((self.reg >> 24) & 0xF) as _
+    }
+
+    /// Set I/O APIC ID.
+    pub fn set_id(&mut self, id: u8) {
+        self.reg = self.reg & !(0xF << 24) | (((id & 0xF) as u32) << 24);
+    }
+}
+
+impl IoApicVersion {
+
+    /// I/O APIC version.
+    pub fn version(&self) -> u8 {
+        (self.reg & 0xFF) as _
+    }
+
+    /// Index of the last redirection table entry. This is entry count
+    /// minus one.
+    pub fn max_redirection_entry(&self) -> u8 {
+        ((self.reg >> 16) & 0xFF) as _
+    }
+
+    /// Count of redirection table entries.
+    pub fn redirection_entry_count(&self) -> u16 {
+        self.max_redirection_entry() as u16 + 1
+    }
+}
+
+impl IoApicArbitration {
+
+    /// Bus arbitration priority of this I/O APIC.
+    pub fn arbitration_id(&self) -> u8 {
+        ((self.reg >> 24) & 0xF) as _
+    }
+}
+
+impl RedirectionEntry {
+
+    const VECTOR        : u64 = 0xFF;
+    const DELIVERY_MODE : u64 = 0b111 << 8;
+    const DEST_MODE     : u64 = 1 << 11;
+    const DELIV_STATUS  : u64 = 1 << 12;
+    const POLARITY      : u64 = 1 << 13;
+    const REMOTE_IRR    : u64 = 1 << 14;
+    const TRIGGER_MODE  : u64 = 1 << 15;
+    const MASK          : u64 = 1 << 16;
+    const DESTINATION   : u64 = 0xFF << 56;
+
+    /// Create new masked entry with fixed delivery mode, physical
+    /// destination, active high polarity and edge trigger mode.
+    pub fn new() -> Self {
+        RedirectionEntry { reg : Self::MASK }
+    }
+
+    /// Interrupt vector.
+    pub fn vector(&self) -> u8 {
+        (self.reg & Self::VECTOR) as _
+    }
+
+    /// Set interrupt vector.
+    pub fn set_vector(&mut self, vec: u8) {
+        self.reg = self.reg & !Self::VECTOR | (vec as u64);
+    }
+
+    /// Delivery mode. None if the field holds reserved value.
+    pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+        DeliveryMode::from_raw(((self.reg & Self::DELIVERY_MODE) >> 8) as u32)
+    }
+
+    /// Set delivery mode.
+    pub fn set_delivery_mode(&mut self, mode: DeliveryMode) {
+        let val = (mode as u64) << 8;
+        self.reg = self.reg & !Self::DELIVERY_MODE | val;
+    }
+
+    /// Destination mode.
+    pub fn destination_mode(&self) -> DestinationMode {
+        use self::DestinationMode::*;
+
+        if self.reg & Self::DEST_MODE != 0 {
+            Logical
+        } else {
+            Physical
+        }
+    }
+
+    /// Set destination mode.
+    pub fn set_destination_mode(&mut self, mode: DestinationMode) {
+        use self::DestinationMode::*;
+
+        match mode {
+            Logical     => self.reg |=  Self::DEST_MODE,
+            Physical    => self.reg &= !Self::DEST_MODE
+        }
+    }
+
+    /// Delivery status.
+    pub fn delivery_status(&self) -> DeliveryStatus {
+        use self::DeliveryStatus::*;
+
+        if self.reg & Self::DELIV_STATUS != 0 {
+            SendPending
+        } else {
+            Idle
+        }
+    }
+
+    /// Interrupt input pin polarity.
+    pub fn input_polarity(&self) -> PinPolarity {
+        use self::PinPolarity::*;
+
+        if self.reg & Self::POLARITY != 0 {
+            ActiveLow
+        } else {
+            ActiveHigh
+        }
+    }
+
+    /// Set interrupt input pin polarity.
+    pub fn set_input_polarity(&mut self, pp: PinPolarity) {
+        use self::PinPolarity::*;
+
+        match pp {
+            ActiveHigh  => self.reg &= !Self::POLARITY,
+            ActiveLow   => self.reg |=  Self::POLARITY
+        }
+    }
+
+    /// Whether remote IRR flag is on. Is only valid for level triggered
+    /// interrupts.
+    pub fn remote_irr(&self) -> bool {
+        self.reg & Self::REMOTE_IRR != 0
+    }
+
+    /// Trigger mode.
+    pub fn trigger_mode(&self) -> TriggerMode {
+        use self::TriggerMode::*;
+
+        if self.reg & Self::TRIGGER_MODE != 0 {
+            LevelSensitive
+        } else {
+            EdgeSensitive
+        }
+    }
+
+    /// Set trigger mode.
+    pub fn set_trigger_mode(&mut self, mode: TriggerMode) {
+        use self::TriggerMode::*;
+
+        match mode {
+            EdgeSensitive   => self.reg &= !Self::TRIGGER_MODE,
+            LevelSensitive  => self.reg |=  Self::TRIGGER_MODE
+        }
+    }
+
+    /// Whether interrupt is masked or not.
+    pub fn masked(&self) -> bool {
+        self.reg & Self::MASK != 0
+    }
+
+    /// Mask this interrupt.
+    pub fn mask(&mut self) {
+        self.reg |= Self::MASK;
+    }
+
+    /// Unmask this interrupt.
+    pub fn unmask(&mut self) {
+        self.reg &= !Self::MASK;
+    }
+
+    /// Destination field. In physical mode only bits 3:0 hold the APIC ID.
+    /// In logical mode it is the set of processors.
+    pub fn destination(&self) -> u8 {
+        (self.reg >> 56) as _
+    }
+
+    /// Set destination field.
+    pub fn set_destination(&mut self, dest: u8) {
+        self.reg = self.reg & !Self::DESTINATION | ((dest as u64) << 56);
+    }
+}
+
+impl Default for RedirectionEntry {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<u64> for RedirectionEntry {
+
+    fn from(v: u64) -> Self {
+        RedirectionEntry { reg : v }
+    }
+}
+
+impl From<RedirectionEntry> for u64 {
+
+    fn from(val: RedirectionEntry) -> Self {
+        val.reg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    const SEL: usize = IOAPIC_DEFAULT_BASE + IOREGSEL;
+    const WIN: usize = IOAPIC_DEFAULT_BASE + IOWIN;
+
+    #[test]
+    fn set_redirection_entry_masks_first() {
+        let mock = Mock::new();
+        // Version 0x11 with 24 entries, then low dword of entry 2.
+        mock.push_mmio_read(WIN, 0x0017_0011);
+        mock.push_mmio_read(WIN, 0x0000_0031);
+
+        let mut entry = RedirectionEntry::new();
+        entry.set_vector(0x41);
+        entry.set_destination(0x03);
+        entry.unmask();
+
+        with_backend(&mock, || unsafe {
+            let mut ioapic = IoApic::new(IOAPIC_DEFAULT_BASE);
+            assert!(ioapic.set_redirection_entry(2, entry));
+        });
+
+        assert!(mock.log_eq(&[
+            MmioWrite(SEL, 0x01),
+            MmioRead (WIN, 0x0017_0011),
+            MmioWrite(SEL, 0x14),
+            MmioRead (WIN, 0x0000_0031),
+            MmioWrite(SEL, 0x14),
+            MmioWrite(WIN, 0x0001_0031), // Old entry masked.
+            MmioWrite(SEL, 0x15),
+            MmioWrite(WIN, 0x0300_0000), // High dword: destination.
+            MmioWrite(SEL, 0x14),
+            MmioWrite(WIN, 0x0000_0041), // Low dword: vector, unmasked.
+        ]), "{:?}", &*mock.log());
+
+        let mock = Mock::new();
+        mock.push_mmio_read(WIN, 0x0017_0011);
+        with_backend(&mock, || unsafe {
+            let mut ioapic = IoApic::new(IOAPIC_DEFAULT_BASE);
+            assert!(!ioapic.set_redirection_entry(24, entry));
+        });
+        assert_eq!(mock.log_len(), 2);
+    }
+
+    #[test]
+    fn eoi_register() {
+        let mock = Mock::new();
+        mock.push_mmio_read(WIN, 0x0017_0020);
+
+        with_backend(&mock, || unsafe {
+            IoApic::new(IOAPIC_DEFAULT_BASE).eoi(0x41);
+        });
+
+        assert!(mock.log_eq(&[
+            MmioWrite(SEL, 0x01),
+            MmioRead (WIN, 0x0017_0020),
+            MmioWrite(IOAPIC_DEFAULT_BASE + EOI, 0x41),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn eoi_without_register_toggles_trigger_mode() {
+        let mock = Mock::new();
+        // Version 0x11 with 3 entries is read twice.
+        mock.push_mmio_read(WIN, 0x0002_0011);
+        mock.push_mmio_read(WIN, 0x0002_0011);
+        // Level triggered entries. Only the first one has matching vector
+        // and remote IRR set.
+        mock.push_mmio_read(WIN, 0x0000_C041);
+        mock.push_mmio_read(WIN, 0x0000_C042);
+        mock.push_mmio_read(WIN, 0x0000_8041);
+
+        with_backend(&mock, || unsafe {
+            IoApic::new(IOAPIC_DEFAULT_BASE).eoi(0x41);
+        });
+
+        assert!(mock.log_eq(&[
+            MmioWrite(SEL, 0x01),
+            MmioRead (WIN, 0x0002_0011),
+            MmioWrite(SEL, 0x01),
+            MmioRead (WIN, 0x0002_0011),
+            MmioWrite(SEL, 0x10),
+            MmioRead (WIN, 0x0000_C041),
+            MmioWrite(SEL, 0x10),
+            MmioWrite(WIN, 0x0001_4041), // Masked, edge triggered.
+            MmioWrite(SEL, 0x10),
+            MmioWrite(WIN, 0x0000_C041), // Restored.
+            MmioWrite(SEL, 0x12),
+            MmioRead (WIN, 0x0000_C042),
+            MmioWrite(SEL, 0x14),
+            MmioRead (WIN, 0x0000_8041),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn max_redirection_entry_count() {
+        let v = IoApicVersion { reg : 0x00FF_0020 };
+        assert_eq!(v.max_redirection_entry(), 0xFF);
+        assert_eq!(v.redirection_entry_count(), 256);
+
+        let v = IoApicVersion { reg : 0x0017_0011 };
+        assert_eq!(v.redirection_entry_count(), 24);
+    }
+
+    #[test]
+    fn reserved_delivery_mode() {
+        let e = RedirectionEntry { reg : 0b011 << 8 };
+        assert_eq!(e.delivery_mode(), None);
+
+        let mut e = RedirectionEntry::new();
+        e.set_delivery_mode(DeliveryMode::ExtInt);
+        assert_eq!(e.delivery_mode(), Some(DeliveryMode::ExtInt));
+    }
+}
@@ -0,0 +1,404 @@
+use super::*;
+
+/// Default count of delivery status reads before IPI is considered
+/// not delivered.
+pub const DEFAULT_IPI_POLL_LIMIT: u32 = 100_000;
+
+/// Destination of inter-processor interrupt.
+#[derive(PartialEq, Clone, Copy)]
+pub enum IpiDestination {
+
+    /// Processor with given APIC ID. Only the low 8 bits are used in
+    /// xAPIC mode.
+    Physical(u32),
+
+    /// Set of processors given by logical destination.
+    Logical(u32),
+
+    /// This processor only.
+    SelfOnly,
+
+    /// All processors including this one.
+    All,
+
+    /// All processors except this one.
+    AllButSelf,
+}
+
+/// Settings of INIT-SIPI-SIPI sequence used to start application
+/// processor. Defaults follow the universal startup algorithm from
+/// the MultiProcessor Specification.
+#[derive(Clone, Copy)]
+pub struct ApStartup {
+    page            : u8,
+    init_delay_us   : u32,
+    sipi_delay_us   : u32,
+    sipi_count      : u8,
+    init_deassert   : bool,
+    poll_limit      : u32,
+}
+
+impl IpiDestination {
+
+    fn shorthand(&self) -> DestinationShorthand {
+        use self::IpiDestination::*;
+        use self::DestinationShorthand::*;
+
+        match *self {
+            Physical(_) | Logical(_)    => NoShorthand,
+            SelfOnly                    => SelfDestination,
+            All                         => AllIncludingSelf,
+            AllButSelf                  => AllExcludingSelf,
+        }
+    }
+
+    fn mode(&self) -> DestinationMode {
+        match *self {
+            IpiDestination::Logical(_)  => DestinationMode::Logical,
+            _                           => DestinationMode::Physical,
+        }
+    }
+
+    fn field(&self) -> u32 {
+        match *self {
+            IpiDestination::Physical(d) => d,
+            IpiDestination::Logical(d)  => d,
+            _                           => 0,
+        }
+    }
+}
+
+impl ApStartup {
+
+    /// Startup sequence that makes application processor execute code
+    /// at given address. Address must be 4 KiB aligned and lie below
+    /// 1 MiB, otherwise None is returned.
+    pub fn new(trampoline_addr: usize) -> Option<Self> {
+        if trampoline_addr & 0xFFF != 0 || trampoline_addr >= 0x10_0000 {
+            return None;
+        }
+
+        Some(ApStartup {
+            page            : (trampoline_addr >> 12) as u8,
+            init_delay_us   : 10_000,
+            sipi_delay_us   : 200,
+            sipi_count      : 2,
+            init_deassert   : false,
+            poll_limit      : DEFAULT_IPI_POLL_LIMIT,
+        })
+    }
+
+    /// Delay after INIT IPI in microseconds.
+    pub fn with_init_delay_us(mut self, us: u32) -> Self {
+        self.init_delay_us = us;
+        self
+    }
+
+    /// Delay after each SIPI in microseconds.
+    pub fn with_sipi_delay_us(mut self, us: u32) -> Self {
+        self.sipi_delay_us = us;
+        self
+    }
+
+    /// Count of SIPIs to send.
+    pub fn with_sipi_count(mut self, count: u8) -> Self {
+        self.sipi_count = count;
+        self
+    }
+
+    /// Send INIT level de-assert IPI after INIT. Only needed for
+    /// processors with discrete APIC (82489DX).
+    pub fn with_init_deassert(mut self, deassert: bool) -> Self {
+        self.init_deassert = deassert;
+        self
+    }
+
+    /// Count of delivery status reads before IPI is considered
+    /// not delivered.
+    pub fn with_poll_limit(mut self, limit: u32) -> Self {
+        self.poll_limit = limit;
+        self
+    }
+
+    /// Vector of SIPI which is the page number of the trampoline.
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+
+    /// Physical address application processor starts execution at.
+    pub fn trampoline_addr(&self) -> usize {
+        (self.page as usize) << 12
+    }
+
+    pub fn init_delay_us(&self) -> u32 {
+        self.init_delay_us
+    }
+
+    pub fn sipi_delay_us(&self) -> u32 {
+        self.sipi_delay_us
+    }
+
+    pub fn sipi_count(&self) -> u8 {
+        self.sipi_count
+    }
+
+    pub fn init_deassert(&self) -> bool {
+        self.init_deassert
+    }
+
+    pub fn poll_limit(&self) -> u32 {
+        self.poll_limit
+    }
+}
+
+impl LocalApic {
+
+    /// Wait until previously sent IPI is delivered. Returns false if it
+    /// is still pending after given count of delivery status reads.
+    /// In x2APIC mode there is no delivery status and true is returned
+    /// immediately.
+    pub fn wait_ipi_idle(&self, poll_limit: u32) -> bool {
+        if self.mode() == LocalApicMode::X2Apic {
+            return true;
+        }
+
+        for _ in 0..poll_limit {
+            let icr0 = Icr0::from_raw(self.read_icr() as u32);
+            if icr0.delivery_status() == DeliveryStatus::Idle {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Compose ICR value and send it. Waits for the IPI to be delivered.
+    unsafe fn send_icr(&mut self, dest: IpiDestination, mode: DeliveryMode,
+            vector: u8, level: IcrLevel, trigger: TriggerMode,
+            poll_limit: u32) -> bool {
+        let mut icr0 = Icr0::from_raw(0);
+        icr0.set_vector(vector);
+        icr0.set_delivery_mode(mode);
+        icr0.set_destination_mode(dest.mode());
+        icr0.set_level(level);
+        icr0.set_trigger_mode(trigger);
+        icr0.set_destination_shorthand(dest.shorthand());
+
+        let mut icr1 = Icr1::from_raw(0);
+        match self.mode() {
+            LocalApicMode::XApic    => icr1.set_destination(dest.field() as _),
+            LocalApicMode::X2Apic   => icr1.set_x2apic_destination(
+                    dest.field()),
+        }
+
+        // Previous IPI must be delivered before ICR is changed.
+        if !self.wait_ipi_idle(poll_limit) {
+            return false;
+        }
+
+        let lo = icr0.raw() as u64;
+        let hi = icr1.raw() as u64;
+        self.write_icr(lo | (hi << 32));
+
+        self.wait_ipi_idle(poll_limit)
+    }
+
+    /// Send edge triggered IPI with given delivery mode. Returns false if
+    /// the IPI was not delivered in time.
+    ///
+    /// # Safety
+    /// Destination processors must be ready to handle the interrupt.
+    pub unsafe fn send_ipi(&mut self, dest: IpiDestination,
+            mode: DeliveryMode, vector: u8) -> bool {
+        self.send_icr(dest, mode, vector, IcrLevel::Assert,
+                TriggerMode::EdgeSensitive, DEFAULT_IPI_POLL_LIMIT)
+    }
+
+    /// Send fixed interrupt with given vector to processor with given
+    /// APIC ID.
+    ///
+    /// # Safety
+    /// Destination processor must be ready to handle the interrupt.
+    pub unsafe fn send_fixed(&mut self, dest: u32, vector: u8) -> bool {
+        self.send_ipi(IpiDestination::Physical(dest), DeliveryMode::Fixed,
+                vector)
+    }
+
+    /// Send fixed interrupt with given vector to this processor. In x2APIC
+    /// mode the dedicated self IPI register is used.
+    ///
+    /// # Safety
+    /// Interrupt with given vector must be handled.
+    pub unsafe fn send_fixed_self(&mut self, vector: u8) -> bool {
+        if self.mode() == LocalApicMode::X2Apic {
+            self.write_reg(LocalApicReg::SelfIpi, vector as u32);
+            return true;
+        }
+
+        self.send_ipi(IpiDestination::SelfOnly, DeliveryMode::Fixed, vector)
+    }
+
+    /// Send fixed interrupt with given vector to all processors including
+    /// this one.
+    ///
+    /// # Safety
+    /// All processors must be ready to handle the interrupt.
+    pub unsafe fn broadcast_fixed(&mut self, vector: u8) -> bool {
+        self.send_ipi(IpiDestination::All, DeliveryMode::Fixed, vector)
+    }
+
+    /// Send fixed interrupt with given vector to all processors except
+    /// this one.
+    ///
+    /// # Safety
+    /// All other processors must be ready to handle the interrupt.
+    pub unsafe fn broadcast_fixed_others(&mut self, vector: u8) -> bool {
+        self.send_ipi(IpiDestination::AllButSelf, DeliveryMode::Fixed,
+                vector)
+    }
+
+    /// Send NMI to processor with given APIC ID.
+    ///
+    /// # Safety
+    /// Destination processor must be ready to handle NMI.
+    pub unsafe fn send_nmi(&mut self, dest: u32) -> bool {
+        self.send_ipi(IpiDestination::Physical(dest), DeliveryMode::Nmi, 0)
+    }
+
+    /// Send NMI to all processors except this one.
+    ///
+    /// # Safety
+    /// All other processors must be ready to handle NMI.
+    pub unsafe fn broadcast_nmi_others(&mut self) -> bool {
+        self.send_ipi(IpiDestination::AllButSelf, DeliveryMode::Nmi, 0)
+    }
+
+    /// Send INIT IPI to processor with given APIC ID. The processor
+    /// resets and waits for startup IPI.
+    ///
+    /// # Safety
+    /// Destination processor state is lost.
+    pub unsafe fn send_init(&mut self, dest: u32) -> bool {
+        self.send_icr(IpiDestination::Physical(dest), DeliveryMode::Init, 0,
+                IcrLevel::Assert, TriggerMode::LevelSensitive,
+                DEFAULT_IPI_POLL_LIMIT)
+    }
+
+    /// Send startup IPI to processor with given APIC ID. The processor
+    /// starts execution in real mode at address `page * 4096`.
+    ///
+    /// # Safety
+    /// Valid startup code must be placed at given page.
+    pub unsafe fn send_startup(&mut self, dest: u32, page: u8) -> bool {
+        self.send_ipi(IpiDestination::Physical(dest), DeliveryMode::StartUp,
+                page)
+    }
+
+    /// Start application processor with given APIC ID using
+    /// INIT-SIPI-SIPI sequence. Delays between the IPIs are performed
+    /// by given function that receives the delay in microseconds.
+    /// Returns false if any of the IPIs was not delivered.
+    ///
+    /// # Safety
+    /// Valid startup code must be placed at trampoline address and
+    /// destination processor must not be running.
+    pub unsafe fn start_ap<F>(&mut self, dest: u32, startup: &ApStartup,
+            mut delay_us: F) -> bool
+            where F: FnMut(u32) {
+        let target = IpiDestination::Physical(dest);
+        let limit = startup.poll_limit;
+
+        self.clear_error_status();
+
+        if !self.send_icr(target, DeliveryMode::Init, 0, IcrLevel::Assert,
+                TriggerMode::LevelSensitive, limit) {
+            return false;
+        }
+
+        if startup.init_deassert && !self.send_icr(target, DeliveryMode::Init,
+                0, IcrLevel::Deassert, TriggerMode::LevelSensitive, limit) {
+            return false;
+        }
+
+        delay_us(startup.init_delay_us);
+
+        for _ in 0..startup.sipi_count {
+            if !self.send_icr(target, DeliveryMode::StartUp, startup.page,
+                    IcrLevel::Assert, TriggerMode::EdgeSensitive, limit) {
+                return false;
+            }
+            delay_us(startup.sipi_delay_us);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    const APIC_BASE_MSR : u32 = 0x1B;
+
+    #[test]
+    fn x2apic_init_sipi_sipi() {
+        let mock = Mock::new();
+        mock.set_msr(APIC_BASE_MSR, 0xFEE0_0D00);
+
+        let startup = ApStartup::new(0x9A000).unwrap();
+        let mut delays = [0; 4];
+        let mut delay_count = 0;
+
+        let started = with_backend(&mock, || unsafe {
+            let mut apic = LocalApic::unsafe_new();
+            apic.start_ap(0x23, &startup, |us| {
+                delays[delay_count] = us;
+                delay_count += 1;
+            })
+        });
+
+        assert!(started);
+        assert_eq!(&delays[..delay_count], &[10_000, 200, 200]);
+
+        // INIT: level triggered assert. SIPI: edge triggered with
+        // trampoline page as the vector. Destination is in bits 63:32.
+        assert!(mock.log_eq(&[
+            MsrRead (0x1B,  0xFEE0_0D00),
+            MsrWrite(0x828, 0),
+            MsrWrite(0x828, 0),
+            MsrWrite(0x830, 0x0000_0023_0000_C500),
+            MsrWrite(0x830, 0x0000_0023_0000_469A),
+            MsrWrite(0x830, 0x0000_0023_0000_469A),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn xapic_ipi_poll_limit() {
+        let mock = Mock::new();
+        mock.set_msr(APIC_BASE_MSR, 0xFEE0_0900);
+        // Delivery status of the previous IPI stays pending.
+        mock.set_mmio(0xFEE0_0300, 1 << 12);
+
+        let startup = ApStartup::new(0x8000).unwrap().with_poll_limit(2);
+        let mut delayed = false;
+
+        let started = with_backend(&mock, || unsafe {
+            let mut apic = LocalApic::unsafe_new();
+            apic.start_ap(1, &startup, |_| delayed = true)
+        });
+
+        assert!(!started);
+        assert!(!delayed);
+        // ICR is polled given count of times and never written.
+        assert!(mock.log_eq(&[
+            MsrRead  (0x1B, 0xFEE0_0900),
+            MmioWrite(0xFEE0_0280, 0),
+            MmioWrite(0xFEE0_0280, 0),
+            MmioRead (0xFEE0_0300, 0x0000_1000),
+            MmioRead (0xFEE0_0310, 0),
+            MmioRead (0xFEE0_0300, 0x0000_1000),
+            MmioRead (0xFEE0_0310, 0),
+        ]), "{:?}", &*mock.log());
+    }
+}
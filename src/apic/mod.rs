@@ -0,0 +1,2077 @@
+use super::msr::ApicBase;
+use super::cpuid;
+use hw::Backend;
+
+/// Module with I/O APIC interface.
+mod ioapic;
+pub use self::ioapic::*;
+
+/// Module with high-level local APIC timer interface.
+mod timer;
+pub use self::timer::*;
+
+/// Module with ISR, TMR and IRR bitmap views.
+mod bitmap;
+pub use self::bitmap::*;
+
+/// Module with inter-processor interrupt helpers.
+mod ipi;
+pub use self::ipi::*;
+
+/// Module with logical destination addressing.
+mod addressing;
+pub use self::addressing::*;
+
+/// Local APIC handle.
+pub struct LocalApic {
+    apic_base_msr   : ApicBase,
+    mode            : LocalApicMode,
+}
+
+/// Local APIC register access mode.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LocalApicMode {
+
+    /// Registers are accessed through memory mapped page.
+    XApic,
+
+    /// Registers are accessed through MSRs starting at 0x800.
+    X2Apic,
+}
+
+/// Base MSR of x2APIC registers.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// List of all local APIC registers and their addresses.
+#[repr(usize)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum LocalApicReg {
+    Id                      = 0x020, // RW (Nehalem RO)
+    Version                 = 0x030, // RO
+
+    TaskPriority            = 0x080, // RW
+    ArbitrationPriority     = 0x090, // RO, 1
+    ProcessorPriority       = 0x0A0, // RO
+    Eoi                     = 0x0B0, // WO
+    RemoteRead              = 0x0C0, // RO, 1
+    LogicalDestination      = 0x0D0, // RW
+    DestinationFormat       = 0x0E0, // RW
+    SpuriousInterruptVector = 0x0F0, // RW
+    Isr0                    = 0x100, // RO
+    Isr1                    = 0x110, // RO
+    Isr2                    = 0x120, // RO
+    Isr3                    = 0x130, // RO
+    Isr4                    = 0x140, // RO
+    Isr5                    = 0x150, // RO
+    Isr6                    = 0x160, // RO
+    Isr7                    = 0x170, // RO
+    Tmr0                    = 0x180, // RO
+    Tmr1                    = 0x190, // RO
+    Tmr2                    = 0x1A0, // RO
+    Tmr3                    = 0x1B0, // RO
+    Tmr4                    = 0x1C0, // RO
+    Tmr5                    = 0x1D0, // RO
+    Tmr6                    = 0x1E0, // RO
+    Tmr7                    = 0x1F0, // RO
+    Irr0                    = 0x200, // RO
+    Irr1                    = 0x210, // RO
+    Irr2                    = 0x220, // RO
+    Irr3                    = 0x230, // RO
+    Irr4                    = 0x240, // RO
+    Irr5                    = 0x250, // RO
+    Irr6                    = 0x260, // RO
+    Irr7                    = 0x270, // RO
+    ErrorStatus             = 0x280, // RO
+
+    LvtCmci                 = 0x2F0, // RW
+    InterruptCommand0       = 0x300, // RW
+    InterruptCommand1       = 0x310, // RW
+    LvtTimer                = 0x320, // RW
+    LvtThermalSensor        = 0x330, // RW, 2
+    LvtPerformanceCounters  = 0x340, // RW, 3
+    LvtLint0                = 0x350, // RW
+    LvtLint1                = 0x360, // RW
+    LvtError                = 0x370, // RW
+    InitialCount            = 0x380, // RW
+    CurrentCount            = 0x390, // RO
+
+    DivideConfiguration     = 0x3E0, // RW
+    SelfIpi                 = 0x3F0, // WO, 4
+
+    // 1  - Not supported on Pentium 4 and Xeon.
+    //
+    // 2  - Introduced in Pentium 4 and Xeon. This APIC registers and its
+    //      associated functions are implementation-dependent and may not be
+    //      present in future IA-32 or Intel 64 processors.
+    //
+    // 3  - Introduced in Pentium Pro. This APIC register and its
+    //      associated function are implementation-dependent and may not be
+    //      present in future IA-32 or Intel 64 processors.
+    //
+    // 4  - Only available in x2APIC mode.
+}
+
+/// Version register local APIC type and version number.
+pub enum VersionNumber {
+    Discrete    (u8),
+    Integrated  (u8),
+}
+
+/// MDA model used in Dfr.
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum MdaModel {
+    Flat        = 0b1111,
+    Cluster     = 0b0000,
+}
+
+/// Delivery status of LVT interrupts.
+#[derive(PartialEq, Clone, Copy)]
+pub enum DeliveryStatus {
+    Idle,
+    SendPending,
+}
+
+/// ICR level field.
+#[derive(PartialEq, Clone, Copy)]
+pub enum IcrLevel {
+    Assert,
+    Deassert,
+}
+
+/// IPI destination mode.
+#[derive(PartialEq, Clone, Copy)]
+pub enum DestinationMode {
+    Physical,
+    Logical,
+}
+
+/// Destination shorthand of ICR.
+#[repr(u32)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum DestinationShorthand {
+
+    /// Destination is set in destination field.
+    NoShorthand         = 0b00,
+    SelfDestination     = 0b01,
+    AllIncludingSelf    = 0b10,
+    AllExcludingSelf    = 0b11,
+}
+
+/// Delivery mode of LVT interrupt.
+#[repr(u32)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DeliveryMode {
+    Fixed   = 0b000,
+
+    /// Only valid for IPIs and I/O APIC redirection entries.
+    LowestPriority = 0b001,
+
+    Smi     = 0b010,
+    Nmi     = 0b100,
+    ExtInt  = 0b111,
+    Init    = 0b101,
+
+    /// Only valid for IPIs.
+    StartUp = 0b110,
+}
+
+/// Interrupt input pin polarity.
+#[derive(PartialEq, Clone, Copy)]
+pub enum PinPolarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// LVT interrupt trigger mode.
+#[derive(PartialEq, Clone, Copy)]
+pub enum TriggerMode {
+    EdgeSensitive,
+    LevelSensitive,
+}
+
+/// Arbitration priority class or sub-class value.
+pub struct PriorityClass {
+    val : u8,
+}
+
+// Id register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Id {
+    reg     : u32,
+}
+
+/// Version register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Version {
+    reg     : u32,
+}
+
+/// Task priority register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Tpr {
+    class   : u8,
+
+    _resv0  : u8,
+    _resv1  : u16,
+}
+
+/// Arbitration priority register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Apr {
+    class   : u8,
+
+    _resv0  : u8,
+    _resv1  : u16,
+}
+
+/// Processor priority register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Ppr {
+    class   : u8,
+
+    _resv0  : u8,
+    _resv1  : u16,
+}
+
+/// Value of DivideConfiguration register of APIC.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct DivideConfiguration {
+    reg     : u32,
+}
+
+/// End Of Interrupt register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Eoi {
+    reg     : u32,
+}
+
+/// Logical destination register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Ldr {
+    reg     : u32,
+}
+
+/// Destination format register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Dfr {
+    _resv0  : u16,
+    _resv1  : u8,
+
+    model   : u8,
+}
+
+/// Spurious Interrupt register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct SpuriousInterrupt {
+    reg     : u32,
+}
+
+/// LVT CMCI register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtCmci {
+    reg     : u32,
+}
+
+/// Interrupt command register 0.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Icr0 {
+    reg     : u32
+}
+
+/// Interrupt command register 1.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct Icr1 {
+    reg     : u32
+}
+
+/// Interrupt command register interface. Takes care of writing values
+/// into the APIC registers in right order and time.
+pub struct Icr<'a> {
+    apic: &'a mut LocalApic,
+
+    icr0_pending: Icr0,
+    icr1_pending: Icr1,
+}
+
+/// Value that is stored in a local APIC register. Allows the same value
+/// types to be used both in xAPIC and x2APIC modes.
+pub trait LapicRegister: Sized {
+
+    /// Create value from raw register data.
+    fn from_raw(val: u32) -> Self;
+
+    /// Raw register data.
+    fn raw(&self) -> u32;
+}
+
+/// Mutable handle of local APIC register value. Value is read when
+/// handle is created and written back to the register when the handle
+/// is dropped, if it was changed.
+pub struct LocalApicRegMut<'a, T: LapicRegister> {
+    apic    : &'a mut LocalApic,
+    reg     : LocalApicReg,
+    val     : T,
+    changed : bool,
+}
+
+/// Value of Local Vector Table Timer register of APIC.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtTimer {
+    reg     : u32,
+}
+
+/// LVT Thermal Sensor register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtThermalSensor {
+    reg     : u32,
+}
+
+/// LVT performance counters register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtPerformanceCounters {
+    reg     : u32,
+}
+
+/// LVT local interrupt pin 0 register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtLint0 {
+    reg     : u32,
+}
+
+/// LVT local interrupt pin 1 register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtLint1 {
+    reg     : u32,
+}
+
+/// LVT error interrupt register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct LvtError {
+    reg     : u32,
+}
+
+/// Error status register.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct ErrorStatus {
+    reg     : u32,
+}
+
+/// Error that can be reported by local APIC in error status register.
+/// The value is the bit of the error in the register.
+#[repr(u32)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ApicError {
+    SendChecksum            = 1 << 0,
+    ReceiveChecksum         = 1 << 1,
+    SendAccept              = 1 << 2,
+    ReceiveAccept           = 1 << 3,
+    RedirectableIpi         = 1 << 4,
+    SendIllegalVector       = 1 << 5,
+    ReceivedIllegalVector   = 1 << 6,
+    IllegalRegisterAddress  = 1 << 7,
+}
+
+/// Iterator over errors set in error status register.
+pub struct ErrorStatusIter {
+    status  : ErrorStatus,
+    index   : usize,
+}
+
+/// LVT Timer mode.
+#[repr(u32)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LvtTimerMode {
+    OneShot     = 0b00 << 17,
+    Periodic    = 0b01 << 17,
+    TscDeadline = 0b10 << 17,
+}
+
+/// Mask that can be used to clear out all bits except timer mode
+/// in LVT Timer register.
+const LVT_TIMER_MODE_MASK: u32 = 0b11 << 17;
+
+/// Value of current timer count register of APIC.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct TimerCurrentCount {
+    reg     : u32,
+}
+
+/// Value of initial timer counter register of APIC.
+#[repr(packed)]
+#[derive(Clone, Copy)]
+pub struct TimerInitialCount {
+    reg     : u32,
+}
+
+/// Divide value that is set in DivideConfiguration register.
+#[repr(u32)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum DivideValue {
+    Div1    = 0b1011,
+    Div2    = 0b0000,
+    Div4    = 0b0001,
+    Div8    = 0b0010,
+    Div16   = 0b0011,
+    Div32   = 0b1000,
+    Div64   = 0b1001,
+    Div128  = 0b1010,
+}
+
+impl LocalApicReg {
+
+    /// Pointer to given local APIC register.
+    pub fn ptr32(&self, apic: &LocalApic) -> *const u32 {
+        let addr = *self as usize + apic.base_addr();
+        addr as _
+    }
+
+    /// Pointer to mutable local APIC register.
+    pub fn ptr32_mut(&self, apic: &mut LocalApic) -> *mut u32 {
+        self.ptr32(apic) as _
+    }
+
+    pub fn ptr64(&self, apic: &LocalApic) -> *const u64 {
+        self.ptr32(apic) as _
+    }
+
+    pub fn ptr64_mut(&self, apic: &mut LocalApic) -> *mut u64 {
+        self.ptr32(apic) as _
+    }
+
+    pub fn ptr128(&self, apic: &LocalApic) -> *const (u64, u64) {
+        self.ptr32(apic) as _
+    }
+
+    pub fn ptr128_mut(&self, apic: &mut LocalApic) -> *mut (u64, u64) {
+        self.ptr32(apic) as _
+    }
+
+    /// MSR that maps this register in x2APIC mode.
+    pub fn msr(&self) -> u32 {
+        X2APIC_MSR_BASE + (*self as u32 >> 4)
+    }
+}
+
+impl VersionNumber {
+
+    /// Create from given version number.
+    pub fn from_number(num: u8) -> Self {
+        if num <= 0xF {
+            VersionNumber::Discrete(num)
+        } else {
+            VersionNumber::Integrated(num)
+        }
+    }
+}
+
+impl From<u8> for MdaModel {
+
+    fn from(val: u8) -> MdaModel {
+        if val & 0b1111 == MdaModel::Flat as u8 {
+            MdaModel::Flat
+        } else {
+            MdaModel::Cluster
+        }
+    }
+}
+
+impl From<u32> for DestinationShorthand {
+
+    /// Shorthand from the two lowest bits of the value.
+    fn from(v: u32) -> DestinationShorthand {
+        use self::DestinationShorthand::*;
+
+        match v & 0b11 {
+            0b00    => NoShorthand,
+            0b01    => SelfDestination,
+            0b10    => AllIncludingSelf,
+            _       => AllExcludingSelf,
+        }
+    }
+}
+
+impl DeliveryMode {
+
+    /// Delivery mode from the value of 3-bit delivery mode field. None
+    /// is returned for reserved values.
+    pub fn from_raw(v: u32) -> Option<Self> {
+        use self::DeliveryMode::*;
+
+        match v {
+            0b000   => Some(Fixed),
+            0b001   => Some(LowestPriority),
+            0b010   => Some(Smi),
+            0b100   => Some(Nmi),
+            0b101   => Some(Init),
+            0b110   => Some(StartUp),
+            0b111   => Some(ExtInt),
+            _       => None,
+        }
+    }
+}
+
+macro_rules! lvt_entry_impl_base {
+    () => {
+
+        /// LVT vector.
+        pub fn vector(&self) -> u8 {
+            (self.reg & 0xFF) as u8
+        }
+
+        /// Set LVT vector.
+        pub fn set_vector(&mut self, vec: u8) {
+            self.reg = self.reg & !0xFF | (vec as u32);
+        }
+
+        /// Delivery status.
+        pub fn delivery_status(&self) -> DeliveryStatus {
+            use self::DeliveryStatus::*;
+
+            if self.reg & (1 << 12) != 0 {
+                SendPending
+            } else {
+                Idle
+            }
+        }
+
+        /// Whether interrupt is masked or not.
+        pub fn masked(&self) -> bool {
+            self.reg & (1 << 16) != 0
+        }
+
+        /// Mask this interrupt.
+        pub fn mask(&mut self) {
+            self.reg |= 1 << 16;
+        }
+
+        /// Unmask this interrupt.
+        pub fn unmask(&mut self) {
+            self.reg &= !(1 << 16);
+        }
+    };
+}
+
+macro_rules! lvt_entry_impl_delivery {
+    ($($mode:ident),*) => {
+        /// Delivery mode. None if the field holds reserved value.
+        pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+            DeliveryMode::from_raw((self.reg >> 8) & 0b111)
+        }
+
+        /// Set delivery mode without checking other fields for valid values.
+        ///
+        /// # Safety
+        /// Mode must be valid for this LVT entry.
+        pub unsafe fn only_set_delivery_mode(&mut self, mode: DeliveryMode) {
+            let val = mode as u32;
+            let mask = val << 8;
+            self.reg = self.reg & !(0b111 << 8) | mask;
+        }
+
+        /// Whether given delivery mode can be used in this LVT entry.
+        pub fn supports_delivery_mode(mode: DeliveryMode) -> bool {
+            $(mode == DeliveryMode::$mode)||*
+        }
+
+        /// Set delivery mode. Returns false and leaves the entry unchanged
+        /// if the mode can't be used in this LVT entry.
+        pub fn set_delivery_mode(&mut self, mode: DeliveryMode) -> bool {
+            if !Self::supports_delivery_mode(mode) {
+                return false;
+            }
+            unsafe { self.only_set_delivery_mode(mode); }
+            true
+        }
+
+        /// Use given delivery mode. None is returned if the mode can't be
+        /// used in this LVT entry.
+        pub fn with_delivery_mode(mut self, mode: DeliveryMode)
+                -> Option<Self> {
+            if self.set_delivery_mode(mode) {
+                Some(self)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+macro_rules! lvt_entry_impl_default {
+    ($($ty:ident),*) => {$(
+        impl Default for $ty {
+
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    )*};
+}
+
+macro_rules! lvt_entry_impl_builder {
+    () => {
+
+        /// New masked entry with fixed delivery mode and zero vector.
+        pub fn new() -> Self {
+            Self::from_raw(1 << 16)
+        }
+
+        /// Use given vector.
+        pub fn with_vector(mut self, vec: u8) -> Self {
+            self.set_vector(vec);
+            self
+        }
+
+        /// Mask or unmask the entry.
+        pub fn with_masked(mut self, masked: bool) -> Self {
+            if masked {
+                self.mask();
+            } else {
+                self.unmask();
+            }
+            self
+        }
+    }
+}
+
+macro_rules! lvt_entry_impl_lint {
+    () => {
+
+        /// Trigger mode.
+        pub fn trigger_mode(&self) -> TriggerMode {
+            use self::TriggerMode::*;
+
+            if self.reg & (1 << 15) != 0 {
+                LevelSensitive
+            } else {
+                EdgeSensitive
+            }
+        }
+
+        /// Whether remote IRR flag is on.
+        pub fn remote_irr(&self) -> bool {
+            self.reg & (1 << 14) != 0
+        }
+
+        /// Interrupt input pin polarity.
+        pub fn input_polarity(&self) -> PinPolarity {
+            use self::PinPolarity::*;
+
+            if self.reg & (1 << 13) != 0 {
+                ActiveLow
+            } else {
+                ActiveHigh
+            }
+        }
+
+        /// Set interrupt input pin polarity.
+        pub fn set_input_polarity(&mut self, pp: PinPolarity) {
+            use self::PinPolarity::*;
+
+            match pp {
+                ActiveHigh  => self.reg &= !(1 << 13),
+                ActiveLow   => self.reg |=   1 << 13
+            }
+        }
+
+        /// Use given interrupt input pin polarity.
+        pub fn with_input_polarity(mut self, pp: PinPolarity) -> Self {
+            self.set_input_polarity(pp);
+            self
+        }
+    }
+}
+
+macro_rules! lapic_register_impl {
+    ($($ty:ident),*) => {$(
+        impl LapicRegister for $ty {
+
+            fn from_raw(val: u32) -> Self {
+                unsafe { ::core::mem::transmute(val) }
+            }
+
+            fn raw(&self) -> u32 {
+                unsafe { ::core::mem::transmute_copy(self) }
+            }
+        }
+    )*};
+}
+
+lvt_entry_impl_default!(LvtCmci, LvtThermalSensor, LvtPerformanceCounters,
+        LvtLint0, LvtLint1);
+
+lapic_register_impl!(Id, Version, Tpr, Apr, Ppr, DivideConfiguration, Eoi,
+        Ldr, Dfr, SpuriousInterrupt, LvtCmci, Icr0, Icr1, LvtTimer,
+        LvtThermalSensor, LvtPerformanceCounters, LvtLint0, LvtLint1,
+        LvtError, TimerCurrentCount, TimerInitialCount, ErrorStatus);
+
+macro_rules! lapic_reg_ref_impl {
+    ($n:ident, $nm:ident, $ty:tt, $doc:expr) => {
+        lapic_reg_ref_impl!($ty, $n, $nm, $ty, $doc);
+    };
+
+    ($enu:ident, $n:ident, $nm:ident, $ty:tt, $doc:expr) => {
+        lapic_reg_ref_impl_ro!($enu, $n , $ty, $doc);
+        lapic_reg_ref_impl_wo!($enu, $nm, $ty, $doc);
+    };
+}
+
+macro_rules! lapic_reg_ref_impl_ro {
+    ($n:ident, $ty:tt, $doc:expr) => {
+        lapic_reg_ref_impl_ro!($ty, $n, $ty, $doc);
+    };
+
+    ($enu:ident, $n:ident, $ty:tt, $doc:expr) => {
+        #[doc=$doc]
+        pub fn $n(&self) -> $ty {
+            let val = self.read_reg(LocalApicReg::$enu);
+            <$ty as LapicRegister>::from_raw(val)
+        }
+    };
+}
+
+macro_rules! lapic_reg_ref_impl_wo {
+    ($n:ident, $ty:tt, $doc:expr) => {
+        lapic_reg_ref_impl_wo!($ty, $n, $ty, $doc);
+    };
+
+    ($enu:ident, $n:ident, $ty:tt, $doc:expr) => {
+        #[doc=$doc]
+        pub fn $n(&mut self) -> LocalApicRegMut<'_, $ty> {
+            LocalApicRegMut::new(self, LocalApicReg::$enu)
+        }
+    };
+}
+
+impl LocalApic {
+
+    /// Get Local APIC reference if APIC is actually available.
+    /// To determine the presence of APIC, CPUID instruction is used.
+    pub fn new() -> Option<LocalApic> {
+        if Self::local_apic_is_present() {
+            unsafe {
+                Some(Self::unsafe_new())
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Get Local APIC reference.
+    ///
+    /// # Safety
+    /// Does not check whether Local APIC exists
+    /// so caller must be sure this operation is valid.
+    pub unsafe fn unsafe_new() -> LocalApic {
+        let apic_base_msr = ApicBase::read();
+        let mode = if apic_base_msr.x2apic_enabled() {
+            LocalApicMode::X2Apic
+        } else {
+            LocalApicMode::XApic
+        };
+
+        LocalApic {
+            apic_base_msr,
+            mode,
+        }
+    }
+
+    /// Check if local APIC is present in given system. Function
+    /// uses CPUID to check feature availability. Because of that
+    /// calls to this function are slow.
+    pub fn local_apic_is_present() -> bool {
+        cpuid::Features::get().local_apic_is_present()
+    }
+
+    /// Check if local APIC supports x2APIC mode. Uses CPUID.
+    pub fn x2apic_is_supported() -> bool {
+        cpuid::Features::get().x2apic_supported()
+    }
+
+    /// Current register access mode.
+    pub fn mode(&self) -> LocalApicMode {
+        self.mode
+    }
+
+    /// Switch local APIC to x2APIC mode. After this all registers are
+    /// accessed through MSRs. Returns false if x2APIC is not supported
+    /// and mode was not changed.
+    ///
+    /// # Safety
+    /// Any other LocalApic handle of this processor created before
+    /// the switch will access registers in a wrong way.
+    pub unsafe fn enable_x2apic(&mut self) -> bool {
+        if self.mode == LocalApicMode::X2Apic {
+            return true;
+        }
+        if !Self::x2apic_is_supported() {
+            return false;
+        }
+
+        self.apic_base_msr.x2apic_enable();
+        self.apic_base_msr.write();
+        self.mode = LocalApicMode::X2Apic;
+        true
+    }
+
+    /// Read raw value of given register.
+    ///
+    /// Note that in x2APIC mode reading write-only registers and
+    /// registers not available in this mode causes General Protection
+    /// fault.
+    pub fn read_reg(&self, reg: LocalApicReg) -> u32 {
+        use self::LocalApicMode::*;
+
+        unsafe { match self.mode {
+            XApic   => ::hw::backend().mmio_read32(reg.ptr32(self) as _),
+            X2Apic  => ::msr::Info::read_by_id(reg.msr()).eax,
+        }}
+    }
+
+    /// Write raw value to given register.
+    ///
+    /// # Safety
+    /// Value is not checked. Writes to read-only registers in x2APIC mode
+    /// cause General Protection fault.
+    pub unsafe fn write_reg(&mut self, reg: LocalApicReg, val: u32) {
+        use self::LocalApicMode::*;
+
+        match self.mode {
+            XApic   => {
+                let addr = reg.ptr32_mut(self) as usize;
+                ::hw::backend().mmio_write32(addr, val);
+            },
+            X2Apic  => {
+                let info = ::msr::Info { eax: val, edx: 0 };
+                info.write_by_id(reg.msr());
+            }
+        }
+    }
+
+    /// Raw 64-bit value of interrupt command register. In xAPIC mode
+    /// it is combined from ICR0 (low) and ICR1 (high) registers.
+    pub fn read_icr(&self) -> u64 {
+        use self::LocalApicMode::*;
+
+        match self.mode {
+            XApic   => {
+                let lo = self.read_reg(LocalApicReg::InterruptCommand0);
+                let hi = self.read_reg(LocalApicReg::InterruptCommand1);
+                lo as u64 | ((hi as u64) << 32)
+            },
+            X2Apic  => {
+                let msr = LocalApicReg::InterruptCommand0.msr();
+                let info = unsafe { ::msr::Info::read_by_id(msr) };
+                info.eax as u64 | ((info.edx as u64) << 32)
+            }
+        }
+    }
+
+    /// Write raw 64-bit value of interrupt command register. In xAPIC mode
+    /// the high part is stored first as writing the low part sends the IPI.
+    /// In x2APIC mode the value is written at once with single MSR write.
+    ///
+    /// # Safety
+    /// This sends an IPI.
+    pub unsafe fn write_icr(&mut self, val: u64) {
+        use self::LocalApicMode::*;
+
+        let lo = val as u32;
+        let hi = (val >> 32) as u32;
+
+        match self.mode {
+            XApic   => {
+                self.write_reg(LocalApicReg::InterruptCommand1, hi);
+                self.write_reg(LocalApicReg::InterruptCommand0, lo);
+            },
+            X2Apic  => {
+                let info = ::msr::Info { eax: lo, edx: hi };
+                info.write_by_id(LocalApicReg::InterruptCommand0.msr());
+            }
+        }
+    }
+
+    /// Local APIC ID. In xAPIC mode this is the 8-bit ID from ID
+    /// register and in x2APIC mode it is the full 32-bit x2APIC ID.
+    pub fn apic_id(&self) -> u32 {
+        use self::LocalApicMode::*;
+
+        match self.mode {
+            XApic   => self.id().id() as _,
+            X2Apic  => self.id().x2apic_id(),
+        }
+    }
+
+    /// Base address of local APIC registers mapped to RAM.
+    pub fn base_addr(&self) -> usize {
+        self.apic_base_msr.apic_base() as _
+    }
+
+    /// Re-map local APIC registers to given new address.
+    ///
+    /// # Safety
+    /// No other code may access local APIC at the old address, and the
+    /// new address must not overlap other memory.
+    pub unsafe fn set_base_addr(&mut self, base: usize) {
+        self.apic_base_msr.set_apic_base(base as _);
+        self.apic_base_msr.write();
+    }
+
+    lapic_reg_ref_impl_ro!(version, Version, "Version register.");
+
+    /// EOI register. Register is write-only so the value is not read.
+    pub fn eoi_mut(&mut self) -> LocalApicRegMut<'_, Eoi> {
+        LocalApicRegMut::new_zeroed(self, LocalApicReg::Eoi)
+    }
+
+    /// Signal end of interrupt to local APIC.
+    pub fn eoi(&mut self) {
+        self.eoi_mut().signal();
+    }
+
+    /// Whether local APIC supports EOI broadcast suppression.
+    pub fn eoi_broadcast_suppression_supported(&self) -> bool {
+        self.version().eoi_broadcast_suppression()
+    }
+
+    /// Whether EOI broadcast suppression is enabled.
+    pub fn eoi_broadcast_suppressed(&self) -> bool {
+        self.spurious_interrupt().eoi_broadcast_suppression()
+    }
+
+    /// Stop broadcasting EOIs of level triggered interrupts to
+    /// I/O APICs. EOIs then must be sent to I/O APICs by
+    /// `end_of_interrupt`. Returns false if suppression is not supported.
+    pub fn enable_eoi_broadcast_suppression(&mut self) -> bool {
+        if !self.eoi_broadcast_suppression_supported() {
+            return false;
+        }
+
+        unsafe {
+            self.spurious_interrupt_mut().enable_eoi_broadcast_suppression();
+        }
+        true
+    }
+
+    /// Broadcast EOIs of level triggered interrupts to I/O APICs again.
+    pub fn disable_eoi_broadcast_suppression(&mut self) {
+        self.spurious_interrupt_mut().disable_eoi_broadcast_suppression();
+    }
+
+    /// Signal end of interrupt with given vector. If the interrupt was
+    /// level triggered and EOI broadcast suppression is enabled, directed
+    /// EOI is sent to given I/O APICs so they can deliver the interrupt
+    /// again.
+    pub fn end_of_interrupt(&mut self, vector: u8, ioapics: &mut [IoApic]) {
+        // TMR is read before EOI as it describes the interrupt being
+        // serviced.
+        let level = self.tmr().is_set(vector);
+
+        self.eoi();
+
+        if level && self.eoi_broadcast_suppressed() {
+            for ioapic in ioapics.iter_mut() {
+                ioapic.eoi(vector);
+            }
+        }
+    }
+
+    lapic_reg_ref_impl!(SpuriousInterruptVector,
+            spurious_interrupt, spurious_interrupt_mut,
+            SpuriousInterrupt, "Spurious interrupt vector register.");
+
+    lapic_reg_ref_impl!(lvt_cmci, lvt_cmci_mut,
+            LvtCmci, "LVT CMCI register.");
+
+    lapic_reg_ref_impl!(lvt_timer, lvt_timer_mut,
+            LvtTimer, "LVT timer register.");
+
+    lapic_reg_ref_impl!(lvt_thermal_sensor, lvt_thermal_sensor_mut,
+            LvtThermalSensor, "LVT thermal sensor register.");
+
+    lapic_reg_ref_impl!(lvt_error, lvt_error_mut,
+            LvtError, "LVT error register.");
+
+    lapic_reg_ref_impl!(lvt_lint0, lvt_lint0_mut,
+            LvtLint0, "LVT LINT0 register.");
+
+    lapic_reg_ref_impl!(lvt_lint1, lvt_lint1_mut,
+            LvtLint1, "LVT LINT1 register.");
+
+    lapic_reg_ref_impl!(lvt_performance_counters,
+            lvt_performance_counters_mut,
+            LvtPerformanceCounters, "LVT performance counters register.");
+
+    /// Configure LINT pins for the common setup: LINT1 delivers NMI and
+    /// LINT0 passes 8259A PIC interrupts in virtual wire mode. When
+    /// interrupts are routed through I/O APIC instead, LINT0 is masked.
+    pub fn configure_lints(&mut self, virtual_wire: bool) {
+        let lint0 = if virtual_wire {
+            LvtLint0::virtual_wire()
+        } else {
+            LvtLint0::new()
+        };
+
+        *self.lvt_lint0_mut() = lint0;
+        *self.lvt_lint1_mut() = LvtLint1::nmi();
+    }
+
+    /// Error status register. Register is written before read as required
+    /// by the error status protocol so the value holds all errors detected
+    /// since the previous call. The errors are cleared afterwards.
+    pub fn error_status(&mut self) -> ErrorStatus {
+        unsafe { self.write_reg(LocalApicReg::ErrorStatus, 0); }
+        let val = self.read_reg(LocalApicReg::ErrorStatus);
+        ErrorStatus::from_raw(val)
+    }
+
+    /// Clear errors recorded in error status register.
+    pub fn clear_error_status(&mut self) {
+        // Write updates register with new errors and the second write
+        // clears them.
+        unsafe {
+            self.write_reg(LocalApicReg::ErrorStatus, 0);
+            self.write_reg(LocalApicReg::ErrorStatus, 0);
+        }
+    }
+
+    /// Deliver APIC error interrupts with given vector. Errors collected
+    /// before are cleared so the first interrupt reports only new ones.
+    pub fn enable_error_interrupt(&mut self, vector: u8) {
+        self.clear_error_status();
+
+        let mut lvt = self.lvt_error_mut();
+        lvt.set_vector(vector);
+        lvt.unmask();
+    }
+
+    /// Mask APIC error interrupts.
+    pub fn disable_error_interrupt(&mut self) {
+        self.lvt_error_mut().mask();
+    }
+
+    lapic_reg_ref_impl!(InitialCount,
+            initial_count, initial_count_mut,
+            TimerInitialCount, "Timer initial count register.");
+
+    lapic_reg_ref_impl!(CurrentCount,
+            current_count, current_count_mut,
+            TimerCurrentCount, "Timer current count register.");
+
+    lapic_reg_ref_impl!(divide_configuration, divide_configuration_mut,
+            DivideConfiguration, "Divide configuration value.");
+
+    /// ICR registers interface.
+    pub fn icr_interface(&mut self) -> Icr<'_> {
+        let icr = self.read_icr();
+
+        Icr {
+            apic : self,
+
+            icr0_pending : Icr0::from_raw(icr as u32),
+            icr1_pending : Icr1::from_raw((icr >> 32) as u32),
+        }
+    }
+
+    lapic_reg_ref_impl!(LogicalDestination, ldr, ldr_mut,
+            Ldr, "Logical destination register.");
+
+    lapic_reg_ref_impl!(DestinationFormat, dfr, dfr_mut,
+            Dfr, "Destination format register.");
+
+    lapic_reg_ref_impl_ro!(ArbitrationPriority, apr,
+            Apr, "Arbitration priority register.");
+
+    lapic_reg_ref_impl!(TaskPriority, tpr, tpr_mut,
+            Tpr, "Task priority register.");
+
+    lapic_reg_ref_impl_ro!(ProcessorPriority, ppr,
+            Ppr, "Processor priority register.");
+
+    lapic_reg_ref_impl!(Id, id, id_mut,
+            Id, "Local APIC ID.");
+
+    /// Read eight consecutive 32-bit registers starting from given one
+    /// into interrupt bitmap.
+    fn read_bitmap(&self, first: LocalApicReg) -> InterruptBitmap {
+        use self::LocalApicMode::*;
+
+        let mut words = [0u32; 8];
+        for (i, w) in words.iter_mut().enumerate() {
+            *w = unsafe { match self.mode {
+                XApic   => {
+                    let addr = first.ptr32(self) as usize + i * 0x10;
+                    ::hw::backend().mmio_read32(addr)
+                },
+                X2Apic  => {
+                    let msr = first.msr() + i as u32;
+                    ::msr::Info::read_by_id(msr).eax
+                }
+            }};
+        }
+        InterruptBitmap::from_words(words)
+    }
+
+    /// In-service register. Holds vectors of interrupts that were
+    /// delivered to the processor and are not yet acknowledged by EOI.
+    pub fn isr(&self) -> InterruptBitmap {
+        self.read_bitmap(LocalApicReg::Isr0)
+    }
+
+    /// Trigger mode register. Vector bit is set for level triggered
+    /// interrupts when they are accepted into IRR.
+    pub fn tmr(&self) -> InterruptBitmap {
+        self.read_bitmap(LocalApicReg::Tmr0)
+    }
+
+    /// Interrupt request register. Holds vectors of interrupts accepted
+    /// by local APIC but not yet delivered to the processor.
+    pub fn irr(&self) -> InterruptBitmap {
+        self.read_bitmap(LocalApicReg::Irr0)
+    }
+
+    /// Vector of highest priority interrupt currently being serviced.
+    pub fn highest_in_service(&self) -> Option<u8> {
+        self.isr().highest()
+    }
+
+    /// Vector of highest priority interrupt pending delivery.
+    pub fn highest_pending(&self) -> Option<u8> {
+        self.irr().highest()
+    }
+
+    /// Whether interrupt with given vector is being serviced and was
+    /// level triggered. EOI for such interrupt is broadcast to I/O APICs.
+    pub fn is_level_in_service(&self, vector: u8) -> bool {
+        self.isr().is_set(vector) && self.tmr().is_set(vector)
+    }
+}
+
+impl PriorityClass {
+
+    /// Try converting given class value to instance of this class.
+    /// This can be possible only for number between 0 and 15 inclusive as
+    /// only these values represent class or sub-class field (which is 4 bits
+    /// in size - 16 different values can be stored).
+    pub fn try_new(val: u8) -> Option<Self> {
+        if val > 0x0F {
+            None
+        } else {
+            Some(PriorityClass { val })
+        }
+    }
+
+    /// Value of priority class.
+    pub fn value(&self) -> u8 {
+        self.val
+    }
+}
+
+impl From<PriorityClass> for u8 {
+
+    fn from(val: PriorityClass) -> Self {
+        val.value()
+    }
+}
+
+impl Id {
+
+    /// Get initial local APIC ID. This value can be changed by OS on some
+    /// processors.
+    pub fn id(&self) -> u8 {
+        (self.reg >> 24) as _
+    }
+
+    /// x2APIC ID. Only valid when the register was read in x2APIC mode
+    /// where the whole register holds the ID.
+    pub fn x2apic_id(&self) -> u32 {
+        self.reg
+    }
+
+    /// Overwrite local APIC ID. This does not change CPUID value
+    /// in EBX (bits 31-24) for EAX=1 which always is initial
+    /// local APIC ID.
+    ///
+    /// # Safety
+    /// ID must be unique among APIC agents and match the system
+    /// configuration.
+    pub unsafe fn set_id(&mut self, id: u8) {
+        self.reg &= 0x00FF_FFFF;
+        self.reg |= (id as u32) << 24;
+    }
+}
+
+impl Version {
+
+    /// Local APIC version number.
+    pub fn version(&self) -> VersionNumber {
+        VersionNumber::from_number((self.reg & 0xFF) as _)
+    }
+
+    /// Max LVT entry count minus one.
+    pub fn max_lvt_entry(&self) -> u8 {
+        ((self.reg >> 16) & 0xFF) as u8
+    }
+
+    /// Check support of EOI broadcast suppression.
+    pub fn eoi_broadcast_suppression(&self) -> bool {
+        self.reg & (1 << 24) != 0
+    }
+}
+
+macro_rules! impl_pr_ro {
+    () => {
+        /// Class field.
+        pub fn class_field(&self) -> u8 {
+            self.class
+        }
+
+        /// Arbitration priority sub-class.
+        pub fn subclass(&self) -> PriorityClass {
+            PriorityClass::try_new(self.class & 0x0F).unwrap()
+        }
+
+        /// Arbitration priority class.
+        pub fn class(&self) -> PriorityClass {
+            let val = self.class >> 4;
+            PriorityClass::try_new(val).unwrap()
+        }
+    };
+}
+
+macro_rules! impl_pr_wo {
+    () => {
+        /// Set class field.
+        pub fn set_class_field(&mut self, class: u8) {
+            self.class = class;
+        }
+
+        pub fn set_subclass(&mut self, class: PriorityClass) {
+            let mask: u8 = class.into();
+            self.class = self.class & 0xF0 | mask;
+        }
+
+        pub fn set_class(&mut self, class: PriorityClass) {
+            let mask: u8 = class.into();
+            let mask = mask << 4;
+            self.class = self.class & 0x0F | mask;
+        }
+    };
+}
+
+impl Tpr {
+    impl_pr_ro!();
+    impl_pr_wo!();
+}
+
+impl Apr {
+    impl_pr_ro!();
+}
+
+impl Ppr {
+    impl_pr_ro!();
+}
+
+impl Eoi {
+
+    /// Send End-Of-Interrupt signal.
+    pub fn signal(&mut self) {
+        self.reg = 0;
+    }
+}
+
+impl Ldr {
+
+    pub fn logical_apic_id(&self) -> u8 {
+        (self.reg >> 24) as _
+    }
+
+    pub fn set_logical_apic_id(&mut self, val: u8) {
+        self.reg = self.reg & 0x00FF_FFFF | ((val as u32) << 24);
+    }
+
+    /// Logical x2APIC ID. Only valid when the register was read in x2APIC
+    /// mode where the whole register holds the ID derived from x2APIC ID.
+    pub fn x2apic_logical_id(&self) -> u32 {
+        self.reg
+    }
+}
+
+impl Dfr {
+
+    /// MDA model that is stored in bits 31:28.
+    pub fn model(&self) -> MdaModel {
+        MdaModel::from(self.model >> 4)
+    }
+
+    /// Set MDA model. Reserved bits are set to ones as required.
+    pub fn set_model(&mut self, model: MdaModel) {
+        self._resv0 = 0xFFFF;
+        self._resv1 = 0xFF;
+        self.model = ((model as u8) << 4) | 0x0F;
+    }
+}
+
+impl SpuriousInterrupt {
+
+    /// Spurious interrupt vector.
+    pub fn vector(&self) -> u8 {
+        (self.reg & 0xFF) as _
+    }
+
+    /// Set spurious interrupt vector.
+    pub fn set_vector(&mut self, vec: u8) {
+        self.reg = self.reg & !0xFF | (vec as u32);
+    }
+
+    /// EOI broadcast suppression.
+    pub fn eoi_broadcast_suppression(&self) -> bool {
+        self.reg & 0b1_0000_0000_0000 != 0
+    }
+
+    /// Enable EOI broadcast suppression.
+    ///
+    /// # Safety
+    /// Not supported on some CPUs.
+    pub unsafe fn enable_eoi_broadcast_suppression(&mut self) {
+        self.reg |= 0b1_0000_0000_0000;
+    }
+
+    pub fn disable_eoi_broadcast_suppression(&mut self) {
+        self.reg &= !0b1_0000_0000_0000;
+    }
+
+    pub fn focus_processor_checking(&self) -> bool {
+        // NOTE if flag is zero then checking is enabled!
+        self.reg & 0b0010_0000_0000 == 0
+    }
+
+    pub fn enable_focus_processor_checking(&mut self) {
+        self.reg &= !0b0010_0000_0000;
+    }
+
+    /// Disable focus processor checking.
+    ///
+    /// # Safety
+    /// Not supported on Pentium 4 and Xeon processors.
+    pub unsafe fn disable_focus_processor_checking(&mut self) {
+        self.reg |= 0b0010_0000_0000;
+    }
+
+    pub fn is_apic_software_enabled(&self) -> bool {
+        self.reg & 0b0001_0000_0000 != 0
+    }
+
+    pub fn software_enable_apic(&mut self) {
+        self.reg |= 0b0001_0000_0000;
+    }
+
+    pub fn software_disable_apic(&mut self) {
+        self.reg &= !0b0001_0000_0000;
+    }
+}
+
+impl LvtCmci {
+    lvt_entry_impl_base!();
+    lvt_entry_impl_delivery!(Fixed, Smi, Nmi);
+    lvt_entry_impl_builder!();
+}
+
+impl Icr0 {
+
+    /// Interrupt vector.
+    pub fn vector(&self) -> u8 {
+        (self.reg & 0xFF) as u8
+    }
+
+    /// Set new interrupt vector.
+    pub fn set_vector(&mut self, vec: u8) {
+        self.reg = self.reg & !0xFF | (vec as u32);
+    }
+
+    /// Interrupt delivery mode. None if the field holds reserved value.
+    pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+        let val = (self.reg >> 8) & 0b111;
+        DeliveryMode::from_raw(val)
+    }
+
+    /// Set delivery mode.
+    pub fn set_delivery_mode(&mut self, mode: DeliveryMode) {
+        let val = mode as u32;
+        self.reg = self.reg & !(0b111 << 8) | (val << 8);
+    }
+
+    /// IPI destination mode.
+    pub fn destination_mode(&self) -> DestinationMode {
+        use self::DestinationMode::*;
+
+        if self.reg & (1 << 11) != 0 {
+            Logical
+        } else {
+            Physical
+        }
+    }
+
+    /// Set IPI destination mode.
+    pub fn set_destination_mode(&mut self, mode: DestinationMode) {
+        use self::DestinationMode::*;
+
+        match mode {
+            Logical     => self.reg |=   1 << 11,
+            Physical    => self.reg &= !(1 << 11)
+        }
+    }
+
+    /// Delivery status.
+    pub fn delivery_status(&self) -> DeliveryStatus {
+        use self::DeliveryStatus::*;
+
+        if self.reg & (1 << 12) != 0 {
+            SendPending
+        } else {
+            Idle
+        }
+    }
+
+    pub fn level(&self) -> IcrLevel {
+        use self::IcrLevel::*;
+
+        if self.reg & (1 << 14) != 0 {
+            Assert
+        } else {
+            Deassert
+        }
+    }
+
+    pub fn set_level(&mut self, level: IcrLevel) {
+        use self::IcrLevel::*;
+
+        match level {
+            Assert      => self.reg |=   1 << 14,
+            Deassert    => self.reg &= !(1 << 14)
+        }
+    }
+
+    pub fn trigger_mode(&self) -> TriggerMode {
+        use self::TriggerMode::*;
+
+        if self.reg & (1 << 15) != 0 {
+            LevelSensitive
+        } else {
+            EdgeSensitive
+        }
+    }
+
+    pub fn set_trigger_mode(&mut self, mode: TriggerMode) {
+        use self::TriggerMode::*;
+
+        match mode {
+            EdgeSensitive   => self.reg &= !(1 << 15),
+            LevelSensitive  => self.reg |=   1 << 15
+        }
+    }
+
+    pub fn destination_shorthand(&self) -> DestinationShorthand {
+        DestinationShorthand::from((self.reg >> 18) & 0b11)
+    }
+
+    pub fn set_destination_shorthand(&mut self, ds: DestinationShorthand) {
+        let val = (ds as u32) << 18;
+        self.reg = self.reg & !(0b11 << 18) | val;
+    }
+}
+
+impl Icr1 {
+
+    pub fn destination(&self) -> u8 {
+        (self.reg >> 24) as _
+    }
+
+    pub fn set_destination(&mut self, dest: u8) {
+        self.reg = self.reg & !(0xFF << 24) | ((dest as u32) << 24);
+    }
+
+    /// Destination in x2APIC mode. Whole register holds the destination.
+    pub fn x2apic_destination(&self) -> u32 {
+        self.reg
+    }
+
+    /// Set destination in x2APIC mode.
+    pub fn set_x2apic_destination(&mut self, dest: u32) {
+        self.reg = dest;
+    }
+}
+
+impl<'a> Icr<'a> {
+
+    pub fn vector(&self) -> u8 {
+        self.icr0_pending.vector()
+    }
+
+    pub fn set_vector(&mut self, vec: u8) {
+        self.icr0_pending.set_vector(vec)
+    }
+
+    pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+        self.icr0_pending.delivery_mode()
+    }
+
+    pub fn set_delivery_mode(&mut self, mode: DeliveryMode) {
+        self.icr0_pending.set_delivery_mode(mode)
+    }
+
+    pub fn destination_mode(&self) -> DestinationMode {
+        self.icr0_pending.destination_mode()
+    }
+
+    pub fn set_destination_mode(&mut self, mode: DestinationMode) {
+        self.icr0_pending.set_destination_mode(mode)
+    }
+
+    pub fn delivery_status(&self) -> DeliveryStatus {
+        // Current value, not pending one. Always idle in x2APIC mode.
+        let val = self.apic.read_icr() as u32;
+        Icr0::from_raw(val).delivery_status()
+    }
+
+    pub fn level(&self) -> IcrLevel {
+        self.icr0_pending.level()
+    }
+
+    pub fn set_level(&mut self, level: IcrLevel) {
+        self.icr0_pending.set_level(level)
+    }
+
+    pub fn trigger_mode(&self) -> TriggerMode {
+        self.icr0_pending.trigger_mode()
+    }
+    pub fn set_trigger_mode(&mut self, mode: TriggerMode) {
+        self.icr0_pending.set_trigger_mode(mode)
+    }
+
+    pub fn destination_shorthand(&self) -> DestinationShorthand {
+        self.icr0_pending.destination_shorthand()
+    }
+
+    pub fn set_destination_shorthand(&mut self, ds: DestinationShorthand) {
+        self.icr0_pending.set_destination_shorthand(ds)
+    }
+
+    pub fn destination(&self) -> u8 {
+        self.icr1_pending.destination()
+    }
+
+    pub fn set_destination(&mut self, dest: u8) {
+        self.icr1_pending.set_destination(dest)
+    }
+
+    /// Destination in x2APIC mode.
+    pub fn x2apic_destination(&self) -> u32 {
+        self.icr1_pending.x2apic_destination()
+    }
+
+    /// Set destination in x2APIC mode.
+    pub fn set_x2apic_destination(&mut self, dest: u32) {
+        self.icr1_pending.set_x2apic_destination(dest)
+    }
+
+    /// Restore ICR0 value from registers.
+    pub fn restore_icr0(&mut self) {
+        let val = self.apic.read_icr() as u32;
+        self.icr0_pending = Icr0::from_raw(val);
+    }
+
+    /// Restore ICR1 value from registers.
+    pub fn restore_icr1(&mut self) {
+        let val = (self.apic.read_icr() >> 32) as u32;
+        self.icr1_pending = Icr1::from_raw(val);
+    }
+
+    /// Save pending values to real registers.
+    pub fn apply(&mut self) {
+        let lo = self.icr0_pending.raw() as u64;
+        let hi = self.icr1_pending.raw() as u64;
+
+        // In xAPIC mode high part is stored first.
+        unsafe { self.apic.write_icr(lo | (hi << 32)); }
+    }
+
+    /// Save changes only from icr0. In x2APIC mode ICR is a single
+    /// register so the whole pending value is stored.
+    pub fn apply_icr0(&mut self) {
+        use self::LocalApicMode::*;
+
+        match self.apic.mode() {
+            XApic   => unsafe {
+                let val = self.icr0_pending.raw();
+                self.apic.write_reg(LocalApicReg::InterruptCommand0, val);
+            },
+            X2Apic  => self.apply(),
+        }
+    }
+}
+
+impl<'a, T: LapicRegister> LocalApicRegMut<'a, T> {
+
+    /// Create handle with current register value.
+    fn new(apic: &'a mut LocalApic, reg: LocalApicReg) -> Self {
+        let val = T::from_raw(apic.read_reg(reg));
+
+        LocalApicRegMut {
+            apic,
+            reg,
+            val,
+            changed : false,
+        }
+    }
+
+    /// Create handle with zero value without reading the register.
+    /// Used for write-only registers.
+    fn new_zeroed(apic: &'a mut LocalApic, reg: LocalApicReg) -> Self {
+        LocalApicRegMut {
+            apic,
+            reg,
+            val     : T::from_raw(0),
+            changed : false,
+        }
+    }
+}
+
+impl<'a, T: LapicRegister> ::core::ops::Deref for LocalApicRegMut<'a, T> {
+
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.val
+    }
+}
+
+impl<'a, T: LapicRegister> ::core::ops::DerefMut for LocalApicRegMut<'a, T> {
+
+    fn deref_mut(&mut self) -> &mut T {
+        self.changed = true;
+        &mut self.val
+    }
+}
+
+impl<'a, T: LapicRegister> Drop for LocalApicRegMut<'a, T> {
+
+    fn drop(&mut self) {
+        if self.changed {
+            let val = self.val.raw();
+            unsafe { self.apic.write_reg(self.reg, val); }
+        }
+    }
+}
+
+impl LvtTimer {
+    lvt_entry_impl_base!();
+
+    /// Current LVT Timer mode. None if the field holds reserved value.
+    pub fn mode(&self) -> Option<LvtTimerMode> {
+        use self::LvtTimerMode::*;
+
+        match self.reg & LVT_TIMER_MODE_MASK {
+            v if v == OneShot as u32        => Some(OneShot),
+            v if v == Periodic as u32       => Some(Periodic),
+            v if v == TscDeadline as u32    => Some(TscDeadline),
+            _                               => None,
+        }
+    }
+
+    /// Set LVT Timer mode.
+    ///
+    /// # Safety
+    /// Caller must ensure that given mode is supported.
+    /// TSC Deadline mode is not supported on some CPUs.
+    pub unsafe fn set_mode(&mut self, mode: LvtTimerMode) {
+        self.reg = self.reg & !LVT_TIMER_MODE_MASK | mode as u32;
+    }
+}
+
+impl LvtThermalSensor {
+    lvt_entry_impl_base!();
+    lvt_entry_impl_delivery!(Fixed, Smi, Nmi);
+    lvt_entry_impl_builder!();
+}
+
+impl LvtPerformanceCounters {
+    lvt_entry_impl_base!();
+    lvt_entry_impl_delivery!(Fixed, Smi, Nmi);
+    lvt_entry_impl_builder!();
+}
+
+impl LvtLint0 {
+    lvt_entry_impl_base!();
+    lvt_entry_impl_delivery!(Fixed, Smi, Nmi, Init, ExtInt);
+    lvt_entry_impl_builder!();
+    lvt_entry_impl_lint!();
+
+    /// Entry that passes interrupts of 8259A PIC to the processor
+    /// in virtual wire mode.
+    pub fn virtual_wire() -> Self {
+        let mut e = Self::new().with_masked(false);
+        e.set_delivery_mode(DeliveryMode::ExtInt);
+        e
+    }
+
+    /// Set trigger mode. Note that some delivery modes ignore this value
+    /// and use their default trigger mode.
+    pub fn set_trigger_mode(&mut self, mode: TriggerMode) {
+        use self::TriggerMode::*;
+
+        match mode {
+            EdgeSensitive   => self.reg &= !(1 << 15),
+            LevelSensitive  => self.reg |=   1 << 15
+        }
+    }
+
+    /// Use given trigger mode.
+    pub fn with_trigger_mode(mut self, mode: TriggerMode) -> Self {
+        self.set_trigger_mode(mode);
+        self
+    }
+}
+
+impl LvtLint1 {
+    lvt_entry_impl_base!();
+    lvt_entry_impl_delivery!(Fixed, Smi, Nmi, Init, ExtInt);
+    lvt_entry_impl_builder!();
+    lvt_entry_impl_lint!();
+
+    /// Entry that delivers signal on LINT1 pin as NMI.
+    pub fn nmi() -> Self {
+        let mut e = Self::new().with_masked(false);
+        e.set_delivery_mode(DeliveryMode::Nmi);
+        e
+    }
+}
+
+impl LvtError {
+    lvt_entry_impl_base!();
+}
+
+impl ErrorStatus {
+
+    /// Raw error bits.
+    pub fn bits(&self) -> u32 {
+        self.reg & 0xFF
+    }
+
+    /// Whether any error is reported.
+    pub fn has_errors(&self) -> bool {
+        self.bits() != 0
+    }
+
+    /// Whether given error is reported.
+    pub fn contains(&self, err: ApicError) -> bool {
+        self.reg & err as u32 != 0
+    }
+
+    /// Checksum error of the message sent on the APIC bus.
+    /// Only used by P6 family and Pentium processors.
+    pub fn send_checksum(&self) -> bool {
+        self.contains(ApicError::SendChecksum)
+    }
+
+    /// Checksum error of the message received on the APIC bus.
+    /// Only used by P6 family and Pentium processors.
+    pub fn receive_checksum(&self) -> bool {
+        self.contains(ApicError::ReceiveChecksum)
+    }
+
+    /// Sent message was not accepted by any APIC on the bus.
+    /// Only used by P6 family and Pentium processors.
+    pub fn send_accept(&self) -> bool {
+        self.contains(ApicError::SendAccept)
+    }
+
+    /// Received message was not accepted by any APIC on the bus
+    /// including this one. Only used by P6 family and Pentium processors.
+    pub fn receive_accept(&self) -> bool {
+        self.contains(ApicError::ReceiveAccept)
+    }
+
+    /// Attempt to send lowest priority IPI which is not supported
+    /// by this processor.
+    pub fn redirectable_ipi(&self) -> bool {
+        self.contains(ApicError::RedirectableIpi)
+    }
+
+    /// Attempt to send IPI with illegal vector (0-15).
+    pub fn send_illegal_vector(&self) -> bool {
+        self.contains(ApicError::SendIllegalVector)
+    }
+
+    /// Interrupt with illegal vector was received or generated locally.
+    pub fn received_illegal_vector(&self) -> bool {
+        self.contains(ApicError::ReceivedIllegalVector)
+    }
+
+    /// Access to unimplemented register in xAPIC register page.
+    pub fn illegal_register_address(&self) -> bool {
+        self.contains(ApicError::IllegalRegisterAddress)
+    }
+
+    /// Iterator over reported errors.
+    pub fn iter(&self) -> ErrorStatusIter {
+        ErrorStatusIter {
+            status  : *self,
+            index   : 0,
+        }
+    }
+}
+
+impl ApicError {
+
+    /// All errors in the order of their bits.
+    pub const ALL: [ApicError; 8] = [
+        ApicError::SendChecksum,
+        ApicError::ReceiveChecksum,
+        ApicError::SendAccept,
+        ApicError::ReceiveAccept,
+        ApicError::RedirectableIpi,
+        ApicError::SendIllegalVector,
+        ApicError::ReceivedIllegalVector,
+        ApicError::IllegalRegisterAddress,
+    ];
+
+    /// Short description of the error to be used in logs.
+    pub fn description(&self) -> &'static str {
+        use self::ApicError::*;
+        match *self {
+            SendChecksum            => "send checksum error",
+            ReceiveChecksum         => "receive checksum error",
+            SendAccept              => "send accept error",
+            ReceiveAccept           => "receive accept error",
+            RedirectableIpi         => "redirectable IPI",
+            SendIllegalVector       => "send illegal vector",
+            ReceivedIllegalVector   => "received illegal vector",
+            IllegalRegisterAddress  => "illegal register address",
+        }
+    }
+}
+
+impl Iterator for ErrorStatusIter {
+
+    type Item = ApicError;
+
+    fn next(&mut self) -> Option<ApicError> {
+        while self.index < ApicError::ALL.len() {
+            let err = ApicError::ALL[self.index];
+            self.index += 1;
+
+            if self.status.contains(err) {
+                return Some(err);
+            }
+        }
+        None
+    }
+}
+
+impl TimerCurrentCount {
+
+    /// Get current timer value.
+    pub fn value(&self) -> u32 {
+        self.reg
+    }
+}
+
+impl TimerInitialCount {
+
+    /// Get initial timer value.
+    pub fn value(&self) -> u32 {
+        self.reg
+    }
+
+    /// Set new initial timer value.
+    pub fn set(&mut self, value: u32) {
+        self.reg = value;
+    }
+
+    /// Stop the timer by setting zero value.
+    pub fn stop_timer(&mut self) {
+        // This stops the timer according to Intel manual.
+        self.set(0);
+    }
+}
+
+impl DivideValue {
+
+    /// Number the timer base frequency is divided by.
+    pub fn divisor(&self) -> u32 {
+        use self::DivideValue::*;
+        match *self {
+            Div1    => 1,
+            Div2    => 2,
+            Div4    => 4,
+            Div8    => 8,
+            Div16   => 16,
+            Div32   => 32,
+            Div64   => 64,
+            Div128  => 128,
+        }
+    }
+}
+
+impl DivideConfiguration {
+
+    /// Set register specified value.
+    unsafe fn set_reg(&mut self, val: u32) {
+        self.reg = val;
+    }
+
+    /// Set specified divide value.
+    pub fn set(&mut self, div: DivideValue) {
+        unsafe { self.set_reg(div as _); }
+    }
+
+    /// Set divide factor 1.
+    pub fn set_1(&mut self) {
+        self.set(DivideValue::Div1);
+    }
+
+    /// Set divide factor 2.
+    pub fn set_2(&mut self) {
+        self.set(DivideValue::Div2);
+    }
+
+    /// Set divide factor 4.
+    pub fn set_4(&mut self) {
+        self.set(DivideValue::Div4);
+    }
+
+    /// Set divide factor 8.
+    pub fn set_8(&mut self) {
+        self.set(DivideValue::Div8);
+    }
+
+    /// Set divide factor 16.
+    pub fn set_16(&mut self) {
+        self.set(DivideValue::Div16);
+    }
+
+    /// Set divide factor 32.
+    pub fn set_32(&mut self) {
+        self.set(DivideValue::Div32);
+    }
+
+    /// Set divide factor 64.
+    pub fn set_64(&mut self) {
+        self.set(DivideValue::Div64);
+    }
+
+    /// Set divide factor 128.
+    pub fn set_128(&mut self) {
+        self.set(DivideValue::Div128);
+    }
+
+    /// Get value of this register.
+    pub fn get(&self) -> DivideValue {
+        use self::DivideValue::*;
+        match self.reg {
+            0b0000 => Div2  ,
+            0b0001 => Div4  ,
+            0b0010 => Div8  ,
+            0b0011 => Div16 ,
+            0b1000 => Div32 ,
+            0b1001 => Div64 ,
+            0b1010 => Div128,
+            0b1011 => Div1  ,
+            _      => unreachable!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    const APIC_BASE_MSR : u32 = 0x1B;
+
+    #[test]
+    fn xapic_error_interrupt_programming() {
+        let mock = Mock::new();
+        mock.set_msr(APIC_BASE_MSR, 0xFEE0_0900);
+        mock.set_mmio(0xFEE0_0370, 0x0001_0000);
+
+        with_backend(&mock, || {
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            assert_eq!(apic.mode(), LocalApicMode::XApic);
+            apic.enable_error_interrupt(0xFE);
+        });
+
+        assert!(mock.log_eq(&[
+            MsrRead  (0x1B, 0xFEE0_0900),
+            MmioWrite(0xFEE0_0280, 0),
+            MmioWrite(0xFEE0_0280, 0),
+            MmioRead (0xFEE0_0370, 0x0001_0000),
+            MmioWrite(0xFEE0_0370, 0x0000_00FE),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn x2apic_lint_programming() {
+        let mock = Mock::new();
+        mock.set_msr(APIC_BASE_MSR, 0xFEE0_0D00);
+        mock.set_msr(0x835, 0x0001_0000);
+        mock.set_msr(0x836, 0x0001_0000);
+
+        with_backend(&mock, || {
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            assert_eq!(apic.mode(), LocalApicMode::X2Apic);
+            apic.configure_lints(true);
+        });
+
+        assert!(mock.log_eq(&[
+            MsrRead (0x1B,  0xFEE0_0D00),
+            MsrRead (0x835, 0x0001_0000),
+            MsrWrite(0x835, 0x0000_0700),
+            MsrRead (0x836, 0x0001_0000),
+            MsrWrite(0x836, 0x0000_0400),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn reserved_delivery_and_timer_modes() {
+        assert_eq!(DeliveryMode::from_raw(0b011), None);
+        assert_eq!(DeliveryMode::from_raw(0b110),
+                Some(DeliveryMode::StartUp));
+        assert_eq!(DeliveryMode::from_raw(0b1000), None);
+
+        assert_eq!(LvtLint0::from_raw(0b011 << 8).delivery_mode(), None);
+        assert_eq!(LvtLint0::from_raw(0b100 << 8).delivery_mode(),
+                Some(DeliveryMode::Nmi));
+        assert_eq!(Icr0::from_raw(0b011 << 8).delivery_mode(), None);
+        assert_eq!(Icr0::from_raw(0b110 << 8).delivery_mode(),
+                Some(DeliveryMode::StartUp));
+
+        assert_eq!(LvtTimer::from_raw(0b11 << 17).mode(), None);
+        assert_eq!(LvtTimer::from_raw(0b10 << 17).mode(),
+                Some(LvtTimerMode::TscDeadline));
+    }
+}
@@ -0,0 +1,297 @@
+use super::{DivideValue, LocalApic, LvtTimerMode};
+use calibration::TimerCalibration;
+use hw::Backend;
+use msr::TscDeadline;
+
+/// Divide values ordered from the finest resolution to the longest
+/// possible period.
+const DIVIDE_VALUES: [DivideValue; 8] = [
+    DivideValue::Div1,
+    DivideValue::Div2,
+    DivideValue::Div4,
+    DivideValue::Div8,
+    DivideValue::Div16,
+    DivideValue::Div32,
+    DivideValue::Div64,
+    DivideValue::Div128,
+];
+
+/// Local APIC timer interface. Arms periodic, one-shot or TSC-deadline
+/// interrupts for given durations using calibrated timer frequencies.
+pub struct ApicTimer<'a> {
+    apic        : &'a mut LocalApic,
+    calibration : TimerCalibration,
+    vector      : u8,
+
+    tsc_deadline_supported : bool,
+
+    /// Last armed setting to be used by `rearm`.
+    armed       : Option<ArmedTimer>,
+}
+
+/// Setting the timer was armed with.
+#[derive(Clone, Copy)]
+enum ArmedTimer {
+
+    /// Periodic or one-shot countdown of given initial count.
+    Count(LvtTimerMode, DivideValue, u32),
+
+    /// TSC deadline after given amount of TSC ticks.
+    Deadline(u64),
+}
+
+impl<'a> ApicTimer<'a> {
+
+    /// Create timer interface that delivers interrupts with given vector.
+    /// Timer frequency must be calibrated for APIC timer, otherwise only
+    /// TSC-deadline mode is available.
+    pub fn new(apic: &'a mut LocalApic, calibration: TimerCalibration,
+            vector: u8) -> Self {
+        ApicTimer {
+            apic,
+            calibration,
+            vector,
+
+            tsc_deadline_supported : TscDeadline::exists(),
+
+            armed       : None,
+        }
+    }
+
+    /// Whether TSC-deadline mode is supported by the processor.
+    pub fn tsc_deadline_supported(&self) -> bool {
+        self.tsc_deadline_supported
+    }
+
+    /// Calibration used by this timer.
+    pub fn calibration(&self) -> &TimerCalibration {
+        &self.calibration
+    }
+
+    /// Choose finest divide value that allows counting given duration and
+    /// return it with the initial count. None if timer is not calibrated
+    /// or duration does not fit in the counter even with max divide value.
+    fn count_for_ns(&self, ns: u64) -> Option<(DivideValue, u32)> {
+        for &div in DIVIDE_VALUES.iter() {
+            let ticks = self.calibration.ns_to_apic_ticks(ns, div)?;
+
+            if ticks > !0u32 as u64 {
+                continue;
+            }
+
+            // Zero initial count stops the timer.
+            let ticks = if ticks == 0 { 1 } else { ticks as u32 };
+            return Some((div, ticks));
+        }
+        None
+    }
+
+    /// Program LVT timer register and start counting.
+    fn start_count(&mut self, mode: LvtTimerMode, div: DivideValue,
+            count: u32) {
+        self.stop();
+
+        {
+            let mut lvt = self.apic.lvt_timer_mut();
+            lvt.set_vector(self.vector);
+            unsafe { lvt.set_mode(mode); }
+            lvt.unmask();
+        }
+        self.apic.divide_configuration_mut().set(div);
+        self.apic.initial_count_mut().set(count);
+
+        self.armed = Some(ArmedTimer::Count(mode, div, count));
+    }
+
+    /// Arm periodic interrupt with given period in nanoseconds. Returns
+    /// false if the timer is not calibrated or the period is too long.
+    pub fn arm_periodic_ns(&mut self, ns: u64) -> bool {
+        match self.count_for_ns(ns) {
+            Some((div, count)) => {
+                self.start_count(LvtTimerMode::Periodic, div, count);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Arm periodic interrupt with given frequency in Hz. Returns false if
+    /// the timer is not calibrated or the frequency is too low or high.
+    pub fn arm_periodic_hz(&mut self, hz: u32) -> bool {
+        if hz == 0 {
+            return false;
+        }
+
+        for &div in DIVIDE_VALUES.iter() {
+            let timer_hz = match self.calibration.apic_timer_hz(div) {
+                Some(t) => t,
+                None    => return false
+            };
+
+            let count = (timer_hz + hz as u64 / 2) / hz as u64;
+            if count == 0 {
+                return false;
+            }
+            if count <= !0u32 as u64 {
+                self.start_count(LvtTimerMode::Periodic, div, count as u32);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Arm one-shot interrupt after given amount of nanoseconds. Returns
+    /// false if the timer is not calibrated or the duration is too long.
+    pub fn arm_one_shot_ns(&mut self, ns: u64) -> bool {
+        match self.count_for_ns(ns) {
+            Some((div, count)) => {
+                self.start_count(LvtTimerMode::OneShot, div, count);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Arm TSC-deadline interrupt at given TSC value. Returns false if
+    /// TSC-deadline mode is not supported.
+    pub fn arm_tsc_deadline(&mut self, tsc: u64) -> bool {
+        if !self.tsc_deadline_supported {
+            return false;
+        }
+
+        self.stop();
+
+        {
+            let mut lvt = self.apic.lvt_timer_mut();
+            lvt.set_vector(self.vector);
+            unsafe { lvt.set_mode(LvtTimerMode::TscDeadline); }
+            lvt.unmask();
+        }
+
+        // MSR write is not ordered with the MMIO write of LVT in xAPIC
+        // mode, so the deadline could be armed in the old mode.
+        ::hw::backend().mfence();
+
+        unsafe {
+            let mut deadline = TscDeadline::read();
+            deadline.set(tsc);
+            deadline.write();
+        }
+
+        let now = ::msr::rdtsc();
+        self.armed = Some(ArmedTimer::Deadline(tsc.saturating_sub(now)));
+        true
+    }
+
+    /// Arm TSC-deadline interrupt after given amount of nanoseconds.
+    /// Returns false if TSC-deadline mode is not supported.
+    pub fn arm_tsc_deadline_ns(&mut self, ns: u64) -> bool {
+        let ticks = self.calibration.ns_to_tsc(ns);
+        let now = ::msr::rdtsc();
+        self.arm_tsc_deadline(now.saturating_add(ticks))
+    }
+
+    /// Stop the timer without forgetting last armed setting.
+    fn stop(&mut self) {
+        if self.apic.lvt_timer().mode() == Some(LvtTimerMode::TscDeadline) {
+            unsafe {
+                let mut deadline = TscDeadline::read();
+                deadline.disarm();
+                deadline.write();
+            }
+        } else {
+            self.apic.initial_count_mut().stop_timer();
+        }
+    }
+
+    /// Cancel pending timer interrupt. Timer can be armed again with the
+    /// same setting by `rearm`.
+    pub fn cancel(&mut self) {
+        self.stop();
+    }
+
+    /// Arm the timer again with the last setting. Countdown starts from
+    /// the beginning. Returns false if the timer was never armed.
+    pub fn rearm(&mut self) -> bool {
+        match self.armed {
+            Some(ArmedTimer::Count(mode, div, count)) => {
+                self.start_count(mode, div, count);
+                true
+            },
+            Some(ArmedTimer::Deadline(ticks)) => {
+                let now = ::msr::rdtsc();
+                self.arm_tsc_deadline(now.saturating_add(ticks))
+            },
+            None => false
+        }
+    }
+
+    /// Whether the timer is counting.
+    pub fn is_armed(&self) -> bool {
+        if self.apic.lvt_timer().mode() == Some(LvtTimerMode::TscDeadline) {
+            let deadline = unsafe { TscDeadline::read() };
+            deadline.value() != 0
+        } else {
+            self.apic.current_count().value() != 0
+        }
+    }
+
+    /// Time left until next interrupt in nanoseconds. None if the timer
+    /// is not armed or the remaining time can't be computed because the
+    /// timer is not calibrated.
+    pub fn remaining_ns(&self) -> Option<u64> {
+        if self.apic.lvt_timer().mode() == Some(LvtTimerMode::TscDeadline) {
+            let deadline = unsafe { TscDeadline::read() }.value();
+            if deadline == 0 {
+                return None;
+            }
+
+            let now = ::msr::rdtsc();
+            return Some(self.calibration.tsc_to_ns(
+                    deadline.saturating_sub(now)));
+        }
+
+        let count = self.apic.current_count().value();
+        if count == 0 {
+            return None;
+        }
+
+        let div = self.apic.divide_configuration().get();
+        self.calibration.apic_ticks_to_ns(count as u64, div)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use calibration::CalibrationSource;
+    use cpuid::Info;
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    #[test]
+    fn tsc_deadline_is_fenced_after_lvt_write() {
+        let mock = Mock::new();
+        mock.set_msr(0x1B, 0xFEE0_0900);
+        mock.set_cpuid(1, 0, Info { eax: 0, ebx: 0, ecx: 1 << 24, edx: 0 });
+
+        let cal = TimerCalibration::new(1_000_000_000, None,
+                CalibrationSource::CpuidCrystal);
+        with_backend(&mock, || {
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            let mut timer = ApicTimer::new(&mut apic, cal, 0x30);
+            assert!(timer.arm_tsc_deadline(0x1_0000_0000));
+        });
+
+        let log = mock.log();
+        let lvt = log.iter()
+                .position(|a| *a == Some(MmioWrite(0xFEE0_0320, 0x0004_0030)))
+                .unwrap();
+        assert_eq!(&log[lvt + 1..lvt + 4], &[
+            Some(Mfence),
+            Some(MsrRead (0x6E0, 0)),
+            Some(MsrWrite(0x6E0, 0x1_0000_0000)),
+        ]);
+    }
+}
@@ -1,16 +1,15 @@
+use core::arch::asm;
+
 #[inline(always)]
 pub fn bsf_u64(i: u64) -> Option<u64> {
-    let zf  : u64;
+    let zf  : u8;
     let r   : u64;
 
-    unsafe { asm! (
-        "
-        bsf    $0, $0   \n
-        setz   $1
-        "
-        : "=r"(r), "=r"(zf)
-        : "r"(i)
-        :: "intel"
+    unsafe { asm!(
+        "bsf    {0}, {0}",
+        "setz   {1}",
+        inout(reg) i => r,
+        lateout(reg_byte) zf,
     ); }
 
     if zf == 0 {
@@ -22,17 +21,14 @@ pub fn bsf_u64(i: u64) -> Option<u64> {
 
 #[inline(always)]
 pub fn bsf_u32(i: u32) -> Option<u32> {
-    let zf  : u32;
+    let zf  : u8;
     let r   : u32;
 
-    unsafe { asm! (
-        "
-        bsf    $0, $0   \n
-        setz   $1
-        "
-        : "=r"(r), "=r"(zf)
-        : "r"(i)
-        :: "intel"
+    unsafe { asm!(
+        "bsf    {0:e}, {0:e}",
+        "setz   {1}",
+        inout(reg) i => r,
+        lateout(reg_byte) zf,
     ); }
 
     if zf == 0 {
@@ -44,17 +40,14 @@ pub fn bsf_u32(i: u32) -> Option<u32> {
 
 #[inline(always)]
 pub fn bsf_u16(i: u16) -> Option<u16> {
-    let zf  : u16;
+    let zf  : u8;
     let r   : u16;
 
-    unsafe { asm! (
-        "
-        bsf    $0, $0   \n
-        setz   $1
-        "
-        : "=r"(r), "=r"(zf)
-        : "r"(i)
-        :: "intel"
+    unsafe { asm!(
+        "bsf    {0:x}, {0:x}",
+        "setz   {1}",
+        inout(reg) i => r,
+        lateout(reg_byte) zf,
     ); }
 
     if zf == 0 {
@@ -66,17 +59,14 @@ pub fn bsf_u16(i: u16) -> Option<u16> {
 
 #[inline(always)]
 pub fn bsr_u64(i: u64) -> Option<u64> {
-    let zf  : u64;
+    let zf  : u8;
     let r   : u64;
 
-    unsafe { asm! (
-        "
-        bsr    $0, $0   \n
-        setz   $1
-        "
-        : "=r"(r), "=r"(zf)
-        : "r"(i)
-        :: "intel"
+    unsafe { asm!(
+        "bsr    {0}, {0}",
+        "setz   {1}",
+        inout(reg) i => r,
+        lateout(reg_byte) zf,
     ); }
 
     if zf == 0 {
@@ -88,17 +78,14 @@ pub fn bsr_u64(i: u64) -> Option<u64> {
 
 #[inline(always)]
 pub fn bsr_u32(i: u32) -> Option<u32> {
-    let zf  : u32;
+    let zf  : u8;
     let r   : u32;
 
-    unsafe { asm! (
-        "
-        bsr    $0, $0   \n
-        setz   $1
-        "
-        : "=r"(r), "=r"(zf)
-        : "r"(i)
-        :: "intel"
+    unsafe { asm!(
+        "bsr    {0:e}, {0:e}",
+        "setz   {1}",
+        inout(reg) i => r,
+        lateout(reg_byte) zf,
     ); }
 
     if zf == 0 {
@@ -110,17 +97,14 @@ pub fn bsr_u32(i: u32) -> Option<u32> {
 
 #[inline(always)]
 pub fn bsr_u16(i: u16) -> Option<u16> {
-    let zf  : u16;
+    let zf  : u8;
     let r   : u16;
 
-    unsafe { asm! (
-        "
-        bsr    $0, $0   \n
-        setz   $1
-        "
-        : "=r"(r), "=r"(zf)
-        : "r"(i)
-        :: "intel"
+    unsafe { asm!(
+        "bsr    {0:x}, {0:x}",
+        "setz   {1}",
+        inout(reg) i => r,
+        lateout(reg_byte) zf,
     ); }
 
     if zf == 0 {
@@ -132,48 +116,30 @@ pub fn bsr_u16(i: u16) -> Option<u16> {
 
 #[inline(always)]
 pub fn bsf_i64(i: i64) -> Option<i64> {
-    match bsf_u64(i as u64) {
-        Some(v) => Some(v as i64),
-        None    => None
-    }
+    bsf_u64(i as u64).map(|v| v as i64)
 }
 
 #[inline(always)]
 pub fn bsf_i32(i: i32) -> Option<i32> {
-    match bsf_u32(i as u32) {
-        Some(v) => Some(v as i32),
-        None    => None
-    }
+    bsf_u32(i as u32).map(|v| v as i32)
 }
 
 #[inline(always)]
 pub fn bsf_i16(i: i16) -> Option<i16> {
-    match bsf_u16(i as u16) {
-        Some(v) => Some(v as i16),
-        None    => None
-    }
+    bsf_u16(i as u16).map(|v| v as i16)
 }
 
 #[inline(always)]
 pub fn bsr_i64(i: i64) -> Option<i64> {
-    match bsr_u64(i as u64) {
-        Some(v) => Some(v as i64),
-        None    => None
-    }
+    bsr_u64(i as u64).map(|v| v as i64)
 }
 
 #[inline(always)]
 pub fn bsr_i32(i: i32) -> Option<i32> {
-    match bsr_u32(i as u32) {
-        Some(v) => Some(v as i32),
-        None    => None
-    }
+    bsr_u32(i as u32).map(|v| v as i32)
 }
 
 #[inline(always)]
 pub fn bsr_i16(i: i16) -> Option<i16> {
-    match bsr_u16(i as u16) {
-        Some(v) => Some(v as i16),
-        None    => None
-    }
+    bsr_u16(i as u16).map(|v| v as i16)
 }
@@ -0,0 +1,456 @@
+use apic::{DivideValue, LocalApic, LvtTimer, LvtTimerMode};
+use cpuid::{FrequencyInfo, TscInfo, VendorString};
+use hw::Backend;
+use msr::TscDeadline;
+use pit::{Divisor, Pit, BASE_FREQUENCY};
+
+/// Duration of single PIT measurement in PIT ticks. This is 10 ms.
+const MEASURE_TICKS: u32 = 11_932;
+
+/// Count of PIT measurements. The shortest one is used as it is the
+/// least disturbed by SMIs and other delays.
+const MEASURE_RUNS: usize = 3;
+
+/// Where calibrated frequency was taken from.
+#[derive(PartialEq, Clone, Copy)]
+pub enum CalibrationSource {
+
+    /// Measured against PIT channel 2.
+    Pit,
+
+    /// Computed from core crystal clock reported by CPUID leaf 0x15.
+    CpuidCrystal,
+
+    /// Nominal frequency reported by CPUID leaf 0x16.
+    CpuidNominal,
+}
+
+/// Calibrated frequencies of TSC and local APIC timer.
+#[derive(Clone, Copy)]
+pub struct TimerCalibration {
+    tsc_hz          : u64,
+    tsc_source      : CalibrationSource,
+
+    apic_hz         : Option<u64>,
+    apic_source     : Option<CalibrationSource>,
+}
+
+/// Local APIC timer settings saved for the time of measurement.
+struct SavedTimer {
+    lvt         : LvtTimer,
+    div         : DivideValue,
+    initial     : u32,
+    deadline    : u64,
+}
+
+/// Result of a single measurement against the PIT.
+struct Measurement {
+    tsc     : u64,
+    apic    : Option<u64>,
+}
+
+/// Run single measurement of TSC and optionally local APIC timer ticks
+/// during given amount of PIT ticks. APIC timer must be already set up
+/// in masked one-shot mode with divide value 1.
+fn measure_once(pit: &mut Pit, apic: &mut Option<&mut LocalApic>,
+        ticks: u32) -> Measurement {
+    let div = Divisor::from_count(ticks).unwrap();
+
+    pit.ch2_start_one_shot(div);
+    if let Some(ref mut apic) = *apic {
+        apic.initial_count_mut().set(!0);
+    }
+    let tsc_start = ::msr::rdtsc();
+
+    while !pit.ch2_one_shot_expired() {}
+
+    let tsc_end = ::msr::rdtsc();
+    let apic_ticks = match *apic {
+        Some(ref mut apic) => {
+            let cur = apic.current_count().value();
+            apic.initial_count_mut().stop_timer();
+            Some((!0 - cur) as u64)
+        },
+        None => None
+    };
+    pit.set_ch2_gate(false);
+
+    Measurement {
+        tsc     : tsc_end.wrapping_sub(tsc_start),
+        apic    : apic_ticks,
+    }
+}
+
+/// Convert ticks counted during given amount of PIT ticks to Hz.
+fn ticks_to_hz(ticks: u64, pit_ticks: u32) -> u64 {
+    ticks * BASE_FREQUENCY as u64 / pit_ticks as u64
+}
+
+impl TimerCalibration {
+
+    /// Create calibration from known frequencies.
+    pub fn new(tsc_hz: u64, apic_hz: Option<u64>,
+            source: CalibrationSource) -> Self {
+        TimerCalibration {
+            tsc_hz,
+            tsc_source      : source,
+
+            apic_hz,
+            apic_source     : apic_hz.map(|_| source),
+        }
+    }
+
+    /// Calibrate timers. Frequencies that are exactly reported by CPUID
+    /// leaf 0x15 are taken from there. Others are measured against PIT
+    /// channel 2, which is skipped when CPUID reports all of them. If
+    /// measurement gives no result, nominal frequencies from CPUID leaf
+    /// 0x16 are used. None is returned if TSC frequency can't be
+    /// determined at all.
+    ///
+    /// When local APIC is given, its timer is calibrated too. See
+    /// `measure_with_pit` for how timer settings are restored.
+    pub fn calibrate(pit: &mut Pit, lapic: Option<&mut LocalApic>)
+            -> Option<Self> {
+        let has_apic = lapic.is_some();
+
+        let mut tsc = Self::tsc_from_cpuid();
+        let mut apic = if has_apic { Self::apic_from_cpuid() } else { None };
+
+        let measured = if tsc.is_none() || (has_apic && apic.is_none()) {
+            Self::measure_with_pit(pit, lapic)
+        } else {
+            None
+        };
+
+        if tsc.is_none() {
+            tsc = measured.map(|m| (m.tsc_hz, CalibrationSource::Pit));
+        }
+        if tsc.is_none() {
+            tsc = Self::tsc_nominal_from_cpuid();
+        }
+
+        if apic.is_none() {
+            apic = match measured {
+                Some(m) => m.apic_hz.map(|hz| (hz, CalibrationSource::Pit)),
+                None    => None
+            };
+        }
+        if apic.is_none() && has_apic {
+            apic = Self::apic_nominal_from_cpuid();
+        }
+
+        tsc.map(|(tsc_hz, tsc_source)| TimerCalibration {
+            tsc_hz,
+            tsc_source,
+
+            apic_hz         : apic.map(|a| a.0),
+            apic_source     : apic.map(|a| a.1),
+        })
+    }
+
+    /// Measure TSC and, if given, local APIC timer frequency against PIT
+    /// channel 2. None is returned if TSC did not advance.
+    ///
+    /// APIC timer is stopped during the measurement. Afterwards its LVT
+    /// entry and divide value are restored and the timer is re-armed: in
+    /// TSC-deadline mode with the saved deadline and in other modes with
+    /// the saved initial count. The count that was in progress is not
+    /// preserved, so a running one-shot or periodic count starts over.
+    pub fn measure_with_pit(pit: &mut Pit, mut apic: Option<&mut LocalApic>)
+            -> Option<Self> {
+        // Save APIC timer settings and stop it in masked one-shot mode.
+        let saved = match apic {
+            Some(ref mut apic) => {
+                let lvt = apic.lvt_timer();
+                let div = apic.divide_configuration().get();
+                let initial = apic.initial_count().value();
+                let tsc_mode = lvt.mode() == Some(LvtTimerMode::TscDeadline);
+                let deadline = if tsc_mode {
+                    unsafe { TscDeadline::read().value() }
+                } else {
+                    0
+                };
+
+                {
+                    let mut t = apic.lvt_timer_mut();
+                    t.mask();
+                    unsafe { t.set_mode(LvtTimerMode::OneShot); }
+                }
+                apic.divide_configuration_mut().set(DivideValue::Div1);
+                // Leaving TSC-deadline mode disarms the deadline.
+                apic.initial_count_mut().stop_timer();
+
+                Some(SavedTimer {
+                    lvt,
+                    div,
+                    initial,
+                    deadline,
+                })
+            },
+            None => None
+        };
+
+        let mut best: Option<Measurement> = None;
+        for _ in 0..MEASURE_RUNS {
+            let m = measure_once(pit, &mut apic, MEASURE_TICKS);
+            best = match best {
+                Some(b) if b.tsc <= m.tsc => Some(b),
+                _ => Some(m),
+            };
+        }
+
+        if let (Some(apic), Some(saved)) = (apic, saved) {
+            Self::restore_timer(apic, saved);
+        }
+
+        let best = best.unwrap();
+        if best.tsc == 0 {
+            return None;
+        }
+
+        let apic_hz = match best.apic {
+            Some(0) | None  => None,
+            Some(t)         => Some(ticks_to_hz(t, MEASURE_TICKS)),
+        };
+
+        Some(Self::new(ticks_to_hz(best.tsc, MEASURE_TICKS), apic_hz,
+                CalibrationSource::Pit))
+    }
+
+    /// Restore APIC timer settings saved by `measure_with_pit`.
+    fn restore_timer(apic: &mut LocalApic, saved: SavedTimer) {
+        *apic.lvt_timer_mut() = saved.lvt;
+        apic.divide_configuration_mut().set(saved.div);
+
+        if saved.lvt.mode() == Some(LvtTimerMode::TscDeadline) {
+            if saved.deadline != 0 {
+                // Order the deadline after the LVT write, see
+                // `ApicTimer::arm_tsc_deadline`.
+                ::hw::backend().mfence();
+                unsafe {
+                    let mut msr = TscDeadline::read();
+                    msr.set(saved.deadline);
+                    msr.write();
+                }
+            }
+        } else if saved.initial != 0 {
+            apic.initial_count_mut().set(saved.initial);
+        }
+    }
+
+    /// Max basic CPUID leaf.
+    fn max_leaf() -> u32 {
+        VendorString::get().max_value()
+    }
+
+    /// Exact TSC frequency from CPUID leaf 0x15.
+    fn tsc_from_cpuid() -> Option<(u64, CalibrationSource)> {
+        if Self::max_leaf() < 0x15 {
+            return None;
+        }
+
+        TscInfo::get().tsc_hz().map(|hz| (hz, CalibrationSource::CpuidCrystal))
+    }
+
+    /// Nominal TSC frequency from CPUID leaf 0x16 base frequency.
+    fn tsc_nominal_from_cpuid() -> Option<(u64, CalibrationSource)> {
+        if Self::max_leaf() < 0x16 {
+            return None;
+        }
+
+        match FrequencyInfo::get().base_mhz() {
+            0   => None,
+            mhz => Some((mhz as u64 * 1_000_000,
+                    CalibrationSource::CpuidNominal))
+        }
+    }
+
+    /// APIC timer frequency from core crystal clock of CPUID leaf 0x15.
+    fn apic_from_cpuid() -> Option<(u64, CalibrationSource)> {
+        if Self::max_leaf() < 0x15 {
+            return None;
+        }
+
+        match TscInfo::get().crystal_hz() {
+            0   => None,
+            hz  => Some((hz as u64, CalibrationSource::CpuidCrystal))
+        }
+    }
+
+    /// APIC timer frequency from bus frequency of CPUID leaf 0x16.
+    fn apic_nominal_from_cpuid() -> Option<(u64, CalibrationSource)> {
+        if Self::max_leaf() < 0x16 {
+            return None;
+        }
+
+        match FrequencyInfo::get().bus_mhz() {
+            0   => None,
+            mhz => Some((mhz as u64 * 1_000_000,
+                    CalibrationSource::CpuidNominal))
+        }
+    }
+
+    /// TSC frequency in Hz.
+    pub fn tsc_hz(&self) -> u64 {
+        self.tsc_hz
+    }
+
+    /// Where TSC frequency was taken from.
+    pub fn tsc_source(&self) -> CalibrationSource {
+        self.tsc_source
+    }
+
+    /// Local APIC timer frequency in Hz for given divide value. None if
+    /// APIC timer was not calibrated.
+    pub fn apic_timer_hz(&self, div: DivideValue) -> Option<u64> {
+        self.apic_hz.map(|hz| hz / div.divisor() as u64)
+    }
+
+    /// Where local APIC timer frequency was taken from.
+    pub fn apic_timer_source(&self) -> Option<CalibrationSource> {
+        self.apic_source
+    }
+
+    /// Amount of TSC ticks in given amount of nanoseconds.
+    pub fn ns_to_tsc(&self, ns: u64) -> u64 {
+        mul_div(ns, self.tsc_hz, 1_000_000_000)
+    }
+
+    /// Amount of nanoseconds in given amount of TSC ticks.
+    pub fn tsc_to_ns(&self, ticks: u64) -> u64 {
+        mul_div(ticks, 1_000_000_000, self.tsc_hz)
+    }
+
+    /// Amount of local APIC timer ticks with given divide value in given
+    /// amount of nanoseconds. None if APIC timer was not calibrated.
+    pub fn ns_to_apic_ticks(&self, ns: u64, div: DivideValue) -> Option<u64> {
+        self.apic_timer_hz(div).map(|hz| mul_div(ns, hz, 1_000_000_000))
+    }
+
+    /// Amount of nanoseconds in given amount of local APIC timer ticks with
+    /// given divide value. None if APIC timer was not calibrated.
+    pub fn apic_ticks_to_ns(&self, ticks: u64, div: DivideValue)
+            -> Option<u64> {
+        match self.apic_timer_hz(div) {
+            Some(0) | None  => None,
+            Some(hz)        => Some(mul_div(ticks, 1_000_000_000, hz)),
+        }
+    }
+}
+
+/// Compute a * b / c without intermediate overflow. Result saturates.
+fn mul_div(a: u64, b: u64, c: u64) -> u64 {
+    if c == 0 {
+        return !0;
+    }
+
+    let r = a as u128 * b as u128 / c as u128;
+    if r > !0u64 as u128 {
+        !0
+    } else {
+        r as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpuid::Info;
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    const LVT_TIMER     : usize = 0xFEE0_0320;
+    const INITIAL_COUNT : usize = 0xFEE0_0380;
+    const DIVIDE_CONFIG : usize = 0xFEE0_03E0;
+    const TSC_DEADLINE  : u32   = 0x6E0;
+
+    fn xapic_mock() -> Mock {
+        let mock = Mock::new();
+        mock.set_msr(0x1B, 0xFEE0_0900);
+        mock
+    }
+
+    fn set_max_leaf(mock: &Mock, max: u32) {
+        mock.set_cpuid(0, 0, Info { eax: max, ebx: 0, ecx: 0, edx: 0 });
+    }
+
+    /// Make every PIT one-shot countdown expire at the first poll.
+    fn script_pit(mock: &Mock) {
+        for _ in 0..MEASURE_RUNS {
+            mock.push_port_in(0x42, 0x80);
+        }
+    }
+
+    #[test]
+    fn calibrate_skips_pit_when_cpuid_reports_frequencies() {
+        let mock = xapic_mock();
+        set_max_leaf(&mock, 0x15);
+        mock.set_cpuid(0x15, 0, Info {
+            eax: 2, ebx: 250, ecx: 24_000_000, edx: 0
+        });
+
+        let cal = with_backend(&mock, || {
+            let mut pit = unsafe { Pit::new_no_sync() };
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            TimerCalibration::calibrate(&mut pit, Some(&mut apic))
+        }).unwrap();
+
+        assert_eq!(cal.tsc_hz(), 3_000_000_000);
+        assert!(cal.tsc_source() == CalibrationSource::CpuidCrystal);
+        assert_eq!(cal.apic_timer_hz(DivideValue::Div1), Some(24_000_000));
+
+        let port_access = mock.log().iter()
+                .any(|a| matches!(*a, Some(PortIn8(..)) | Some(PortOut8(..))));
+        assert!(!port_access);
+    }
+
+    #[test]
+    fn measure_restores_tsc_deadline() {
+        let mock = xapic_mock();
+        mock.set_mmio(LVT_TIMER, LvtTimerMode::TscDeadline as u32 | 0x30);
+        mock.set_mmio(DIVIDE_CONFIG, 0b1011);
+        mock.set_msr(TSC_DEADLINE, 0x1234_5678);
+        mock.set_tsc(0, 1000);
+        script_pit(&mock);
+
+        let cal = with_backend(&mock, || {
+            let mut pit = unsafe { Pit::new_no_sync() };
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            TimerCalibration::measure_with_pit(&mut pit, Some(&mut apic))
+        });
+        assert!(cal.is_some());
+
+        // Deadline is written last, after LVT is back in TSC-deadline mode.
+        let log = mock.log();
+        let lvt = log.iter()
+                .rposition(|a| *a == Some(MmioWrite(LVT_TIMER, 0x0004_0030)));
+        assert!(lvt.is_some());
+        assert_eq!(log.last(),
+                Some(&Some(MsrWrite(TSC_DEADLINE, 0x1234_5678))));
+        assert_eq!(mock.mmio(DIVIDE_CONFIG), Some(0b1011));
+    }
+
+    #[test]
+    fn measure_restores_initial_count() {
+        let mock = xapic_mock();
+        mock.set_mmio(LVT_TIMER, LvtTimerMode::Periodic as u32 | 0x30);
+        mock.set_mmio(DIVIDE_CONFIG, 0b0011);
+        mock.set_mmio(INITIAL_COUNT, 100_000);
+        mock.set_tsc(0, 1000);
+        script_pit(&mock);
+
+        with_backend(&mock, || {
+            let mut pit = unsafe { Pit::new_no_sync() };
+            let mut apic = unsafe { LocalApic::unsafe_new() };
+            TimerCalibration::measure_with_pit(&mut pit, Some(&mut apic))
+        });
+
+        assert_eq!(mock.mmio(LVT_TIMER), Some(0x0002_0030));
+        assert_eq!(mock.mmio(DIVIDE_CONFIG), Some(0b0011));
+        assert_eq!(mock.mmio(INITIAL_COUNT), Some(100_000));
+
+        let n = mock.log_len();
+        assert_eq!(mock.access(n - 1), Some(MmioWrite(INITIAL_COUNT, 100_000)));
+    }
+}
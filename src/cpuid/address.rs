@@ -0,0 +1,104 @@
+use super::*;
+
+/// Architectural limit of physical address width in bits.
+pub const MAX_PHYS_ADDR_BITS: u8 = 52;
+
+/// Physical and linear address widths and paging capabilities of the
+/// processor.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct AddressWidths {
+    phys_bits   : u8,
+    linear_bits : u8,
+    la57        : bool,
+    page_1g     : bool,
+}
+
+impl AddressWidths {
+
+    /// Query CPUID for address widths. If leaf 0x8000_0008 is not
+    /// available, physical width is 36 bits with PAE or 32 bits otherwise.
+    pub fn get() -> Self {
+        let features = CpuFeatures::get();
+        let max_ext = IntelExtended::get().max_value();
+
+        let (phys_bits, linear_bits) = if max_ext >= 0x8000_0008 {
+            let eax = Info::get_by_code(0x8000_0008).eax;
+            (eax as u8, (eax >> 8) as u8)
+        } else if features.has(CpuFeature::Pae) {
+            (36, 32)
+        } else {
+            (32, 32)
+        };
+
+        Self::new(phys_bits, linear_bits, features.has(CpuFeature::La57),
+                features.has(CpuFeature::Page1Gb))
+    }
+
+    /// Create address widths from given values. Physical width is limited
+    /// by `MAX_PHYS_ADDR_BITS`.
+    pub fn new(phys_bits: u8, linear_bits: u8, la57: bool, page_1g: bool)
+            -> Self {
+        AddressWidths {
+            phys_bits   : phys_bits.min(MAX_PHYS_ADDR_BITS),
+            linear_bits,
+            la57,
+            page_1g,
+        }
+    }
+
+    /// Physical address width (MAXPHYADDR) in bits.
+    pub fn phys_bits(&self) -> u8 {
+        self.phys_bits
+    }
+
+    /// Linear address width in bits.
+    pub fn linear_bits(&self) -> u8 {
+        self.linear_bits
+    }
+
+    /// Whether 5-level paging is supported.
+    pub fn la57_supported(&self) -> bool {
+        self.la57
+    }
+
+    /// Whether 1 GiB pages are supported.
+    pub fn page_1g_supported(&self) -> bool {
+        self.page_1g
+    }
+
+    /// Count of paging levels that give the widest linear address space.
+    pub fn max_paging_levels(&self) -> u8 {
+        if self.la57 { 5 } else { 4 }
+    }
+
+    /// Mask of all valid physical address bits.
+    pub fn phys_mask(&self) -> u64 {
+        (1u64 << self.phys_bits) - 1
+    }
+
+    /// Whether physical address fits into MAXPHYADDR.
+    pub fn phys_addr_valid(&self, addr: u64) -> bool {
+        addr & !self.phys_mask() == 0
+    }
+
+    /// Mask of address bits of paging entry that points to a region
+    /// aligned to `1 << align_bits` bytes.
+    pub fn entry_addr_mask(&self, align_bits: u8) -> u64 {
+        self.phys_mask() & !((1u64 << align_bits) - 1)
+    }
+
+    /// Mask of reserved bits between MAXPHYADDR and bit 51 of paging entry.
+    /// Entries with these bits set cause page faults.
+    pub fn entry_reserved_mask(&self) -> u64 {
+        let arch = (1u64 << MAX_PHYS_ADDR_BITS) - 1;
+        arch & !self.phys_mask()
+    }
+
+    /// Whether linear address is canonical for given count of paging
+    /// levels: bits above the translated ones must equal the top one.
+    pub fn is_canonical(&self, addr: u64, levels: u8) -> bool {
+        let bits = if levels >= 5 { 57 } else { 48 };
+        let shift = 64 - bits;
+        (((addr << shift) as i64) >> shift) as u64 == addr
+    }
+}
@@ -0,0 +1,733 @@
+use super::*;
+use core::slice;
+
+/// Max count of caches in `CacheHierarchy`.
+pub const MAX_CACHES: usize = 8;
+
+/// Max count of TLBs in `CacheHierarchy`.
+pub const MAX_TLBS: usize = 16;
+
+/// TLB holds translations for 4 KiB pages.
+pub const TLB_PAGE_4K: u8 = 1 << 0;
+
+/// TLB holds translations for 2 MiB pages.
+pub const TLB_PAGE_2M: u8 = 1 << 1;
+
+/// TLB holds translations for 4 MiB pages.
+pub const TLB_PAGE_4M: u8 = 1 << 2;
+
+/// TLB holds translations for 1 GiB pages.
+pub const TLB_PAGE_1G: u8 = 1 << 3;
+
+/// Associativity value of fully associative caches and TLBs.
+pub const FULLY_ASSOCIATIVE: u16 = 0xFFFF;
+
+/// Kind of data held by cache or TLB.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// Parameters of a single cache.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct CacheInfo {
+    level       : u8,
+    kind        : CacheType,
+    ways        : u16,
+    partitions  : u16,
+    line_size   : u16,
+    sets        : u32,
+    shared_by   : u16,
+    inclusive   : bool,
+}
+
+/// Parameters of a single TLB.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TlbInfo {
+    level       : u8,
+    kind        : CacheType,
+    page_sizes  : u8,
+    entries     : u16,
+    ways        : u16,
+}
+
+/// Meaning of a descriptor byte of leaf 2.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Leaf2Descriptor {
+    Cache(CacheInfo),
+    Tlb(TlbInfo),
+
+    /// Hardware prefetch stride in bytes.
+    Prefetch(u16),
+
+    /// Cache parameters must be read from leaf 4.
+    UseLeaf4,
+}
+
+/// Iterator over decoded descriptors of leaf 2. Unknown descriptors are
+/// skipped. Some descriptors describe two TLBs and yield two items.
+pub struct Leaf2Descriptors {
+    bytes       : [u8; 15],
+    index       : usize,
+    item        : usize,
+}
+
+/// Caches and TLBs of the processor.
+#[derive(Clone, Copy)]
+pub struct CacheHierarchy {
+    caches      : [CacheInfo; MAX_CACHES],
+    cache_count : usize,
+    tlbs        : [TlbInfo; MAX_TLBS],
+    tlb_count   : usize,
+    prefetch    : Option<u16>,
+}
+
+/// Leaf 2 cache descriptors: byte, level, type, size in KiB, ways and
+/// line size.
+const LEAF2_CACHES: &[(u8, u8, CacheType, u32, u16, u16)] = &[
+    (0x06, 1, CacheType::Instruction,     8,  4, 32),
+    (0x08, 1, CacheType::Instruction,    16,  4, 32),
+    (0x09, 1, CacheType::Instruction,    32,  4, 64),
+    (0x0A, 1, CacheType::Data,            8,  2, 32),
+    (0x0C, 1, CacheType::Data,           16,  4, 32),
+    (0x0D, 1, CacheType::Data,           16,  4, 64),
+    (0x0E, 1, CacheType::Data,           24,  6, 64),
+    (0x1D, 2, CacheType::Unified,       128,  2, 64),
+    (0x21, 2, CacheType::Unified,       256,  8, 64),
+    (0x22, 3, CacheType::Unified,       512,  4, 64),
+    (0x23, 3, CacheType::Unified,      1024,  8, 64),
+    (0x24, 2, CacheType::Unified,      1024, 16, 64),
+    (0x25, 3, CacheType::Unified,      2048,  8, 64),
+    (0x29, 3, CacheType::Unified,      4096,  8, 64),
+    (0x2C, 1, CacheType::Data,           32,  8, 64),
+    (0x30, 1, CacheType::Instruction,    32,  8, 64),
+    (0x41, 2, CacheType::Unified,       128,  4, 32),
+    (0x42, 2, CacheType::Unified,       256,  4, 32),
+    (0x43, 2, CacheType::Unified,       512,  4, 32),
+    (0x44, 2, CacheType::Unified,      1024,  4, 32),
+    (0x45, 2, CacheType::Unified,      2048,  4, 32),
+    (0x46, 3, CacheType::Unified,      4096,  4, 64),
+    (0x47, 3, CacheType::Unified,      8192,  8, 64),
+    (0x48, 2, CacheType::Unified,      3072, 12, 64),
+    (0x49, 3, CacheType::Unified,      4096, 16, 64),
+    (0x4A, 3, CacheType::Unified,      6144, 12, 64),
+    (0x4B, 3, CacheType::Unified,      8192, 16, 64),
+    (0x4C, 3, CacheType::Unified,     12288, 12, 64),
+    (0x4D, 3, CacheType::Unified,     16384, 16, 64),
+    (0x4E, 2, CacheType::Unified,      6144, 24, 64),
+    (0x60, 1, CacheType::Data,           16,  8, 64),
+    (0x66, 1, CacheType::Data,            8,  4, 64),
+    (0x67, 1, CacheType::Data,           16,  4, 64),
+    (0x68, 1, CacheType::Data,           32,  4, 64),
+    (0x78, 2, CacheType::Unified,      1024,  4, 64),
+    (0x79, 2, CacheType::Unified,       128,  8, 64),
+    (0x7A, 2, CacheType::Unified,       256,  8, 64),
+    (0x7B, 2, CacheType::Unified,       512,  8, 64),
+    (0x7C, 2, CacheType::Unified,      1024,  8, 64),
+    (0x7D, 2, CacheType::Unified,      2048,  8, 64),
+    (0x7F, 2, CacheType::Unified,       512,  2, 64),
+    (0x80, 2, CacheType::Unified,       512,  8, 64),
+    (0x82, 2, CacheType::Unified,       256,  8, 32),
+    (0x83, 2, CacheType::Unified,       512,  8, 32),
+    (0x84, 2, CacheType::Unified,      1024,  8, 32),
+    (0x85, 2, CacheType::Unified,      2048,  8, 32),
+    (0x86, 2, CacheType::Unified,       512,  4, 64),
+    (0x87, 2, CacheType::Unified,      1024,  8, 64),
+    (0xD0, 3, CacheType::Unified,       512,  4, 64),
+    (0xD1, 3, CacheType::Unified,      1024,  4, 64),
+    (0xD2, 3, CacheType::Unified,      2048,  4, 64),
+    (0xD6, 3, CacheType::Unified,      1024,  8, 64),
+    (0xD7, 3, CacheType::Unified,      2048,  8, 64),
+    (0xD8, 3, CacheType::Unified,      4096,  8, 64),
+    (0xDC, 3, CacheType::Unified,      1536, 12, 64),
+    (0xDD, 3, CacheType::Unified,      3072, 12, 64),
+    (0xDE, 3, CacheType::Unified,      6144, 12, 64),
+    (0xE2, 3, CacheType::Unified,      2048, 16, 64),
+    (0xE3, 3, CacheType::Unified,      4096, 16, 64),
+    (0xE4, 3, CacheType::Unified,      8192, 16, 64),
+    (0xEA, 3, CacheType::Unified,     12288, 24, 64),
+    (0xEB, 3, CacheType::Unified,     18432, 24, 64),
+    (0xEC, 3, CacheType::Unified,     24576, 24, 64),
+];
+
+const P4K: u8 = TLB_PAGE_4K;
+const P2M: u8 = TLB_PAGE_2M;
+const P4M: u8 = TLB_PAGE_4M;
+const P1G: u8 = TLB_PAGE_1G;
+const FULL: u16 = FULLY_ASSOCIATIVE;
+
+/// Leaf 2 TLB descriptors: byte, level, type, page sizes, entries and
+/// ways. Descriptors that describe two TLBs are listed twice.
+const LEAF2_TLBS: &[(u8, u8, CacheType, u8, u16, u16)] = &[
+    (0x01, 1, CacheType::Instruction, P4K,               32,    4),
+    (0x02, 1, CacheType::Instruction, P4M,                2, FULL),
+    (0x03, 1, CacheType::Data,        P4K,               64,    4),
+    (0x04, 1, CacheType::Data,        P4M,                8,    4),
+    (0x05, 1, CacheType::Data,        P4M,               32,    4),
+    (0x0B, 1, CacheType::Instruction, P4M,                4,    4),
+    (0x50, 1, CacheType::Instruction, P4K | P2M | P4M,   64, FULL),
+    (0x51, 1, CacheType::Instruction, P4K | P2M | P4M,  128, FULL),
+    (0x52, 1, CacheType::Instruction, P4K | P2M | P4M,  256, FULL),
+    (0x55, 1, CacheType::Instruction, P2M | P4M,          7, FULL),
+    (0x56, 1, CacheType::Data,        P4M,               16,    4),
+    (0x57, 1, CacheType::Data,        P4K,               16,    4),
+    (0x59, 1, CacheType::Data,        P4K,               16, FULL),
+    (0x5A, 1, CacheType::Data,        P2M | P4M,         32,    4),
+    (0x5B, 1, CacheType::Data,        P4K | P4M,         64, FULL),
+    (0x5C, 1, CacheType::Data,        P4K | P4M,        128, FULL),
+    (0x5D, 1, CacheType::Data,        P4K | P4M,        256, FULL),
+    (0x61, 1, CacheType::Instruction, P4K,               48, FULL),
+    (0x63, 1, CacheType::Data,        P2M | P4M,         32,    4),
+    (0x63, 1, CacheType::Data,        P1G,                4,    4),
+    (0x64, 1, CacheType::Data,        P4K,              512,    4),
+    (0x6A, 1, CacheType::Data,        P4K,               64,    8),
+    (0x6B, 1, CacheType::Data,        P4K,              256,    8),
+    (0x6C, 1, CacheType::Data,        P2M | P4M,        128,    8),
+    (0x6D, 1, CacheType::Data,        P1G,               16, FULL),
+    (0x76, 1, CacheType::Instruction, P2M | P4M,          8, FULL),
+    (0xA0, 1, CacheType::Data,        P4K,               32, FULL),
+    (0xB0, 1, CacheType::Instruction, P4K,              128,    4),
+    (0xB1, 1, CacheType::Instruction, P2M,                8,    4),
+    (0xB2, 1, CacheType::Instruction, P4K,               64,    4),
+    (0xB3, 1, CacheType::Data,        P4K,              128,    4),
+    (0xB4, 1, CacheType::Data,        P4K,              256,    4),
+    (0xB5, 1, CacheType::Instruction, P4K,               64,    8),
+    (0xB6, 1, CacheType::Instruction, P4K,              128,    8),
+    (0xBA, 1, CacheType::Data,        P4K,               64,    4),
+    (0xC0, 1, CacheType::Data,        P4K | P4M,          8,    4),
+    (0xC1, 2, CacheType::Unified,     P4K | P2M,       1024,    8),
+    (0xC2, 1, CacheType::Data,        P4K | P2M,         16,    4),
+    (0xC3, 2, CacheType::Unified,     P4K | P2M,       1536,    6),
+    (0xC3, 2, CacheType::Unified,     P1G,               16,    4),
+    (0xC4, 1, CacheType::Data,        P2M | P4M,         32,    4),
+    (0xCA, 2, CacheType::Unified,     P4K,              512,    4),
+];
+
+impl CacheInfo {
+
+    /// Create cache information. Count of sets is given for each
+    /// partition. Zero `shared_by` means the value is not reported.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(level: u8, kind: CacheType, ways: u16, partitions: u16,
+            line_size: u16, sets: u32, shared_by: u16, inclusive: bool)
+            -> Self {
+        CacheInfo {
+            level,
+            kind,
+            ways,
+            partitions,
+            line_size,
+            sets,
+            shared_by,
+            inclusive,
+        }
+    }
+
+    /// Cache from leaf 4 or AMD leaf 0x8000_001D register values. None
+    /// is returned for the null entry that terminates the list.
+    pub fn from_deterministic(info: Info) -> Option<Self> {
+        let kind = match info.eax & 0x1F {
+            1 => CacheType::Data,
+            2 => CacheType::Instruction,
+            3 => CacheType::Unified,
+            _ => return None,
+        };
+
+        let ways = if info.eax & (1 << 9) != 0 {
+            FULLY_ASSOCIATIVE
+        } else {
+            ((info.ebx >> 22) & 0x3FF) as u16 + 1
+        };
+
+        Some(CacheInfo::new(
+            ((info.eax >> 5) & 0x7) as u8,
+            kind,
+            ways,
+            ((info.ebx >> 12) & 0x3FF) as u16 + 1,
+            (info.ebx & 0xFFF) as u16 + 1,
+            info.ecx.wrapping_add(1),
+            ((info.eax >> 14) & 0xFFF) as u16 + 1,
+            info.edx & (1 << 1) != 0,
+        ))
+    }
+
+    /// Cache of given size in KiB with unknown sharing.
+    fn from_size(level: u8, kind: CacheType, size_kb: u32, ways: u16,
+            line_size: u16) -> Self {
+        let sets = if ways == FULLY_ASSOCIATIVE || ways == 0 || line_size == 0
+        {
+            1
+        } else {
+            size_kb * 1024 / (ways as u32 * line_size as u32)
+        };
+        let ways = if sets == 1 && line_size != 0 {
+            (size_kb * 1024 / line_size as u32) as u16
+        } else {
+            ways
+        };
+
+        CacheInfo::new(level, kind, ways, 1, line_size, sets, 0, false)
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn kind(&self) -> CacheType {
+        self.kind
+    }
+
+    /// Ways of associativity.
+    pub fn ways(&self) -> u16 {
+        self.ways
+    }
+
+    /// Physical line partitions.
+    pub fn partitions(&self) -> u16 {
+        self.partitions
+    }
+
+    /// Line size in bytes.
+    pub fn line_size(&self) -> u16 {
+        self.line_size
+    }
+
+    /// Count of sets.
+    pub fn sets(&self) -> u32 {
+        self.sets
+    }
+
+    /// Max count of logical processors sharing this cache. Zero if not
+    /// reported.
+    pub fn shared_by(&self) -> u16 {
+        self.shared_by
+    }
+
+    /// Whether the cache is inclusive of lower cache levels.
+    pub fn inclusive(&self) -> bool {
+        self.inclusive
+    }
+
+    /// Whether the cache is fully associative.
+    pub fn fully_associative(&self) -> bool {
+        self.ways == FULLY_ASSOCIATIVE || self.sets == 1
+    }
+
+    /// Cache size in bytes.
+    pub fn size(&self) -> u64 {
+        let ways = if self.ways == FULLY_ASSOCIATIVE { 1 } else { self.ways };
+        ways as u64 * self.partitions as u64 * self.line_size as u64
+                * self.sets as u64
+    }
+
+    /// Count of page colors for pages of given size: pages of different
+    /// colors never compete for the same sets of this cache.
+    pub fn colors(&self, page_size: u64) -> u64 {
+        let way_size = self.sets as u64 * self.line_size as u64
+                * self.partitions as u64;
+        if page_size == 0 || self.fully_associative() {
+            return 1;
+        }
+        (way_size / page_size).max(1)
+    }
+}
+
+impl TlbInfo {
+
+    /// Create TLB information. Page sizes are given by `TLB_PAGE_*`
+    /// flags.
+    pub fn new(level: u8, kind: CacheType, page_sizes: u8, entries: u16,
+            ways: u16) -> Self {
+        TlbInfo {
+            level,
+            kind,
+            page_sizes,
+            entries,
+            ways,
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn kind(&self) -> CacheType {
+        self.kind
+    }
+
+    /// Page sizes as a combination of `TLB_PAGE_*` flags.
+    pub fn page_sizes(&self) -> u8 {
+        self.page_sizes
+    }
+
+    /// Whether TLB holds translations for pages of all given sizes.
+    pub fn supports(&self, page_sizes: u8) -> bool {
+        self.page_sizes & page_sizes == page_sizes
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+
+    /// Ways of associativity.
+    pub fn ways(&self) -> u16 {
+        self.ways
+    }
+
+    pub fn fully_associative(&self) -> bool {
+        self.ways == FULLY_ASSOCIATIVE
+    }
+}
+
+impl Leaf2Descriptor {
+
+    /// Decode given descriptor byte. Each descriptor may have several
+    /// meanings, `index` selects one of them. None is returned for
+    /// unknown descriptors and when index is out of range.
+    pub fn decode(byte: u8, index: usize) -> Option<Self> {
+        let cache = LEAF2_CACHES.iter()
+            .filter(|d| d.0 == byte)
+            .map(|&(_, l, k, s, w, ls)| {
+                Leaf2Descriptor::Cache(CacheInfo::from_size(l, k, s, w, ls))
+            });
+        let tlb = LEAF2_TLBS.iter()
+            .filter(|d| d.0 == byte)
+            .map(|&(_, l, k, p, e, w)| {
+                Leaf2Descriptor::Tlb(TlbInfo::new(l, k, p, e, w))
+            });
+        let other = match byte {
+            0xF0    => Some(Leaf2Descriptor::Prefetch(64)),
+            0xF1    => Some(Leaf2Descriptor::Prefetch(128)),
+            0xFF    => Some(Leaf2Descriptor::UseLeaf4),
+            _       => None,
+        };
+
+        cache.chain(tlb).chain(other).nth(index)
+    }
+}
+
+impl Tlb {
+
+    /// Descriptor bytes of leaf 2. Descriptors of registers that are
+    /// marked invalid are zeroed. The low byte of EAX that holds the
+    /// iteration count is not included.
+    pub fn descriptor_bytes(&self) -> [u8; 15] {
+        let regs = [self.info.eax, self.info.ebx, self.info.ecx,
+                self.info.edx];
+        let mut b = [0; 15];
+        for (r, reg) in regs.iter().enumerate() {
+            if reg & (1 << 31) != 0 {
+                continue;
+            }
+            for i in 0..4 {
+                if r == 0 && i == 0 {
+                    continue;
+                }
+                b[r * 4 + i - 1] = (reg >> (i * 8)) as u8;
+            }
+        }
+        b
+    }
+
+    /// Iterator over decoded descriptors.
+    pub fn descriptors(&self) -> Leaf2Descriptors {
+        Leaf2Descriptors {
+            bytes   : self.descriptor_bytes(),
+            index   : 0,
+            item    : 0,
+        }
+    }
+}
+
+impl Iterator for Leaf2Descriptors {
+
+    type Item = Leaf2Descriptor;
+
+    fn next(&mut self) -> Option<Leaf2Descriptor> {
+        while self.index < self.bytes.len() {
+            let byte = self.bytes[self.index];
+            if byte != 0 {
+                if let Some(d) = Leaf2Descriptor::decode(byte, self.item) {
+                    self.item += 1;
+                    return Some(d);
+                }
+            }
+            self.index += 1;
+            self.item = 0;
+        }
+        None
+    }
+}
+
+impl Default for CacheHierarchy {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheHierarchy {
+
+    /// Empty hierarchy.
+    pub fn new() -> Self {
+        CacheHierarchy {
+            caches      : [CacheInfo::new(0, CacheType::Unified, 0, 0, 0, 0,
+                    0, false); MAX_CACHES],
+            cache_count : 0,
+            tlbs        : [TlbInfo::new(0, CacheType::Unified, 0, 0, 0);
+                    MAX_TLBS],
+            tlb_count   : 0,
+            prefetch    : None,
+        }
+    }
+
+    /// Query CPUID for caches and TLBs of the current processor. Leaf 4 or
+    /// AMD leaf 0x8000_001D is used for caches when available, otherwise
+    /// leaf 2 or AMD leaves 0x8000_0005 and 0x8000_0006. TLBs are taken
+    /// from leaf 2 or AMD leaves 0x8000_0005, 0x8000_0006 and 0x8000_0019.
+    pub fn get() -> Self {
+        let max_basic = VendorString::get().max_value();
+        let max_ext = IntelExtended::get().max_value();
+        let vendor = Vendor::from_bytes(&VendorString::get().bytes());
+        let amd = vendor == Vendor::Amd || vendor == Vendor::Hygon;
+
+        let mut h = Self::new();
+
+        if !amd && max_basic >= 2 {
+            h.add_leaf2(max_basic >= 4);
+        }
+        if h.cache_count == 0 {
+            if !amd && max_basic >= 4 {
+                h.add_deterministic(4);
+            } else if amd && max_ext >= 0x8000_001D
+                    && CpuFeatures::get().has(CpuFeature::TopoExt) {
+                h.add_deterministic(0x8000_001D);
+            }
+        }
+        if amd {
+            let need_caches = h.cache_count == 0;
+            h.add_amd_leaves(max_ext, need_caches);
+        }
+
+        h.sort();
+        h
+    }
+
+    /// Add cache. Returns false if there is no room for it.
+    pub fn add_cache(&mut self, cache: CacheInfo) -> bool {
+        if self.cache_count >= MAX_CACHES {
+            return false;
+        }
+        self.caches[self.cache_count] = cache;
+        self.cache_count += 1;
+        true
+    }
+
+    /// Add TLB. Returns false if there is no room for it.
+    pub fn add_tlb(&mut self, tlb: TlbInfo) -> bool {
+        if self.tlb_count >= MAX_TLBS {
+            return false;
+        }
+        self.tlbs[self.tlb_count] = tlb;
+        self.tlb_count += 1;
+        true
+    }
+
+    /// Add caches and TLBs described by leaf 2. Caches are skipped if
+    /// leaf 4 is available and is going to be used instead.
+    fn add_leaf2(&mut self, leaf4_available: bool) {
+        let leaf2 = Tlb::get();
+        let use_leaf4 = leaf4_available || leaf2.descriptors()
+            .any(|d| d == Leaf2Descriptor::UseLeaf4);
+
+        for d in leaf2.descriptors() {
+            match d {
+                Leaf2Descriptor::Cache(c)       => {
+                    if !use_leaf4 {
+                        self.add_cache(c);
+                    }
+                },
+                Leaf2Descriptor::Tlb(t)         => { self.add_tlb(t); },
+                Leaf2Descriptor::Prefetch(p)    => self.prefetch = Some(p),
+                Leaf2Descriptor::UseLeaf4       => (),
+            }
+        }
+    }
+
+    fn add_deterministic(&mut self, leaf: u32) {
+        for subleaf in 0..MAX_CACHES as u32 {
+            let info = Info::get_by_code_ecx(leaf, subleaf);
+            match CacheInfo::from_deterministic(info) {
+                Some(c) => { self.add_cache(c); },
+                None    => break,
+            }
+        }
+    }
+
+    /// Add TLBs described by AMD leaves and caches too if requested.
+    fn add_amd_leaves(&mut self, max_ext: u32, caches: bool) {
+        use self::CacheType::*;
+
+        if max_ext >= 0x8000_0005 {
+            let i = Info::get_by_code(0x8000_0005);
+            let l1_assoc = |a: u32| match a & 0xFF {
+                0xFF    => FULLY_ASSOCIATIVE,
+                a       => a as u16,
+            };
+
+            for &(reg, pages) in [(i.ebx, P4K), (i.eax, P2M | P4M)].iter() {
+                self.add_tlb(TlbInfo::new(1, Data, pages,
+                        ((reg >> 16) & 0xFF) as u16, l1_assoc(reg >> 24)));
+                self.add_tlb(TlbInfo::new(1, Instruction, pages,
+                        (reg & 0xFF) as u16, l1_assoc(reg >> 8)));
+            }
+
+            if caches {
+                for &(reg, kind) in [(i.ecx, Data), (i.edx, Instruction)]
+                        .iter() {
+                    self.add_cache(CacheInfo::from_size(1, kind, reg >> 24,
+                            l1_assoc(reg >> 16), (reg & 0xFF) as u16));
+                }
+            }
+        }
+
+        if max_ext >= 0x8000_0006 {
+            let i = Info::get_by_code(0x8000_0006);
+            self.add_amd_l2_tlbs(i.ebx, P4K);
+            self.add_amd_l2_tlbs(i.eax, P2M | P4M);
+
+            if caches {
+                let l2_ways = amd_l2_assoc(i.ecx >> 12);
+                if l2_ways != 0 {
+                    self.add_cache(CacheInfo::from_size(2, Unified,
+                            i.ecx >> 16, l2_ways, (i.ecx & 0xFF) as u16));
+                }
+                let l3_ways = amd_l2_assoc(i.edx >> 12);
+                if l3_ways != 0 {
+                    self.add_cache(CacheInfo::from_size(3, Unified,
+                            (i.edx >> 18) * 512, l3_ways,
+                            (i.edx & 0xFF) as u16));
+                }
+            }
+        }
+
+        if max_ext >= 0x8000_0019 {
+            let i = Info::get_by_code(0x8000_0019);
+            let d_assoc = amd_l2_assoc(i.eax >> 28);
+            let i_assoc = amd_l2_assoc(i.eax >> 12);
+            if d_assoc != 0 {
+                self.add_tlb(TlbInfo::new(1, Data, P1G,
+                        ((i.eax >> 16) & 0xFFF) as u16, d_assoc));
+            }
+            if i_assoc != 0 {
+                self.add_tlb(TlbInfo::new(1, Instruction, P1G,
+                        (i.eax & 0xFFF) as u16, i_assoc));
+            }
+            self.add_amd_l2_tlbs(i.ebx, P1G);
+        }
+    }
+
+    /// Add L2 data and instruction TLBs described in the format of
+    /// leaves 0x8000_0006 and 0x8000_0019. TLBs that are disabled are
+    /// skipped.
+    fn add_amd_l2_tlbs(&mut self, reg: u32, pages: u8) {
+        let d_assoc = amd_l2_assoc(reg >> 28);
+        let i_assoc = amd_l2_assoc(reg >> 12);
+
+        if d_assoc != 0 {
+            self.add_tlb(TlbInfo::new(2, CacheType::Data, pages,
+                    ((reg >> 16) & 0xFFF) as u16, d_assoc));
+        }
+        if i_assoc != 0 {
+            self.add_tlb(TlbInfo::new(2, CacheType::Instruction, pages,
+                    (reg & 0xFFF) as u16, i_assoc));
+        }
+    }
+
+    /// Order caches and TLBs by level.
+    fn sort(&mut self) {
+        let count = self.cache_count;
+        self.caches[..count].sort_unstable_by_key(|c| c.level);
+        let count = self.tlb_count;
+        self.tlbs[..count].sort_unstable_by_key(|t| t.level);
+    }
+
+    /// Caches ordered by level.
+    pub fn caches(&self) -> &[CacheInfo] {
+        &self.caches[..self.cache_count]
+    }
+
+    /// TLBs ordered by level.
+    pub fn tlbs(&self) -> &[TlbInfo] {
+        &self.tlbs[..self.tlb_count]
+    }
+
+    /// Iterator over caches ordered by level.
+    pub fn iter(&self) -> slice::Iter<'_, CacheInfo> {
+        self.caches().iter()
+    }
+
+    /// Hardware prefetch stride in bytes if reported by leaf 2.
+    pub fn prefetch(&self) -> Option<u16> {
+        self.prefetch
+    }
+
+    /// Cache of given level that holds data: either data or unified one.
+    pub fn data_cache(&self, level: u8) -> Option<&CacheInfo> {
+        self.iter()
+            .find(|c| c.level == level && c.kind != CacheType::Instruction)
+    }
+
+    /// Last level cache that holds data.
+    pub fn last_level_cache(&self) -> Option<&CacheInfo> {
+        self.iter().rev().find(|c| c.kind != CacheType::Instruction)
+    }
+
+    /// Smallest line size of data caches. None if no data caches are
+    /// known.
+    pub fn line_size(&self) -> Option<u16> {
+        self.iter()
+            .filter(|c| c.kind != CacheType::Instruction)
+            .map(|c| c.line_size)
+            .min()
+    }
+
+    /// Count of page colors of the last level cache for pages of given
+    /// size. One if no caches are known.
+    pub fn colors(&self, page_size: u64) -> u64 {
+        self.last_level_cache().map(|c| c.colors(page_size)).unwrap_or(1)
+    }
+}
+
+impl<'a> IntoIterator for &'a CacheHierarchy {
+
+    type Item = &'a CacheInfo;
+    type IntoIter = slice::Iter<'a, CacheInfo>;
+
+    fn into_iter(self) -> slice::Iter<'a, CacheInfo> {
+        self.iter()
+    }
+}
+
+/// Decode associativity in the format of leaves 0x8000_0006 and
+/// 0x8000_0019. Zero means disabled.
+fn amd_l2_assoc(raw: u32) -> u16 {
+    match raw & 0xF {
+        0x1     => 1,
+        0x2     => 2,
+        0x3     => 3,
+        0x4     => 4,
+        0x5     => 6,
+        0x6     => 8,
+        0x8     => 16,
+        0xA     => 32,
+        0xB     => 48,
+        0xC     => 64,
+        0xD     => 96,
+        0xE     => 128,
+        0xF     => FULLY_ASSOCIATIVE,
+        _       => 0,
+    }
+}
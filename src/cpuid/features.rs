@@ -0,0 +1,455 @@
+use super::Info;
+
+/// Count of CPUID registers that hold feature flags.
+pub const FEATURE_WORD_COUNT: usize = 12;
+
+/// CPUID register that holds the feature flag.
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FeatureWord {
+    Leaf1Ecx        = 0,
+    Leaf1Edx        = 1,
+    Leaf7S0Ebx      = 2,
+    Leaf7S0Ecx      = 3,
+    Leaf7S0Edx      = 4,
+    Leaf7S1Eax      = 5,
+    Leaf7S1Edx      = 6,
+    Leaf7S2Edx      = 7,
+    Ext1Ecx         = 8,
+    Ext1Edx         = 9,
+    Ext7Edx         = 10,
+    Ext8Ebx         = 11,
+}
+
+/// Set of processor features reported by CPUID leaves 1, 7 (subleaves
+/// 0 to 2), 0x8000_0001, 0x8000_0007 and 0x8000_0008.
+#[derive(PartialEq, Clone, Copy)]
+pub struct CpuFeatures {
+    words   : [u32; FEATURE_WORD_COUNT],
+}
+
+/// Iterator over features present in the set.
+pub struct CpuFeaturesIter {
+    features    : CpuFeatures,
+    index       : usize,
+}
+
+macro_rules! cpu_features {
+    ($($feature:ident = $word:ident [$bit:expr], $name:expr;)*) => {
+
+        /// Processor feature flag. Names follow the ones used by Linux
+        /// in /proc/cpuinfo.
+        #[derive(PartialEq, Clone, Copy, Debug)]
+        pub enum CpuFeature {
+            $($feature,)*
+        }
+
+        impl CpuFeature {
+
+            /// All known features.
+            pub const ALL: &'static [CpuFeature] = &[
+                $(CpuFeature::$feature,)*
+            ];
+
+            /// Short lowercase name of the feature.
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $(CpuFeature::$feature => $name,)*
+                }
+            }
+
+            /// CPUID register and the bit that hold the feature flag.
+            pub fn location(&self) -> (FeatureWord, u8) {
+                match *self {
+                    $(CpuFeature::$feature => (FeatureWord::$word, $bit),)*
+                }
+            }
+        }
+    };
+}
+
+cpu_features! {
+    Sse3            = Leaf1Ecx[0],      "sse3";
+    Pclmulqdq       = Leaf1Ecx[1],      "pclmulqdq";
+    Dtes64          = Leaf1Ecx[2],      "dtes64";
+    Monitor         = Leaf1Ecx[3],      "monitor";
+    DsCpl           = Leaf1Ecx[4],      "ds_cpl";
+    Vmx             = Leaf1Ecx[5],      "vmx";
+    Smx             = Leaf1Ecx[6],      "smx";
+    Est             = Leaf1Ecx[7],      "est";
+    Tm2             = Leaf1Ecx[8],      "tm2";
+    Ssse3           = Leaf1Ecx[9],      "ssse3";
+    CnxtId          = Leaf1Ecx[10],     "cnxt_id";
+    Sdbg            = Leaf1Ecx[11],     "sdbg";
+    Fma             = Leaf1Ecx[12],     "fma";
+    Cx16            = Leaf1Ecx[13],     "cx16";
+    Xtpr            = Leaf1Ecx[14],     "xtpr";
+    Pdcm            = Leaf1Ecx[15],     "pdcm";
+    Pcid            = Leaf1Ecx[17],     "pcid";
+    Dca             = Leaf1Ecx[18],     "dca";
+    Sse41           = Leaf1Ecx[19],     "sse4_1";
+    Sse42           = Leaf1Ecx[20],     "sse4_2";
+    X2Apic          = Leaf1Ecx[21],     "x2apic";
+    Movbe           = Leaf1Ecx[22],     "movbe";
+    Popcnt          = Leaf1Ecx[23],     "popcnt";
+    TscDeadline     = Leaf1Ecx[24],     "tsc_deadline_timer";
+    Aes             = Leaf1Ecx[25],     "aes";
+    Xsave           = Leaf1Ecx[26],     "xsave";
+    Osxsave         = Leaf1Ecx[27],     "osxsave";
+    Avx             = Leaf1Ecx[28],     "avx";
+    F16c            = Leaf1Ecx[29],     "f16c";
+    Rdrand          = Leaf1Ecx[30],     "rdrand";
+    Hypervisor      = Leaf1Ecx[31],     "hypervisor";
+
+    Fpu             = Leaf1Edx[0],      "fpu";
+    Vme             = Leaf1Edx[1],      "vme";
+    De              = Leaf1Edx[2],      "de";
+    Pse             = Leaf1Edx[3],      "pse";
+    Tsc             = Leaf1Edx[4],      "tsc";
+    Msr             = Leaf1Edx[5],      "msr";
+    Pae             = Leaf1Edx[6],      "pae";
+    Mce             = Leaf1Edx[7],      "mce";
+    Cx8             = Leaf1Edx[8],      "cx8";
+    Apic            = Leaf1Edx[9],      "apic";
+    Sep             = Leaf1Edx[11],     "sep";
+    Mtrr            = Leaf1Edx[12],     "mtrr";
+    Pge             = Leaf1Edx[13],     "pge";
+    Mca             = Leaf1Edx[14],     "mca";
+    Cmov            = Leaf1Edx[15],     "cmov";
+    Pat             = Leaf1Edx[16],     "pat";
+    Pse36           = Leaf1Edx[17],     "pse36";
+    Psn             = Leaf1Edx[18],     "pn";
+    Clflush         = Leaf1Edx[19],     "clflush";
+    Ds              = Leaf1Edx[21],     "dts";
+    Acpi            = Leaf1Edx[22],     "acpi";
+    Mmx             = Leaf1Edx[23],     "mmx";
+    Fxsr            = Leaf1Edx[24],     "fxsr";
+    Sse             = Leaf1Edx[25],     "sse";
+    Sse2            = Leaf1Edx[26],     "sse2";
+    SelfSnoop       = Leaf1Edx[27],     "ss";
+    Htt             = Leaf1Edx[28],     "ht";
+    Tm              = Leaf1Edx[29],     "tm";
+    Pbe             = Leaf1Edx[31],     "pbe";
+
+    Fsgsbase        = Leaf7S0Ebx[0],    "fsgsbase";
+    TscAdjust       = Leaf7S0Ebx[1],    "tsc_adjust";
+    Sgx             = Leaf7S0Ebx[2],    "sgx";
+    Bmi1            = Leaf7S0Ebx[3],    "bmi1";
+    Hle             = Leaf7S0Ebx[4],    "hle";
+    Avx2            = Leaf7S0Ebx[5],    "avx2";
+    FdpExcptnOnly   = Leaf7S0Ebx[6],    "fdp_excptn_only";
+    Smep            = Leaf7S0Ebx[7],    "smep";
+    Bmi2            = Leaf7S0Ebx[8],    "bmi2";
+    Erms            = Leaf7S0Ebx[9],    "erms";
+    Invpcid         = Leaf7S0Ebx[10],   "invpcid";
+    Rtm             = Leaf7S0Ebx[11],   "rtm";
+    Rdt             = Leaf7S0Ebx[12],   "cqm";
+    ZeroFcsFds      = Leaf7S0Ebx[13],   "zero_fcs_fds";
+    Mpx             = Leaf7S0Ebx[14],   "mpx";
+    Rdta            = Leaf7S0Ebx[15],   "rdt_a";
+    Avx512f         = Leaf7S0Ebx[16],   "avx512f";
+    Avx512dq        = Leaf7S0Ebx[17],   "avx512dq";
+    Rdseed          = Leaf7S0Ebx[18],   "rdseed";
+    Adx             = Leaf7S0Ebx[19],   "adx";
+    Smap            = Leaf7S0Ebx[20],   "smap";
+    Avx512ifma      = Leaf7S0Ebx[21],   "avx512ifma";
+    Clflushopt      = Leaf7S0Ebx[23],   "clflushopt";
+    Clwb            = Leaf7S0Ebx[24],   "clwb";
+    IntelPt         = Leaf7S0Ebx[25],   "intel_pt";
+    Avx512pf        = Leaf7S0Ebx[26],   "avx512pf";
+    Avx512er        = Leaf7S0Ebx[27],   "avx512er";
+    Avx512cd        = Leaf7S0Ebx[28],   "avx512cd";
+    Sha             = Leaf7S0Ebx[29],   "sha_ni";
+    Avx512bw        = Leaf7S0Ebx[30],   "avx512bw";
+    Avx512vl        = Leaf7S0Ebx[31],   "avx512vl";
+
+    Prefetchwt1     = Leaf7S0Ecx[0],    "prefetchwt1";
+    Avx512vbmi      = Leaf7S0Ecx[1],    "avx512vbmi";
+    Umip            = Leaf7S0Ecx[2],    "umip";
+    Pku             = Leaf7S0Ecx[3],    "pku";
+    Ospke           = Leaf7S0Ecx[4],    "ospke";
+    Waitpkg         = Leaf7S0Ecx[5],    "waitpkg";
+    Avx512vbmi2     = Leaf7S0Ecx[6],    "avx512_vbmi2";
+    CetSs           = Leaf7S0Ecx[7],    "shstk";
+    Gfni            = Leaf7S0Ecx[8],    "gfni";
+    Vaes            = Leaf7S0Ecx[9],    "vaes";
+    Vpclmulqdq      = Leaf7S0Ecx[10],   "vpclmulqdq";
+    Avx512vnni      = Leaf7S0Ecx[11],   "avx512_vnni";
+    Avx512bitalg    = Leaf7S0Ecx[12],   "avx512_bitalg";
+    Tme             = Leaf7S0Ecx[13],   "tme";
+    Avx512vpopcntdq = Leaf7S0Ecx[14],   "avx512_vpopcntdq";
+    La57            = Leaf7S0Ecx[16],   "la57";
+    Rdpid           = Leaf7S0Ecx[22],   "rdpid";
+    KeyLocker       = Leaf7S0Ecx[23],   "kl";
+    BusLockDetect   = Leaf7S0Ecx[24],   "bus_lock_detect";
+    Cldemote        = Leaf7S0Ecx[25],   "cldemote";
+    Movdiri         = Leaf7S0Ecx[27],   "movdiri";
+    Movdir64b       = Leaf7S0Ecx[28],   "movdir64b";
+    Enqcmd          = Leaf7S0Ecx[29],   "enqcmd";
+    SgxLc           = Leaf7S0Ecx[30],   "sgx_lc";
+    Pks             = Leaf7S0Ecx[31],   "pks";
+
+    Avx5124vnniw    = Leaf7S0Edx[2],    "avx512_4vnniw";
+    Avx5124fmaps    = Leaf7S0Edx[3],    "avx512_4fmaps";
+    Fsrm            = Leaf7S0Edx[4],    "fsrm";
+    Uintr           = Leaf7S0Edx[5],    "uintr";
+    Avx512vp2intersect = Leaf7S0Edx[8], "avx512_vp2intersect";
+    SrbdsCtrl       = Leaf7S0Edx[9],    "srbds_ctrl";
+    MdClear         = Leaf7S0Edx[10],   "md_clear";
+    Serialize       = Leaf7S0Edx[14],   "serialize";
+    Hybrid          = Leaf7S0Edx[15],   "hybrid_cpu";
+    Tsxldtrk        = Leaf7S0Edx[16],   "tsxldtrk";
+    Pconfig         = Leaf7S0Edx[18],   "pconfig";
+    ArchLbr         = Leaf7S0Edx[19],   "arch_lbr";
+    CetIbt          = Leaf7S0Edx[20],   "ibt";
+    AmxBf16         = Leaf7S0Edx[22],   "amx_bf16";
+    Avx512fp16      = Leaf7S0Edx[23],   "avx512_fp16";
+    AmxTile         = Leaf7S0Edx[24],   "amx_tile";
+    AmxInt8         = Leaf7S0Edx[25],   "amx_int8";
+    SpecCtrl        = Leaf7S0Edx[26],   "spec_ctrl";
+    Stibp           = Leaf7S0Edx[27],   "intel_stibp";
+    L1dFlush        = Leaf7S0Edx[28],   "flush_l1d";
+    ArchCapabilities = Leaf7S0Edx[29],  "arch_capabilities";
+    CoreCapabilities = Leaf7S0Edx[30],  "core_capabilities";
+    Ssbd            = Leaf7S0Edx[31],   "spec_ctrl_ssbd";
+
+    Sha512          = Leaf7S1Eax[0],    "sha512";
+    Sm3             = Leaf7S1Eax[1],    "sm3";
+    Sm4             = Leaf7S1Eax[2],    "sm4";
+    AvxVnni         = Leaf7S1Eax[4],    "avx_vnni";
+    Avx512bf16      = Leaf7S1Eax[5],    "avx512_bf16";
+    Lass            = Leaf7S1Eax[6],    "lass";
+    Cmpccxadd       = Leaf7S1Eax[7],    "cmpccxadd";
+    Fzrm            = Leaf7S1Eax[10],   "fzrm";
+    Fsrs            = Leaf7S1Eax[11],   "fsrs";
+    Fsrc            = Leaf7S1Eax[12],   "fsrc";
+    Fred            = Leaf7S1Eax[17],   "fred";
+    Lkgs            = Leaf7S1Eax[18],   "lkgs";
+    Wrmsrns         = Leaf7S1Eax[19],   "wrmsrns";
+    AmxFp16         = Leaf7S1Eax[21],   "amx_fp16";
+    Hreset          = Leaf7S1Eax[22],   "hreset";
+    AvxIfma         = Leaf7S1Eax[23],   "avx_ifma";
+    Lam             = Leaf7S1Eax[26],   "lam";
+
+    AvxVnniInt8     = Leaf7S1Edx[4],    "avx_vnni_int8";
+    AvxNeConvert    = Leaf7S1Edx[5],    "avx_ne_convert";
+    AmxComplex      = Leaf7S1Edx[8],    "amx_complex";
+    AvxVnniInt16    = Leaf7S1Edx[10],   "avx_vnni_int16";
+    Prefetchi       = Leaf7S1Edx[14],   "prefetchi";
+    CetSss          = Leaf7S1Edx[18],   "cet_sss";
+    Avx10           = Leaf7S1Edx[19],   "avx10";
+    ApxF            = Leaf7S1Edx[21],   "apx_f";
+
+    Psfd            = Leaf7S2Edx[0],    "psfd";
+    IpredCtrl       = Leaf7S2Edx[1],    "ipred_ctrl";
+    RrsbaCtrl       = Leaf7S2Edx[2],    "rrsba_ctrl";
+    DdpdU           = Leaf7S2Edx[3],    "ddpd_u";
+    BhiCtrl         = Leaf7S2Edx[4],    "bhi_ctrl";
+    McdtNo          = Leaf7S2Edx[5],    "mcdt_no";
+
+    LahfLm          = Ext1Ecx[0],       "lahf_lm";
+    CmpLegacy       = Ext1Ecx[1],       "cmp_legacy";
+    Svm             = Ext1Ecx[2],       "svm";
+    ExtApic         = Ext1Ecx[3],       "extapic";
+    Cr8Legacy       = Ext1Ecx[4],       "cr8_legacy";
+    Abm             = Ext1Ecx[5],       "abm";
+    Sse4a           = Ext1Ecx[6],       "sse4a";
+    MisalignSse     = Ext1Ecx[7],       "misalignsse";
+    Prefetchw       = Ext1Ecx[8],       "3dnowprefetch";
+    Osvw            = Ext1Ecx[9],       "osvw";
+    Ibs             = Ext1Ecx[10],      "ibs";
+    Xop             = Ext1Ecx[11],      "xop";
+    Skinit          = Ext1Ecx[12],      "skinit";
+    Wdt             = Ext1Ecx[13],      "wdt";
+    Lwp             = Ext1Ecx[15],      "lwp";
+    Fma4            = Ext1Ecx[16],      "fma4";
+    Tce             = Ext1Ecx[17],      "tce";
+    NodeIdMsr       = Ext1Ecx[19],      "nodeid_msr";
+    Tbm             = Ext1Ecx[21],      "tbm";
+    TopoExt         = Ext1Ecx[22],      "topoext";
+    PerfCtrCore     = Ext1Ecx[23],      "perfctr_core";
+    PerfCtrNb       = Ext1Ecx[24],      "perfctr_nb";
+    Dbx             = Ext1Ecx[26],      "bpext";
+    PerfTsc         = Ext1Ecx[27],      "ptsc";
+    PerfCtrLlc      = Ext1Ecx[28],      "perfctr_llc";
+    Mwaitx          = Ext1Ecx[29],      "mwaitx";
+
+    Syscall         = Ext1Edx[11],      "syscall";
+    Mp              = Ext1Edx[19],      "mp";
+    Nx              = Ext1Edx[20],      "nx";
+    MmxExt          = Ext1Edx[22],      "mmxext";
+    FxsrOpt         = Ext1Edx[25],      "fxsr_opt";
+    Page1Gb         = Ext1Edx[26],      "pdpe1gb";
+    Rdtscp          = Ext1Edx[27],      "rdtscp";
+    LongMode        = Ext1Edx[29],      "lm";
+    ThreeDNowExt    = Ext1Edx[30],      "3dnowext";
+    ThreeDNow       = Ext1Edx[31],      "3dnow";
+
+    TempSensor      = Ext7Edx[0],       "ts";
+    FreqId          = Ext7Edx[1],       "fid";
+    VoltId          = Ext7Edx[2],       "vid";
+    ThermTrip       = Ext7Edx[3],       "ttp";
+    HwThermControl  = Ext7Edx[4],       "tm_ctrl";
+    SwThermControl  = Ext7Edx[5],       "stc";
+    Steps100Mhz     = Ext7Edx[6],       "100mhzsteps";
+    HwPstate        = Ext7Edx[7],       "hwpstate";
+    InvariantTsc    = Ext7Edx[8],       "constant_tsc";
+    Cpb             = Ext7Edx[9],       "cpb";
+    EffFreqRo       = Ext7Edx[10],      "eff_freq_ro";
+    ProcFeedback    = Ext7Edx[11],      "proc_feedback";
+    ProcPowerReport = Ext7Edx[12],      "acc_power";
+
+    Clzero          = Ext8Ebx[0],       "clzero";
+    Irperf          = Ext8Ebx[1],       "irperf";
+    XsaveErPtr      = Ext8Ebx[2],       "xsaveerptr";
+    Invlpgb         = Ext8Ebx[3],       "invlpgb";
+    Rdpru           = Ext8Ebx[4],       "rdpru";
+    Mcommit         = Ext8Ebx[8],       "mcommit";
+    Wbnoinvd        = Ext8Ebx[9],       "wbnoinvd";
+    AmdIbpb         = Ext8Ebx[12],      "ibpb";
+    AmdIbrs         = Ext8Ebx[14],      "ibrs";
+    AmdStibp        = Ext8Ebx[15],      "stibp";
+    AmdIbrsAlwaysOn = Ext8Ebx[16],      "ibrs_always_on";
+    AmdStibpAlwaysOn = Ext8Ebx[17],     "stibp_always_on";
+    AmdIbrsPreferred = Ext8Ebx[18],     "ibrs_preferred";
+    AmdIbrsSameMode = Ext8Ebx[19],      "ibrs_same_mode";
+    Ppin            = Ext8Ebx[23],      "amd_ppin";
+    AmdSsbd         = Ext8Ebx[24],      "amd_ssbd";
+    VirtSsbd        = Ext8Ebx[25],      "virt_ssbd";
+    AmdSsbNo        = Ext8Ebx[26],      "amd_ssb_no";
+    Cppc            = Ext8Ebx[27],      "cppc";
+    AmdPsfd         = Ext8Ebx[28],      "amd_psfd";
+    BtcNo           = Ext8Ebx[29],      "btc_no";
+    IbpbRet         = Ext8Ebx[30],      "ibpb_ret";
+}
+
+impl CpuFeature {
+
+    /// Find feature by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().find(|f| f.name() == name).cloned()
+    }
+}
+
+impl CpuFeatures {
+
+    /// Query CPUID for all feature leaves. Leaves and subleaves that are
+    /// not supported by the processor are left zeroed.
+    pub fn get() -> Self {
+        use self::FeatureWord::*;
+
+        let mut f = CpuFeatures { words : [0; FEATURE_WORD_COUNT] };
+
+        let max_basic = Info::get_by_code(0).eax;
+        if max_basic >= 1 {
+            let i = Info::get_by_code(1);
+            f.set_word(Leaf1Ecx, i.ecx);
+            f.set_word(Leaf1Edx, i.edx);
+        }
+        if max_basic >= 7 {
+            let i = Info::get_by_code_ecx(7, 0);
+            let max_subleaf = i.eax;
+            f.set_word(Leaf7S0Ebx, i.ebx);
+            f.set_word(Leaf7S0Ecx, i.ecx);
+            f.set_word(Leaf7S0Edx, i.edx);
+
+            if max_subleaf >= 1 {
+                let i = Info::get_by_code_ecx(7, 1);
+                f.set_word(Leaf7S1Eax, i.eax);
+                f.set_word(Leaf7S1Edx, i.edx);
+            }
+            if max_subleaf >= 2 {
+                let i = Info::get_by_code_ecx(7, 2);
+                f.set_word(Leaf7S2Edx, i.edx);
+            }
+        }
+
+        let max_ext = Info::get_by_code(0x8000_0000).eax;
+        if max_ext >= 0x8000_0001 {
+            let i = Info::get_by_code(0x8000_0001);
+            f.set_word(Ext1Ecx, i.ecx);
+            f.set_word(Ext1Edx, i.edx);
+        }
+        if max_ext >= 0x8000_0007 {
+            f.set_word(Ext7Edx, Info::get_by_code(0x8000_0007).edx);
+        }
+        if max_ext >= 0x8000_0008 {
+            f.set_word(Ext8Ebx, Info::get_by_code(0x8000_0008).ebx);
+        }
+
+        f
+    }
+
+    /// Create feature set from raw register values in the order
+    /// of `FeatureWord` variants.
+    pub fn from_words(words: [u32; FEATURE_WORD_COUNT]) -> Self {
+        CpuFeatures { words }
+    }
+
+    /// Raw register values in the order of `FeatureWord` variants.
+    pub fn words(&self) -> [u32; FEATURE_WORD_COUNT] {
+        self.words
+    }
+
+    /// Raw value of given register.
+    pub fn word(&self, word: FeatureWord) -> u32 {
+        self.words[word as usize]
+    }
+
+    fn set_word(&mut self, word: FeatureWord, val: u32) {
+        self.words[word as usize] = val;
+    }
+
+    /// Whether given feature is present.
+    pub fn has(&self, feature: CpuFeature) -> bool {
+        let (word, bit) = feature.location();
+        self.word(word) & (1 << bit) != 0
+    }
+
+    /// Whether all given features are present.
+    pub fn has_all(&self, features: &[CpuFeature]) -> bool {
+        features.iter().all(|f| self.has(*f))
+    }
+
+    /// Mark given feature as present or absent. Can be used to mask
+    /// features that must not be used.
+    pub fn set(&mut self, feature: CpuFeature, present: bool) {
+        let (word, bit) = feature.location();
+        let w = &mut self.words[word as usize];
+        if present {
+            *w |= 1 << bit;
+        } else {
+            *w &= !(1 << bit);
+        }
+    }
+
+    /// Iterator over present features.
+    pub fn iter(&self) -> CpuFeaturesIter {
+        CpuFeaturesIter {
+            features    : *self,
+            index       : 0,
+        }
+    }
+
+    /// Iterator over names of present features.
+    pub fn names<'a>(&'a self) -> impl Iterator<Item = &'static str> + 'a {
+        self.iter().map(|f| f.name())
+    }
+}
+
+impl Iterator for CpuFeaturesIter {
+
+    type Item = CpuFeature;
+
+    fn next(&mut self) -> Option<CpuFeature> {
+        while self.index < CpuFeature::ALL.len() {
+            let f = CpuFeature::ALL[self.index];
+            self.index += 1;
+
+            if self.features.has(f) {
+                return Some(f);
+            }
+        }
+        None
+    }
+}
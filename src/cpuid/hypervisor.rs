@@ -0,0 +1,573 @@
+use super::*;
+
+/// First leaf of the hypervisor range.
+pub const HYPERVISOR_BASE_LEAF: u32 = 0x4000_0000;
+
+/// Hypervisors may expose several interfaces at bases that are multiples
+/// of this step, e.g. Xen moves its leaves here when it emulates Hyper-V.
+const HYPERVISOR_BASE_STEP: u32 = 0x100;
+
+/// Last base that is probed for hypervisor interfaces.
+const HYPERVISOR_BASE_LAST: u32 = 0x4001_0000;
+
+/// Hyper-V interface signature "Hv#1" reported in EAX of base + 1 leaf.
+const HYPERV_INTERFACE_SIGNATURE: u32 = 0x3123_7648;
+
+/// Hypervisor interface found in the hypervisor CPUID range.
+#[derive(Clone, Copy)]
+pub struct Hypervisor {
+    base        : u32,
+    max_leaf    : u32,
+    signature   : [u8; 12],
+}
+
+/// KVM paravirtual features reported in leaf base + 1.
+#[derive(Clone, Copy)]
+pub struct KvmFeatures {
+    eax     : u32,
+    edx     : u32,
+}
+
+/// Hyper-V features and enlightenment recommendations reported in leaves
+/// base + 3 to base + 5.
+#[derive(Clone, Copy)]
+pub struct HyperVFeatures {
+    features        : Info,
+    recommendations : Info,
+    limits          : Info,
+}
+
+impl Hypervisor {
+
+    /// Whether CPUID reports running under a hypervisor.
+    pub fn present() -> bool {
+        VendorString::get().max_value() >= 1
+                && CpuFeatures::get().has(CpuFeature::Hypervisor)
+    }
+
+    /// Primary hypervisor interface. None if not running under
+    /// a hypervisor.
+    pub fn detect() -> Option<Self> {
+        if !Self::present() {
+            return None;
+        }
+        Self::at(HYPERVISOR_BASE_LEAF)
+    }
+
+    /// Find interface of given hypervisor at any of the bases. Useful when
+    /// the hypervisor emulates another one at the primary base, e.g. KVM
+    /// or Xen with Hyper-V enlightenments enabled.
+    pub fn find(vendor: Vendor) -> Option<Self> {
+        if !Self::present() {
+            return None;
+        }
+
+        let mut base = HYPERVISOR_BASE_LEAF;
+        while base < HYPERVISOR_BASE_LAST {
+            if let Some(h) = Self::at(base) {
+                if h.vendor() == vendor {
+                    return Some(h);
+                }
+            }
+            base += HYPERVISOR_BASE_STEP;
+        }
+        None
+    }
+
+    /// Interface at given base leaf. None if the leaf does not hold
+    /// a valid hypervisor signature.
+    fn at(base: u32) -> Option<Self> {
+        let i = Info::get_by_code(base);
+
+        let mut signature = [0; 12];
+        for (n, reg) in [i.ebx, i.ecx, i.edx].iter().enumerate() {
+            for b in 0..4 {
+                signature[n * 4 + b] = (reg >> (b * 8)) as u8;
+            }
+        }
+
+        // Some hypervisors report zero max leaf meaning base + 1.
+        let max_leaf = if i.eax == 0 { base + 1 } else { i.eax };
+        if max_leaf < base || max_leaf - base >= HYPERVISOR_BASE_STEP
+                || signature.iter().all(|&b| b == 0) {
+            return None;
+        }
+
+        Some(Hypervisor {
+            base,
+            max_leaf,
+            signature,
+        })
+    }
+
+    /// Hypervisor vendor decoded from the signature.
+    pub fn vendor(&self) -> Vendor {
+        Vendor::from_bytes(&self.signature)
+    }
+
+    /// Raw signature bytes from EBX, ECX and EDX.
+    pub fn signature(&self) -> &[u8; 12] {
+        &self.signature
+    }
+
+    /// Base leaf of this interface.
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// Max leaf of this interface.
+    pub fn max_leaf(&self) -> u32 {
+        self.max_leaf
+    }
+
+    /// Leaf with given offset from the base. None if it is beyond the max
+    /// leaf.
+    pub fn leaf(&self, offset: u32) -> Option<Info> {
+        if self.base + offset > self.max_leaf {
+            None
+        } else {
+            Some(Info::get_by_code(self.base + offset))
+        }
+    }
+
+    /// KVM features. None if this is not KVM interface.
+    pub fn kvm_features(&self) -> Option<KvmFeatures> {
+        if self.vendor() != Vendor::Kvm {
+            return None;
+        }
+        let i = self.leaf(1)?;
+        Some(KvmFeatures { eax: i.eax, edx: i.edx })
+    }
+
+    /// Hyper-V features. None if this interface does not implement Hyper-V
+    /// interface, which other hypervisors may emulate too.
+    pub fn hyperv_features(&self) -> Option<HyperVFeatures> {
+        if self.leaf(1)?.eax != HYPERV_INTERFACE_SIGNATURE {
+            return None;
+        }
+
+        let zero = Info { eax: 0, ebx: 0, ecx: 0, edx: 0 };
+        Some(HyperVFeatures {
+            features        : self.leaf(3)?,
+            recommendations : self.leaf(4).unwrap_or(zero),
+            limits          : self.leaf(5).unwrap_or(zero),
+        })
+    }
+
+    /// Xen version as (major, minor). None if this is not Xen interface.
+    pub fn xen_version(&self) -> Option<(u16, u16)> {
+        if self.vendor() != Vendor::Xen {
+            return None;
+        }
+        let eax = self.leaf(1)?.eax;
+        Some(((eax >> 16) as u16, eax as u16))
+    }
+
+    /// TSC frequency in kHz from the timing leaf base + 0x10 that VMware
+    /// and some KVM setups provide. None if not reported.
+    pub fn tsc_khz(&self) -> Option<u32> {
+        match self.leaf(0x10)?.eax {
+            0   => None,
+            khz => Some(khz),
+        }
+    }
+
+    /// Local APIC timer frequency in kHz from the timing leaf base + 0x10.
+    /// None if not reported.
+    pub fn apic_khz(&self) -> Option<u32> {
+        match self.leaf(0x10)?.ebx {
+            0   => None,
+            khz => Some(khz),
+        }
+    }
+}
+
+impl KvmFeatures {
+
+    const CLOCKSOURCE           : u32 = 1 << 0;
+    const NOP_IO_DELAY          : u32 = 1 << 1;
+    const CLOCKSOURCE2          : u32 = 1 << 3;
+    const ASYNC_PF              : u32 = 1 << 4;
+    const STEAL_TIME            : u32 = 1 << 5;
+    const PV_EOI                : u32 = 1 << 6;
+    const PV_UNHALT             : u32 = 1 << 7;
+    const PV_TLB_FLUSH          : u32 = 1 << 9;
+    const PV_SEND_IPI           : u32 = 1 << 11;
+    const POLL_CONTROL          : u32 = 1 << 12;
+    const PV_SCHED_YIELD        : u32 = 1 << 13;
+    const MSI_EXT_DEST_ID       : u32 = 1 << 15;
+    const CLOCKSOURCE_STABLE    : u32 = 1 << 24;
+
+    const HINTS_REALTIME        : u32 = 1 << 0;
+
+    /// Raw feature bits from EAX.
+    pub fn bits(&self) -> u32 {
+        self.eax
+    }
+
+    /// Whether kvmclock is available through the old MSRs 0x11 and 0x12.
+    pub fn clocksource(&self) -> bool {
+        self.eax & Self::CLOCKSOURCE != 0
+    }
+
+    /// Whether kvmclock is available through the new MSRs.
+    pub fn clocksource2(&self) -> bool {
+        self.eax & Self::CLOCKSOURCE2 != 0
+    }
+
+    /// Whether kvmclock does not go backwards across CPUs.
+    pub fn clocksource_stable(&self) -> bool {
+        self.eax & Self::CLOCKSOURCE_STABLE != 0
+    }
+
+    /// Whether port 0x80 delays are not needed.
+    pub fn nop_io_delay(&self) -> bool {
+        self.eax & Self::NOP_IO_DELAY != 0
+    }
+
+    pub fn async_pf(&self) -> bool {
+        self.eax & Self::ASYNC_PF != 0
+    }
+
+    /// Whether steal time reporting is available.
+    pub fn steal_time(&self) -> bool {
+        self.eax & Self::STEAL_TIME != 0
+    }
+
+    /// Whether paravirtual end of interrupt is available.
+    pub fn pv_eoi(&self) -> bool {
+        self.eax & Self::PV_EOI != 0
+    }
+
+    /// Whether halted vCPU can be kicked by hypercall.
+    pub fn pv_unhalt(&self) -> bool {
+        self.eax & Self::PV_UNHALT != 0
+    }
+
+    /// Whether TLB flush of preempted vCPUs can be deferred.
+    pub fn pv_tlb_flush(&self) -> bool {
+        self.eax & Self::PV_TLB_FLUSH != 0
+    }
+
+    /// Whether IPIs can be sent to many vCPUs with one hypercall.
+    pub fn pv_send_ipi(&self) -> bool {
+        self.eax & Self::PV_SEND_IPI != 0
+    }
+
+    pub fn poll_control(&self) -> bool {
+        self.eax & Self::POLL_CONTROL != 0
+    }
+
+    pub fn pv_sched_yield(&self) -> bool {
+        self.eax & Self::PV_SCHED_YIELD != 0
+    }
+
+    /// Whether MSI address can hold extended destination ID.
+    pub fn msi_ext_dest_id(&self) -> bool {
+        self.eax & Self::MSI_EXT_DEST_ID != 0
+    }
+
+    /// Whether vCPUs are never preempted by the host.
+    pub fn hints_realtime(&self) -> bool {
+        self.edx & Self::HINTS_REALTIME != 0
+    }
+}
+
+impl HyperVFeatures {
+
+    const TIME_REF_COUNT        : u32 = 1 << 1;
+    const SYNIC                 : u32 = 1 << 2;
+    const SYNTIMER              : u32 = 1 << 3;
+    const APIC_ACCESS           : u32 = 1 << 4;
+    const HYPERCALL             : u32 = 1 << 5;
+    const VP_INDEX              : u32 = 1 << 6;
+    const REFERENCE_TSC         : u32 = 1 << 9;
+    const FREQUENCY_MSRS        : u32 = 1 << 11;
+    const TSC_INVARIANT         : u32 = 1 << 15;
+
+    const STIMER_DIRECT_MODE    : u32 = 1 << 19;
+
+    const REMOTE_TLB_FLUSH_REC  : u32 = 1 << 2;
+    const APIC_ACCESS_REC       : u32 = 1 << 3;
+    const RELAXED_TIMING_REC    : u32 = 1 << 5;
+    const DEPRECATING_AEOI_REC  : u32 = 1 << 9;
+    const CLUSTER_IPI_REC       : u32 = 1 << 10;
+    const EX_PROC_MASKS_REC     : u32 = 1 << 11;
+
+    /// Raw partition privileges from EAX of base + 3.
+    pub fn privileges(&self) -> u32 {
+        self.features.eax
+    }
+
+    /// Raw recommendations from EAX of base + 4.
+    pub fn recommendations(&self) -> u32 {
+        self.recommendations.eax
+    }
+
+    /// Whether partition reference counter MSR is available.
+    pub fn time_ref_count(&self) -> bool {
+        self.features.eax & Self::TIME_REF_COUNT != 0
+    }
+
+    /// Whether partition reference TSC page is available.
+    pub fn reference_tsc(&self) -> bool {
+        self.features.eax & Self::REFERENCE_TSC != 0
+    }
+
+    /// Whether synthetic interrupt controller is available.
+    pub fn synic(&self) -> bool {
+        self.features.eax & Self::SYNIC != 0
+    }
+
+    /// Whether synthetic timers are available.
+    pub fn synthetic_timers(&self) -> bool {
+        self.features.eax & Self::SYNTIMER != 0
+    }
+
+    /// Whether synthetic timers can deliver interrupts directly without
+    /// SynIC messages.
+    pub fn synthetic_timers_direct(&self) -> bool {
+        self.features.edx & Self::STIMER_DIRECT_MODE != 0
+    }
+
+    /// Whether EOI, ICR and TPR MSRs are available.
+    pub fn apic_msrs(&self) -> bool {
+        self.features.eax & Self::APIC_ACCESS != 0
+    }
+
+    pub fn hypercall(&self) -> bool {
+        self.features.eax & Self::HYPERCALL != 0
+    }
+
+    pub fn vp_index(&self) -> bool {
+        self.features.eax & Self::VP_INDEX != 0
+    }
+
+    /// Whether TSC and APIC frequency MSRs are available.
+    pub fn frequency_msrs(&self) -> bool {
+        self.features.eax & Self::FREQUENCY_MSRS != 0
+    }
+
+    /// Whether TSC is invariant across migrations when enabled.
+    pub fn tsc_invariant(&self) -> bool {
+        self.features.eax & Self::TSC_INVARIANT != 0
+    }
+
+    /// Whether APIC MSRs are recommended over APIC access for EOI, ICR and
+    /// TPR.
+    pub fn apic_msrs_recommended(&self) -> bool {
+        self.recommendations.eax & Self::APIC_ACCESS_REC != 0
+    }
+
+    /// Whether remote TLB flush hypercall is recommended over IPIs.
+    pub fn remote_tlb_flush_recommended(&self) -> bool {
+        self.recommendations.eax & Self::REMOTE_TLB_FLUSH_REC != 0
+    }
+
+    /// Whether watchdog timeouts should be relaxed.
+    pub fn relaxed_timing_recommended(&self) -> bool {
+        self.recommendations.eax & Self::RELAXED_TIMING_REC != 0
+    }
+
+    /// Whether auto EOI of SynIC should not be used.
+    pub fn deprecating_aeoi_recommended(&self) -> bool {
+        self.recommendations.eax & Self::DEPRECATING_AEOI_REC != 0
+    }
+
+    /// Whether IPI hypercall is recommended over IPIs sent by APIC.
+    pub fn cluster_ipi_recommended(&self) -> bool {
+        self.recommendations.eax & Self::CLUSTER_IPI_REC != 0
+    }
+
+    /// Whether extended processor masks in hypercalls are recommended.
+    pub fn ex_processor_masks_recommended(&self) -> bool {
+        self.recommendations.eax & Self::EX_PROC_MASKS_REC != 0
+    }
+
+    /// Recommended count of spinlock retries before notifying hypervisor.
+    /// All ones mean never notify.
+    pub fn spinlock_retries(&self) -> u32 {
+        self.recommendations.ebx
+    }
+
+    /// Max count of virtual processors. Zero if not reported.
+    pub fn max_virtual_processors(&self) -> u32 {
+        self.limits.eax
+    }
+
+    /// Max count of logical processors. Zero if not reported.
+    pub fn max_logical_processors(&self) -> u32 {
+        self.limits.ebx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    /// Mock of a processor that reports running under a hypervisor.
+    fn guest() -> Mock {
+        let mock = Mock::new();
+        mock.set_cpuid(0, 0, Info { eax: 1, ebx: 0, ecx: 0, edx: 0 });
+        mock.set_cpuid(1, 0, Info { eax: 0, ebx: 0, ecx: 1 << 31, edx: 0 });
+        mock
+    }
+
+    /// Set base leaf of a hypervisor interface with given signature.
+    fn set_base(mock: &Mock, base: u32, max_leaf: u32, signature: &[u8; 12]) {
+        let reg = |n: usize| {
+            let b = &signature[n * 4..n * 4 + 4];
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        };
+        mock.set_cpuid(base, 0, Info {
+            eax: max_leaf, ebx: reg(0), ecx: reg(1), edx: reg(2)
+        });
+    }
+
+    #[test]
+    fn base_leaf_validation() {
+        let mock = guest();
+        let base = HYPERVISOR_BASE_LEAF;
+
+        let at = |max_leaf| {
+            set_base(&mock, base, max_leaf, b"KVMKVMKVM\0\0\0");
+            with_backend(&mock, || Hypervisor::at(base))
+        };
+
+        // Zero max leaf means base + 1.
+        assert_eq!(at(0).unwrap().max_leaf(), base + 1);
+        assert_eq!(at(base + 0xFF).unwrap().max_leaf(), base + 0xFF);
+        // Max leaf must stay in the range of this base.
+        assert!(at(base + 0x100).is_none());
+        assert!(at(base - 1).is_none());
+
+        set_base(&mock, base, base + 1, &[0; 12]);
+        assert!(with_backend(&mock, || Hypervisor::at(base)).is_none());
+    }
+
+    #[test]
+    fn vendor_signatures() {
+        let mock = guest();
+        let base = HYPERVISOR_BASE_LEAF;
+
+        let signatures: [(&[u8; 12], Vendor); 3] = [
+            (b"KVMKVMKVM\0\0\0", Vendor::Kvm),
+            (b"Microsoft Hv", Vendor::HyperV),
+            (b"XenVMMXenVMM", Vendor::Xen),
+        ];
+        for &(signature, vendor) in signatures.iter() {
+            set_base(&mock, base, base + 1, signature);
+            let h = with_backend(&mock, Hypervisor::detect).unwrap();
+            assert!(h.vendor() == vendor);
+            assert_eq!(h.signature(), signature);
+        }
+    }
+
+    #[test]
+    fn not_detected_without_hypervisor_bit() {
+        let mock = Mock::new();
+        mock.set_cpuid(0, 0, Info { eax: 1, ebx: 0, ecx: 0, edx: 0 });
+        set_base(&mock, HYPERVISOR_BASE_LEAF, 0, b"KVMKVMKVM\0\0\0");
+
+        with_backend(&mock, || {
+            assert!(!Hypervisor::present());
+            assert!(Hypervisor::detect().is_none());
+            assert!(Hypervisor::find(Vendor::Kvm).is_none());
+        });
+    }
+
+    #[test]
+    fn kvm_feature_bits() {
+        let mock = guest();
+        let base = HYPERVISOR_BASE_LEAF;
+        set_base(&mock, base, base + 1, b"KVMKVMKVM\0\0\0");
+        mock.set_cpuid(base + 1, 0, Info {
+            eax: (1 << 3) | (1 << 6) | (1 << 15) | (1 << 24),
+            ebx: 0, ecx: 0, edx: 1 << 0,
+        });
+
+        let f = with_backend(&mock, || {
+            Hypervisor::detect().unwrap().kvm_features().unwrap()
+        });
+        assert!(f.clocksource2() && f.pv_eoi() && f.msi_ext_dest_id()
+                && f.clocksource_stable() && f.hints_realtime());
+        assert!(!f.clocksource() && !f.nop_io_delay() && !f.steal_time()
+                && !f.pv_unhalt() && !f.pv_send_ipi());
+    }
+
+    #[test]
+    fn hyperv_found_at_second_base() {
+        let mock = guest();
+        let kvm = HYPERVISOR_BASE_LEAF;
+        let hv = HYPERVISOR_BASE_LEAF + HYPERVISOR_BASE_STEP;
+
+        // KVM with Hyper-V enlightenments. Interface between the bases is
+        // not probed.
+        set_base(&mock, kvm, kvm + 1, b"KVMKVMKVM\0\0\0");
+        set_base(&mock, kvm + 0x80, kvm + 0x81, b"XenVMMXenVMM");
+        set_base(&mock, hv, hv + 5, b"Microsoft Hv");
+        mock.set_cpuid(hv + 1, 0, Info {
+            eax: HYPERV_INTERFACE_SIGNATURE, ebx: 0, ecx: 0, edx: 0
+        });
+        mock.set_cpuid(hv + 3, 0, Info {
+            eax: (1 << 1) | (1 << 4) | (1 << 9), ebx: 0, ecx: 0,
+            edx: 1 << 19,
+        });
+        mock.set_cpuid(hv + 4, 0, Info {
+            eax: (1 << 3) | (1 << 11), ebx: 0xFFFF_FFFF, ecx: 0, edx: 0
+        });
+        mock.set_cpuid(hv + 5, 0, Info { eax: 240, ebx: 512, ecx: 0, edx: 0 });
+
+        with_backend(&mock, || {
+            assert!(Hypervisor::detect().unwrap().vendor() == Vendor::Kvm);
+            assert!(Hypervisor::find(Vendor::Xen).is_none());
+
+            let h = Hypervisor::find(Vendor::HyperV).unwrap();
+            assert_eq!(h.base(), hv);
+            assert_eq!(h.max_leaf(), hv + 5);
+            assert!(h.kvm_features().is_none());
+
+            let f = h.hyperv_features().unwrap();
+            assert!(f.time_ref_count() && f.apic_msrs() && f.reference_tsc()
+                    && f.synthetic_timers_direct());
+            assert!(!f.synic() && !f.hypercall() && !f.frequency_msrs());
+            assert!(f.apic_msrs_recommended()
+                    && f.ex_processor_masks_recommended());
+            assert!(!f.remote_tlb_flush_recommended());
+            assert_eq!(f.spinlock_retries(), 0xFFFF_FFFF);
+            assert_eq!(f.max_virtual_processors(), 240);
+            assert_eq!(f.max_logical_processors(), 512);
+        });
+    }
+
+    #[test]
+    fn replay_kvm() {
+        let text = include_str!("testdata/kvm_sapphire_rapids.txt");
+        let snapshot = CpuidSnapshot::parse_text(text).unwrap();
+        let mock = Mock::new();
+        let replay = CpuidReplay::new(snapshot, &mock);
+
+        with_backend(&replay, || {
+            let h = Hypervisor::detect().unwrap();
+            assert!(h.vendor() == Vendor::Kvm);
+            assert_eq!(h.base(), HYPERVISOR_BASE_LEAF);
+            assert_eq!(h.max_leaf(), 0x4000_0001);
+            assert!(h.hyperv_features().is_none());
+            assert!(h.tsc_khz().is_none());
+            assert!(Hypervisor::find(Vendor::HyperV).is_none());
+
+            // EAX of leaf 0x40000001 is 0x01007EFB.
+            let f = h.kvm_features().unwrap();
+            assert_eq!(f.bits(), 0x0100_7EFB);
+            assert!(f.clocksource() && f.nop_io_delay() && f.clocksource2()
+                    && f.async_pf() && f.steal_time() && f.pv_eoi()
+                    && f.pv_unhalt() && f.pv_tlb_flush() && f.pv_send_ipi()
+                    && f.poll_control() && f.pv_sched_yield()
+                    && f.clocksource_stable());
+            assert!(!f.msi_ext_dest_id());
+            assert!(!f.hints_realtime());
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use super::*;
+use core::str;
+
+/// Length of processor brand string in bytes.
+pub const BRAND_STRING_LEN: usize = 48;
+
+/// Processor or hypervisor vendor decoded from CPUID vendor string.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Vendor {
+    Intel,
+    Amd,
+    Hygon,
+    Zhaoxin,
+    Centaur,
+    Kvm,
+    HyperV,
+    Xen,
+    VMware,
+    QemuTcg,
+    Bhyve,
+    Parallels,
+    VirtualBox,
+    Unknown,
+}
+
+/// Processor identification: vendor, brand string and signature.
+#[derive(Clone, Copy)]
+pub struct CpuIdentity {
+    vendor      : [u8; 12],
+    features    : Features,
+    brand       : [u8; BRAND_STRING_LEN],
+}
+
+impl Vendor {
+
+    const SIGNATURES: &'static [(&'static [u8; 12], Vendor)] = &[
+        (b"GenuineIntel", Vendor::Intel),
+        (b"AuthenticAMD", Vendor::Amd),
+        (b"AMDisbetter!", Vendor::Amd),
+        (b"HygonGenuine", Vendor::Hygon),
+        (b"  Shanghai  ", Vendor::Zhaoxin),
+        (b"CentaurHauls", Vendor::Centaur),
+        (b"KVMKVMKVM\0\0\0", Vendor::Kvm),
+        (b"Microsoft Hv", Vendor::HyperV),
+        (b"XenVMMXenVMM", Vendor::Xen),
+        (b"VMwareVMware", Vendor::VMware),
+        (b"TCGTCGTCGTCG", Vendor::QemuTcg),
+        (b"bhyve bhyve ", Vendor::Bhyve),
+        (b" lrpepyh  vr", Vendor::Parallels),
+        (b"VBoxVBoxVBox", Vendor::VirtualBox),
+    ];
+
+    /// Decode vendor from the 12-byte vendor string.
+    pub fn from_bytes(bytes: &[u8; 12]) -> Self {
+        Self::SIGNATURES.iter()
+            .find(|&&(sig, _)| sig == bytes)
+            .map(|&(_, v)| v)
+            .unwrap_or(Vendor::Unknown)
+    }
+
+    /// Human readable vendor name.
+    pub fn name(&self) -> &'static str {
+        use self::Vendor::*;
+        match *self {
+            Intel       => "Intel",
+            Amd         => "AMD",
+            Hygon       => "Hygon",
+            Zhaoxin     => "Zhaoxin",
+            Centaur     => "Centaur",
+            Kvm         => "KVM",
+            HyperV      => "Microsoft Hyper-V",
+            Xen         => "Xen",
+            VMware      => "VMware",
+            QemuTcg     => "QEMU TCG",
+            Bhyve       => "bhyve",
+            Parallels   => "Parallels",
+            VirtualBox  => "VirtualBox",
+            Unknown     => "Unknown",
+        }
+    }
+
+    /// Whether this is a hypervisor rather than a hardware vendor.
+    pub fn is_hypervisor(&self) -> bool {
+        use self::Vendor::*;
+        !matches!(*self, Intel | Amd | Hygon | Zhaoxin | Centaur | Unknown)
+    }
+
+    /// Whether extended model ID forms the upper bits of model for
+    /// given family ID. AMD and Hygon use it with family 0xF only,
+    /// Zhaoxin and Centaur with all families from 0x6 and other vendors
+    /// with families 0x6 and 0xF.
+    fn uses_extended_model(&self, family: u8) -> bool {
+        use self::Vendor::*;
+
+        match *self {
+            Amd | Hygon         => family == 0xF,
+            Zhaoxin | Centaur   => family >= 0x6,
+            _                   => family == 0x6 || family == 0xF,
+        }
+    }
+}
+
+impl CpuIdentity {
+
+    /// Query CPUID for vendor, signature and brand string. Brand string is
+    /// empty if the processor does not report it.
+    pub fn get() -> Self {
+        let vendor = VendorString::get();
+        let features = if vendor.max_value() >= 1 {
+            Features::get()
+        } else {
+            Features::from(Info { eax: 0, ebx: 0, ecx: 0, edx: 0 })
+        };
+
+        let mut brand = [0; BRAND_STRING_LEN];
+        if IntelExtended::get().max_value() >= 0x8000_0004 {
+            brand[0..16].copy_from_slice(&IntelBrandString::get().bytes());
+            brand[16..32].copy_from_slice(
+                    &IntelBrandStringMore::get().bytes());
+            brand[32..48].copy_from_slice(
+                    &IntelBrandStringEnd::get().bytes());
+        }
+
+        Self::new(vendor.bytes(), features, brand)
+    }
+
+    /// Create identity from raw vendor string, leaf 1 information and
+    /// brand string bytes.
+    pub fn new(vendor: [u8; 12], features: Features,
+            brand: [u8; BRAND_STRING_LEN]) -> Self {
+        CpuIdentity {
+            vendor,
+            features,
+            brand,
+        }
+    }
+
+    /// Processor vendor.
+    pub fn vendor(&self) -> Vendor {
+        Vendor::from_bytes(&self.vendor)
+    }
+
+    /// Raw vendor string bytes.
+    pub fn vendor_bytes(&self) -> &[u8; 12] {
+        &self.vendor
+    }
+
+    /// Vendor string. None if it is not valid UTF-8.
+    pub fn vendor_str(&self) -> Option<&str> {
+        str::from_utf8(&self.vendor).ok()
+    }
+
+    /// Raw brand string bytes. Zeroed if not reported.
+    pub fn brand_bytes(&self) -> &[u8; BRAND_STRING_LEN] {
+        &self.brand
+    }
+
+    /// Brand string without the null terminator and surrounding spaces.
+    /// None if the string is not reported or is not valid UTF-8.
+    pub fn brand(&self) -> Option<&str> {
+        let len = self.brand.iter()
+            .position(|&b| b == 0)
+            .unwrap_or(BRAND_STRING_LEN);
+
+        match str::from_utf8(&self.brand[..len]) {
+            Ok(s) if !s.trim().is_empty()   => Some(s.trim()),
+            _                               => None,
+        }
+    }
+
+    /// Leaf 1 information the signature is taken from.
+    pub fn features(&self) -> &Features {
+        &self.features
+    }
+
+    /// Raw processor signature as reported in EAX of leaf 1.
+    pub fn signature(&self) -> u32 {
+        let info: Info = self.features.into();
+        info.eax
+    }
+
+    /// Display family. Extended family ID is added when family ID is 0xF.
+    pub fn family(&self) -> u16 {
+        let family = self.features.family_id() as u16;
+        if family == 0xF {
+            family + self.features.extended_family_id() as u16
+        } else {
+            family
+        }
+    }
+
+    /// Display model. Extended model ID forms the upper four bits when
+    /// family ID is 0xF, or 0x6 on processors other than AMD and Hygon,
+    /// or 0x6 and above on Zhaoxin and Centaur processors.
+    pub fn model(&self) -> u8 {
+        let family = self.features.family_id();
+        let model = self.features.model();
+
+        if self.vendor().uses_extended_model(family) {
+            (self.features.extended_model_id() << 4) | model
+        } else {
+            model
+        }
+    }
+
+    /// Stepping ID.
+    pub fn stepping(&self) -> u8 {
+        self.features.stepping_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{INTEL_6_9E, AMD_17_71, AMD_19_21};
+
+    fn identity(vendor: &[u8; 12], leaf1: Info) -> CpuIdentity {
+        CpuIdentity::new(*vendor, Features::from(leaf1),
+                [0; BRAND_STRING_LEN])
+    }
+
+    #[test]
+    fn intel_family_6_extended_model() {
+        let id = identity(b"GenuineIntel", INTEL_6_9E);
+
+        assert_eq!(id.vendor(), Vendor::Intel);
+        assert_eq!(id.signature(), 0x0009_06EA);
+        assert_eq!(id.family(), 0x6);
+        assert_eq!(id.model(), 0x9E);
+        assert_eq!(id.stepping(), 0xA);
+    }
+
+    #[test]
+    fn amd_family_17() {
+        let id = identity(b"AuthenticAMD", AMD_17_71);
+
+        assert_eq!(id.vendor(), Vendor::Amd);
+        assert_eq!(id.family(), 0x17);
+        assert_eq!(id.model(), 0x71);
+        assert_eq!(id.stepping(), 0x0);
+    }
+
+    #[test]
+    fn amd_family_19() {
+        let id = identity(b"AuthenticAMD", AMD_19_21);
+
+        assert_eq!(id.family(), 0x19);
+        assert_eq!(id.model(), 0x21);
+        assert_eq!(id.stepping(), 0x0);
+    }
+
+    #[test]
+    fn zhaoxin_family_7_extended_model() {
+        // KX-7000: family 7, model 0x5B.
+        let leaf1 = Info {
+            eax: 0x0005_07B0, ebx: 0x0008_0800, ecx: 0, edx: 0
+        };
+
+        let id = identity(b"  Shanghai  ", leaf1);
+        assert_eq!(id.vendor(), Vendor::Zhaoxin);
+        assert_eq!(id.family(), 0x7);
+        assert_eq!(id.model(), 0x5B);
+
+        let id = identity(b"CentaurHauls", leaf1);
+        assert_eq!(id.model(), 0x5B);
+
+        // Other vendors don't use extended model with family 7.
+        let id = identity(b"GenuineIntel", leaf1);
+        assert_eq!(id.model(), 0xB);
+    }
+}
@@ -1,5 +1,35 @@
 #![allow(dead_code)]
 
+use hw::Backend;
+
+/// Module with complete CPU feature set.
+mod features;
+pub use self::features::*;
+
+/// Module with processor vendor, brand string and signature.
+mod identity;
+pub use self::identity::*;
+
+/// Module with processor topology enumeration.
+mod topology;
+pub use self::topology::*;
+
+/// Module with cache and TLB parameters.
+mod cache;
+pub use self::cache::*;
+
+/// Module with recorded CPUID responses.
+mod snapshot;
+pub use self::snapshot::*;
+
+/// Module with hypervisor detection and paravirtual leaves.
+mod hypervisor;
+pub use self::hypervisor::*;
+
+/// Module with address widths and paging capabilities.
+mod address;
+pub use self::address::*;
+
 /// Information stored by CPUID instruction in appropriate registers.
 #[derive(Clone, Copy)]
 pub struct Info {
@@ -18,6 +48,9 @@ pub enum InfoType {
     Tlb             = 0x02,
     Serial          = 0x03,
 
+    TscInfo         = 0x15,
+    FrequencyInfo   = 0x16,
+
     // Xsave           = 0x0D, // Sub-function needs to be specified too.
 
     IntelExtended       = 0x8000_0000,
@@ -51,28 +84,12 @@ impl Info {
 
     #[inline(always)]
     pub fn get_by_code(request: u32) -> Self {
-        let (a, b, c, d);
-
-        unsafe { asm!(
-            "cpuid"
-            : "={eax}"(a), "={ebx}"(b), "={ecx}"(c), "={edx}"(d)
-            : "{eax}"(request)
-        ); }
-
-        Info { eax:a, ebx:b, ecx:c, edx:d }
+        Self::get_by_code_ecx(request, 0)
     }
 
     #[inline(always)]
     pub fn get_by_code_ecx(request: u32, subfunction: u32) -> Self {
-        let (a, b, c, d);
-
-        unsafe { asm!(
-            "cpuid"
-            : "={eax}"(a), "={ebx}"(b), "={ecx}"(c), "={edx}"(d)
-            : "{eax}"(request), "{ecx}"(subfunction)
-        ); }
-
-        Info { eax:a, ebx:b, ecx:c, edx:d }
+        ::hw::backend().cpuid(request, subfunction)
     }
 }
 
@@ -90,10 +107,10 @@ macro_rules! derive_conversions {
             }
         }
 
-        impl Into<Info> for $x {
+        impl From<$x> for Info {
 
-            fn into(self) -> Info {
-                self.info
+            fn from(v: $x) -> Info {
+                v.info
             }
         }
     );
@@ -117,6 +134,8 @@ derive_info!(VendorString);
 derive_info!(Features);
 derive_info!(Tlb);
 derive_info!(Serial);
+derive_info!(TscInfo);
+derive_info!(FrequencyInfo);
 derive_info!(IntelExtended);
 derive_info!(IntelFeatures);
 derive_info!(IntelBrandString);
@@ -192,26 +211,121 @@ impl VendorString {
     pub fn vendor(&self, s: &mut [char; 13]) {
         s[12] = '\0'; // Null-terminate the string.
 
-        s[0x00] = ((self.info.ebx & 0x000000FF) >> 0x00) as u8 as char;
+        s[0x00] = (self.info.ebx & 0x000000FF) as u8 as char;
         s[0x01] = ((self.info.ebx & 0x0000FF00) >> 0x08) as u8 as char;
         s[0x02] = ((self.info.ebx & 0x00FF0000) >> 0x10) as u8 as char;
         s[0x03] = ((self.info.ebx & 0xFF000000) >> 0x18) as u8 as char;
-        s[0x04] = ((self.info.edx & 0x000000FF) >> 0x00) as u8 as char;
+        s[0x04] = (self.info.edx & 0x000000FF) as u8 as char;
         s[0x05] = ((self.info.edx & 0x0000FF00) >> 0x08) as u8 as char;
         s[0x06] = ((self.info.edx & 0x00FF0000) >> 0x10) as u8 as char;
         s[0x07] = ((self.info.edx & 0xFF000000) >> 0x18) as u8 as char;
-        s[0x08] = ((self.info.ecx & 0x000000FF) >> 0x00) as u8 as char;
+        s[0x08] = (self.info.ecx & 0x000000FF) as u8 as char;
         s[0x09] = ((self.info.ecx & 0x0000FF00) >> 0x08) as u8 as char;
         s[0x0A] = ((self.info.ecx & 0x00FF0000) >> 0x10) as u8 as char;
         s[0x0B] = ((self.info.ecx & 0xFF000000) >> 0x18) as u8 as char;
     }
 
+    /// Vendor identification string bytes.
+    pub fn bytes(&self) -> [u8; 12] {
+        let mut b = [0; 12];
+        put_bytes(&mut b[0..4], self.info.ebx);
+        put_bytes(&mut b[4..8], self.info.edx);
+        put_bytes(&mut b[8..12], self.info.ecx);
+        b
+    }
+
     /// Maximal input value for basic CPUID information.
     pub fn max_value(&self) -> u32 {
         self.info.eax
     }
 }
 
+impl IntelExtended {
+
+    /// Maximal input value for extended CPUID information.
+    pub fn max_value(&self) -> u32 {
+        self.info.eax
+    }
+}
+
+macro_rules! brand_string_impl {
+    ($($x:ident),*) => ($(
+        impl $x {
+
+            /// Part of the processor brand string stored in this leaf.
+            pub fn bytes(&self) -> [u8; 16] {
+                let mut b = [0; 16];
+                put_bytes(&mut b[0..4], self.info.eax);
+                put_bytes(&mut b[4..8], self.info.ebx);
+                put_bytes(&mut b[8..12], self.info.ecx);
+                put_bytes(&mut b[12..16], self.info.edx);
+                b
+            }
+        }
+    )*);
+}
+
+brand_string_impl!(IntelBrandString, IntelBrandStringMore,
+        IntelBrandStringEnd);
+
+/// Store register value in little-endian byte order as CPUID strings are
+/// laid out.
+fn put_bytes(dst: &mut [u8], reg: u32) {
+    for (i, b) in dst.iter_mut().take(4).enumerate() {
+        *b = (reg >> (i * 8)) as u8;
+    }
+}
+
+impl TscInfo {
+
+    /// Denominator of TSC to core crystal clock ratio.
+    pub fn denominator(&self) -> u32 {
+        self.info.eax
+    }
+
+    /// Numerator of TSC to core crystal clock ratio.
+    pub fn numerator(&self) -> u32 {
+        self.info.ebx
+    }
+
+    /// Core crystal clock frequency in Hz. Zero if not enumerated.
+    pub fn crystal_hz(&self) -> u32 {
+        self.info.ecx
+    }
+
+    /// TSC frequency in Hz computed from crystal clock and the ratio.
+    /// None if any of the values is not enumerated.
+    pub fn tsc_hz(&self) -> Option<u64> {
+        let den = self.denominator() as u64;
+        let num = self.numerator() as u64;
+        let crystal = self.crystal_hz() as u64;
+
+        if den == 0 || num == 0 || crystal == 0 {
+            None
+        } else {
+            Some(crystal * num / den)
+        }
+    }
+}
+
+impl FrequencyInfo {
+
+    /// Processor base frequency in MHz. Zero if not enumerated.
+    pub fn base_mhz(&self) -> u16 {
+        self.info.eax as u16
+    }
+
+    /// Maximum processor frequency in MHz. Zero if not enumerated.
+    pub fn max_mhz(&self) -> u16 {
+        self.info.ebx as u16
+    }
+
+    /// Bus (reference) frequency in MHz. Zero if not enumerated.
+    pub fn bus_mhz(&self) -> u16 {
+        self.info.ecx as u16
+    }
+}
+
 impl Features {
 
     /// Get brand index.
@@ -222,7 +336,7 @@ impl Features {
     /// CLFLUSH line size. Value * 8 = cache line size in bytes.
     /// Used also by CLFLUSHOPT.
     pub fn clflush_line_size(&self) -> u8 {
-        (self.info.ebx & 0xFF00 >> 8) as u8
+        (self.info.ebx >> 8) as u8
     }
 
     /// Maximum number of addressable IDs for logical processors in this
@@ -231,41 +345,54 @@ impl Features {
     /// for addressing different logical processors in a physical package.
     /// This field is only valid if EDX.HTT (bit 28) is set.
     pub fn max_addressable_ids(&self) -> u8 {
-        (self.info.ebx & 0xFF0000 >> 16) as u8
+        (self.info.ebx >> 16) as u8
     }
 
     /// Get initial APIC ID.
     pub fn initial_apic_id(&self) -> u8 {
-        (self.info.ebx & 0xFF000000 >> 24) as u8
+        (self.info.ebx >> 24) as u8
     }
 
+    /// Extended family ID. Added to family ID when the latter is 0xF.
     pub fn extended_family_id(&self) -> u8 {
-        (self.info.eax & 0b0000_1111_1111_0000_0000_0000_0000_0000 >> 20) as u8
+        ((self.info.eax >> 20) & 0xFF) as u8
     }
 
+    /// Extended model ID. Forms upper four bits of the display model
+    /// for some families, see `CpuIdentity::model`.
     pub fn extended_model_id(&self) -> u8 {
-        (self.info.eax & 0b0000_0000_0000_1111_0000_0000_0000_0000 >> 16) as u8
+        ((self.info.eax >> 16) & 0xF) as u8
     }
 
+    /// Processor type: 0 - original OEM, 1 - OverDrive, 2 - dual
+    /// processor.
     pub fn processor_type(&self) -> u8 {
-        (self.info.eax & 0b0000_0000_0000_0000_0011_0000_0000_0000 >> 12) as u8
+        ((self.info.eax >> 12) & 0x3) as u8
     }
 
+    /// Family ID.
     pub fn family_id(&self) -> u8 {
-        (self.info.eax & 0b0000_0000_0000_0000_0000_1111_0000_0000 >> 8) as u8
+        ((self.info.eax >> 8) & 0xF) as u8
     }
 
+    /// Model ID. See `CpuIdentity::model` for the display model.
     pub fn model(&self) -> u8 {
-        (self.info.eax & 0b0000_0000_0000_0000_0000_0000_1111_0000 >> 4) as u8
+        ((self.info.eax >> 4) & 0xF) as u8
     }
 
+    /// Stepping ID.
     pub fn stepping_id(&self) -> u8 {
-        (self.info.eax & 0b0000_0000_0000_0000_0000_0000_0000_1111) as u8
+        (self.info.eax & 0xF) as u8
     }
 
     /// Check if Local APIC is present.
     pub fn local_apic_is_present(&self) -> bool {
-        self.info.edx & 0b0000_0000_0000_0000_0000_0001_0000_0000 != 0
+        self.info.edx & (1 << 9) != 0
+    }
+
+    /// Whether x2APIC mode of local APIC is supported.
+    pub fn x2apic_supported(&self) -> bool {
+        self.info.ecx & (1 << 21) != 0
     }
 
     /// Whether APIC supports one-shot operation using TSC deadline value.
@@ -278,3 +405,56 @@ impl Features {
         self.info.ecx & (1 << 26) != 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leaf 1 of Core i7-8700: family 6, model 0x9E, stepping 0xA, APIC ID
+    /// 0x0B. Local APIC is disabled in IA32_APIC_BASE, which clears EDX
+    /// bit 9 while bit 8 (CX8) stays set.
+    pub const INTEL_6_9E: Info = Info {
+        eax: 0x0009_06EA, ebx: 0x0B10_0800, ecx: 0x7FFA_FBBF, edx: 0xBFEB_F9FF
+    };
+
+    /// Leaf 1 of Ryzen 7 3700X: family 0x17, model 0x71, stepping 0.
+    pub const AMD_17_71: Info = Info {
+        eax: 0x0087_0F10, ebx: 0x0410_0800, ecx: 0x7ED8_3203, edx: 0x178B_FBFF
+    };
+
+    /// Leaf 1 of Ryzen 9 5950X: family 0x19, model 0x21, stepping 0.
+    pub const AMD_19_21: Info = Info {
+        eax: 0x00A2_0F10, ebx: 0x1F20_0800, ecx: 0x7EF8_320B, edx: 0x178B_FBFF
+    };
+
+    #[test]
+    fn leaf1_ebx_fields() {
+        let f = Features::from(INTEL_6_9E);
+
+        assert_eq!(f.brand_index(), 0);
+        assert_eq!(f.clflush_line_size(), 8);
+        assert_eq!(f.max_addressable_ids(), 0x10);
+        assert_eq!(f.initial_apic_id(), 0x0B);
+    }
+
+    #[test]
+    fn leaf1_eax_fields() {
+        let f = Features::from(AMD_17_71);
+
+        assert_eq!(f.stepping_id(), 0x0);
+        assert_eq!(f.model(), 0x1);
+        assert_eq!(f.family_id(), 0xF);
+        assert_eq!(f.processor_type(), 0);
+        assert_eq!(f.extended_model_id(), 0x7);
+        assert_eq!(f.extended_family_id(), 0x08);
+    }
+
+    #[test]
+    fn leaf1_apic_bit() {
+        let disabled = Features::from(INTEL_6_9E);
+        let enabled = Features::from(AMD_19_21);
+
+        assert!(!disabled.local_apic_is_present());
+        assert!(enabled.local_apic_is_present());
+    }
+}
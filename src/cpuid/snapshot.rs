@@ -0,0 +1,544 @@
+use super::*;
+use core::fmt;
+use hw::{Backend, ControlReg};
+
+/// Max count of leaf and subleaf pairs a snapshot can hold.
+pub const SNAPSHOT_CAPACITY: usize = 256;
+
+/// Size of a single entry in binary form: leaf, subleaf, EAX, EBX, ECX and
+/// EDX as little-endian 32-bit values.
+pub const SNAPSHOT_ENTRY_SIZE: usize = 24;
+
+/// Max count of subleaves enumerated for a single leaf.
+const MAX_SUBLEAVES: u32 = 64;
+
+/// Max count of leaves enumerated in a single range.
+const MAX_RANGE_LEAVES: u32 = 0x100;
+
+/// Result of CPUID for given leaf and subleaf.
+#[derive(Clone, Copy)]
+pub struct CpuidEntry {
+    pub leaf    : u32,
+    pub subleaf : u32,
+    pub info    : Info,
+}
+
+/// Recorded CPUID responses of a processor.
+///
+/// Text form follows the raw dump of the `cpuid -r` tool:
+///
+/// ```text
+/// CPU 0:
+///    0x00000000 0x00: eax=0x0000000d ebx=0x756e6547 ecx=0x6c65746e edx=...
+/// ```
+#[derive(Clone, Copy)]
+pub struct CpuidSnapshot {
+    entries     : [CpuidEntry; SNAPSHOT_CAPACITY],
+    len         : usize,
+    truncated   : bool,
+}
+
+/// Backend that serves CPUID from a snapshot and forwards all other
+/// accesses to another backend. Install it with `hw::with_backend` to run
+/// feature detection against recorded data. Inner backend must not be
+/// `hw::backend()` of test builds as that one forwards back to the
+/// installed replay.
+pub struct CpuidReplay<'a> {
+    snapshot    : CpuidSnapshot,
+    inner       : &'a dyn Backend,
+}
+
+impl CpuidSnapshot {
+
+    /// Empty snapshot.
+    pub fn new() -> Self {
+        let zero = Info { eax: 0, ebx: 0, ecx: 0, edx: 0 };
+        CpuidSnapshot {
+            entries     : [CpuidEntry { leaf: 0, subleaf: 0, info: zero };
+                    SNAPSHOT_CAPACITY],
+            len         : 0,
+            truncated   : false,
+        }
+    }
+
+    /// Record all valid leaves and subleaves of basic, hypervisor and
+    /// extended ranges of the current processor. Leaves that do not fit
+    /// are dropped and `truncated` is set then.
+    pub fn capture() -> Self {
+        let mut s = Self::new();
+
+        let max_basic = s.record_leaf(0, 0).eax;
+        let hypervisor = max_basic >= 1
+                && s.record_leaf(1, 0).ecx & (1 << 31) != 0;
+        for leaf in 2..max_basic.min(MAX_RANGE_LEAVES - 1) + 1 {
+            s.record_subleaves(leaf);
+        }
+
+        if hypervisor {
+            s.record_range(0x4000_0000);
+        }
+        s.record_range(0x8000_0000);
+        s
+    }
+
+    /// Record leaves from the start of the range to the max one it
+    /// reports.
+    fn record_range(&mut self, base: u32) {
+        let max = self.record_leaf(base, 0).eax;
+        if max <= base || max - base >= MAX_RANGE_LEAVES {
+            return;
+        }
+        for leaf in base + 1..max + 1 {
+            self.record_subleaves(leaf);
+        }
+    }
+
+    /// Record leaf with all its valid subleaves.
+    fn record_subleaves(&mut self, leaf: u32) {
+        let first = self.record_leaf(leaf, 0);
+
+        match leaf {
+            // Cache parameters, terminated by null cache type.
+            0x04 | 0x8000_001D => {
+                let mut info = first;
+                let mut subleaf = 1;
+                while info.eax & 0x1F != 0 && subleaf < MAX_SUBLEAVES {
+                    info = self.record_leaf(leaf, subleaf);
+                    subleaf += 1;
+                }
+            },
+
+            // Extended topology, terminated by invalid level type.
+            0x0B | 0x1F => {
+                let mut info = first;
+                let mut subleaf = 1;
+                while (info.ecx >> 8) & 0xFF != 0 && subleaf < MAX_SUBLEAVES {
+                    info = self.record_leaf(leaf, subleaf);
+                    subleaf += 1;
+                }
+            },
+
+            // XSAVE, subleaves 2 and above describe state components
+            // supported in XCR0 or IA32_XSS.
+            0x0D => {
+                let ext = self.record_leaf(leaf, 1);
+                let xcr0 = first.eax as u64 | (first.edx as u64) << 32;
+                let xss = ext.ecx as u64 | (ext.edx as u64) << 32;
+                for subleaf in 2..MAX_SUBLEAVES {
+                    if (xcr0 | xss) & (1 << subleaf) != 0 {
+                        self.record_leaf(leaf, subleaf);
+                    }
+                }
+            },
+
+            // Platform QoS monitoring and enforcement.
+            0x0F => { self.record_leaf(leaf, 1); },
+            0x10 => {
+                for subleaf in 1..4 {
+                    self.record_leaf(leaf, subleaf);
+                }
+            },
+
+            // SGX, EPC sections terminated by invalid type.
+            0x12 => {
+                self.record_leaf(leaf, 1);
+                let mut subleaf = 2;
+                while subleaf < MAX_SUBLEAVES {
+                    let info = self.record_leaf(leaf, subleaf);
+                    if info.eax & 0xF == 0 {
+                        break;
+                    }
+                    subleaf += 1;
+                }
+            },
+
+            // Leaves that report max subleaf in EAX of subleaf 0.
+            0x07 | 0x14 | 0x17 | 0x18 | 0x1D | 0x20 => {
+                let max = first.eax.min(MAX_SUBLEAVES - 1);
+                for subleaf in 1..max + 1 {
+                    self.record_leaf(leaf, subleaf);
+                }
+            },
+
+            _ => (),
+        }
+    }
+
+    /// Run CPUID and record the result. The result is returned even if
+    /// the snapshot is full.
+    fn record_leaf(&mut self, leaf: u32, subleaf: u32) -> Info {
+        let info = Info::get_by_code_ecx(leaf, subleaf);
+        if !self.insert(leaf, subleaf, info) {
+            self.truncated = true;
+        }
+        info
+    }
+
+    /// Add or replace result for given leaf and subleaf. Returns false if
+    /// the snapshot is full.
+    pub fn insert(&mut self, leaf: u32, subleaf: u32, info: Info) -> bool {
+        for e in self.entries[..self.len].iter_mut() {
+            if e.leaf == leaf && e.subleaf == subleaf {
+                e.info = info;
+                return true;
+            }
+        }
+
+        if self.len >= SNAPSHOT_CAPACITY {
+            return false;
+        }
+        self.entries[self.len] = CpuidEntry {
+            leaf,
+            subleaf,
+            info,
+        };
+        self.len += 1;
+        true
+    }
+
+    /// Recorded entries in the order they were added.
+    pub fn entries(&self) -> &[CpuidEntry] {
+        &self.entries[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether `capture` had to drop some leaves because the snapshot
+    /// was full. Replay of such snapshot returns zeros for them.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Result that the recorded processor would return. Leaves that were
+    /// recorded with subleaf 0 only do not depend on subleaf. Unknown
+    /// leaves return zeros.
+    pub fn get(&self, leaf: u32, subleaf: u32) -> Info {
+        let mut only_subleaf0 = None;
+        for e in self.entries().iter().filter(|e| e.leaf == leaf) {
+            if e.subleaf == subleaf {
+                return e.info;
+            }
+            only_subleaf0 = match (only_subleaf0, e.subleaf) {
+                (None, 0)   => Some(Some(e.info)),
+                _           => Some(None),
+            };
+        }
+
+        match only_subleaf0 {
+            Some(Some(info))    => info,
+            _                   => Info { eax: 0, ebx: 0, ecx: 0, edx: 0 },
+        }
+    }
+
+    /// Write snapshot in `cpuid -r` text form.
+    pub fn write_text<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        writeln!(w, "CPU 0:")?;
+        for e in self.entries() {
+            writeln!(w, "   0x{:08x} 0x{:02x}: eax=0x{:08x} ebx=0x{:08x} \
+                    ecx=0x{:08x} edx=0x{:08x}", e.leaf, e.subleaf,
+                    e.info.eax, e.info.ebx, e.info.ecx, e.info.edx)?;
+        }
+        Ok(())
+    }
+
+    /// Parse snapshot from `cpuid -r` text form. Only the first CPU of the
+    /// dump is read. Lines that are not entries are ignored. None is
+    /// returned if an entry is malformed or the snapshot is full.
+    pub fn parse_text(text: &str) -> Option<Self> {
+        let mut s = Self::new();
+        let mut cpus = 0;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with("CPU") {
+                cpus += 1;
+                if cpus > 1 {
+                    break;
+                }
+                continue;
+            }
+            if !line.starts_with("0x") {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let leaf = parse_hex(words.next()?)?;
+            let subleaf = parse_hex(words.next()?.trim_end_matches(':'))?;
+
+            let mut regs = [None; 4];
+            for w in words {
+                let mut kv = w.splitn(2, '=');
+                let index = match kv.next()? {
+                    "eax"   => 0,
+                    "ebx"   => 1,
+                    "ecx"   => 2,
+                    "edx"   => 3,
+                    _       => return None,
+                };
+                regs[index] = Some(parse_hex(kv.next()?)?);
+            }
+
+            let info = Info {
+                eax : regs[0]?,
+                ebx : regs[1]?,
+                ecx : regs[2]?,
+                edx : regs[3]?,
+            };
+            if !s.insert(leaf, subleaf, info) {
+                return None;
+            }
+        }
+        Some(s)
+    }
+
+    /// Size of the binary form in bytes.
+    pub fn binary_size(&self) -> usize {
+        self.len * SNAPSHOT_ENTRY_SIZE
+    }
+
+    /// Write binary form into given buffer. Returns count of written bytes
+    /// or None if the buffer is too small.
+    pub fn write_binary(&self, buf: &mut [u8]) -> Option<usize> {
+        let size = self.binary_size();
+        if buf.len() < size {
+            return None;
+        }
+
+        for (e, chunk) in self.entries().iter()
+                .zip(buf.chunks_mut(SNAPSHOT_ENTRY_SIZE)) {
+            let vals = [e.leaf, e.subleaf, e.info.eax, e.info.ebx,
+                    e.info.ecx, e.info.edx];
+            for (v, b) in vals.iter().zip(chunk.chunks_mut(4)) {
+                for (i, byte) in b.iter_mut().enumerate() {
+                    *byte = (v >> (i * 8)) as u8;
+                }
+            }
+        }
+        Some(size)
+    }
+
+    /// Read snapshot from binary form. None is returned if the size is
+    /// not a multiple of entry size or the snapshot does not fit.
+    pub fn parse_binary(buf: &[u8]) -> Option<Self> {
+        if buf.len() % SNAPSHOT_ENTRY_SIZE != 0 {
+            return None;
+        }
+
+        let mut s = Self::new();
+        for chunk in buf.chunks(SNAPSHOT_ENTRY_SIZE) {
+            let mut vals = [0u32; 6];
+            for (v, b) in vals.iter_mut().zip(chunk.chunks(4)) {
+                *v = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16
+                        | (b[3] as u32) << 24;
+            }
+            let info = Info {
+                eax : vals[2],
+                ebx : vals[3],
+                ecx : vals[4],
+                edx : vals[5],
+            };
+            if !s.insert(vals[0], vals[1], info) {
+                return None;
+            }
+        }
+        Some(s)
+    }
+}
+
+impl Default for CpuidSnapshot {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for CpuidSnapshot {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_text(f)
+    }
+}
+
+/// Parse hexadecimal number with optional `0x` prefix.
+fn parse_hex(s: &str) -> Option<u32> {
+    let digits = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+    u32::from_str_radix(digits, 16).ok()
+}
+
+impl<'a> CpuidReplay<'a> {
+
+    /// Replay given snapshot. Accesses other than CPUID go to `inner`.
+    pub fn new(snapshot: CpuidSnapshot, inner: &'a dyn Backend) -> Self {
+        CpuidReplay {
+            snapshot,
+            inner,
+        }
+    }
+
+    pub fn snapshot(&self) -> &CpuidSnapshot {
+        &self.snapshot
+    }
+}
+
+impl<'a> Backend for CpuidReplay<'a> {
+
+    fn port_in_u8(&self, port: u16) -> u8 {
+        self.inner.port_in_u8(port)
+    }
+
+    fn port_in_u16(&self, port: u16) -> u16 {
+        self.inner.port_in_u16(port)
+    }
+
+    fn port_in_u32(&self, port: u16) -> u32 {
+        self.inner.port_in_u32(port)
+    }
+
+    fn port_out_u8(&self, port: u16, data: u8) {
+        self.inner.port_out_u8(port, data)
+    }
+
+    fn port_out_u16(&self, port: u16, data: u16) {
+        self.inner.port_out_u16(port, data)
+    }
+
+    fn port_out_u32(&self, port: u16, data: u32) {
+        self.inner.port_out_u32(port, data)
+    }
+
+    unsafe fn rdmsr(&self, id: u32) -> u64 {
+        self.inner.rdmsr(id)
+    }
+
+    unsafe fn wrmsr(&self, id: u32, val: u64) {
+        self.inner.wrmsr(id, val)
+    }
+
+    unsafe fn read_cr(&self, cr: ControlReg) -> u64 {
+        self.inner.read_cr(cr)
+    }
+
+    unsafe fn write_cr(&self, cr: ControlReg, val: u64) {
+        self.inner.write_cr(cr, val)
+    }
+
+    fn cpuid(&self, leaf: u32, subleaf: u32) -> Info {
+        self.snapshot.get(leaf, subleaf)
+    }
+
+    fn rdtsc(&self) -> u64 {
+        self.inner.rdtsc()
+    }
+
+    fn mfence(&self) {
+        self.inner.mfence()
+    }
+
+    unsafe fn mmio_read32(&self, addr: usize) -> u32 {
+        self.inner.mmio_read32(addr)
+    }
+
+    unsafe fn mmio_write32(&self, addr: usize, val: u32) {
+        self.inner.mmio_write32(addr, val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    /// `cpuid -r` dump of a KVM guest on Sapphire Rapids host.
+    const KVM_SAPPHIRE_RAPIDS: &str =
+            include_str!("testdata/kvm_sapphire_rapids.txt");
+
+    fn replay<R, F: FnOnce() -> R>(text: &str, f: F) -> R {
+        let snapshot = CpuidSnapshot::parse_text(text).unwrap();
+        let mock = Mock::new();
+        let replay = CpuidReplay::new(snapshot, &mock);
+        with_backend(&replay, f)
+    }
+
+    #[test]
+    fn parse_real_dump() {
+        let s = CpuidSnapshot::parse_text(KVM_SAPPHIRE_RAPIDS).unwrap();
+        assert_eq!(s.len(), 71);
+        assert!(!s.truncated());
+        assert_eq!(s.get(0x0D, 0x12).eax, 0x0000_2000);
+
+        // Leaf 6 is recorded with subleaf 0 only.
+        assert_eq!(s.get(6, 3).eax, 4);
+        // Leaf 4 has subleaves so unknown ones are zeros.
+        assert_eq!(s.get(4, 9).eax, 0);
+    }
+
+    #[test]
+    fn replay_features() {
+        let f = replay(KVM_SAPPHIRE_RAPIDS, CpuFeatures::get);
+
+        assert!(f.has_all(&[CpuFeature::Sse2, CpuFeature::X2Apic,
+                CpuFeature::Hypervisor, CpuFeature::Avx2, CpuFeature::La57,
+                CpuFeature::Page1Gb]));
+        assert!(!f.has(CpuFeature::TopoExt));
+    }
+
+    #[test]
+    fn replay_topology() {
+        let t = replay(KVM_SAPPHIRE_RAPIDS, CpuTopology::get);
+
+        assert_eq!(t.source(), TopologySource::Leaf1F);
+        assert_eq!(t.x2apic_id(), 0);
+        assert_eq!(t.levels().len(), 2);
+        assert_eq!(t.smt_shift(), 0);
+        assert_eq!(t.package_shift(), 5);
+        assert_eq!(t.level(TopologyLevelType::Core).unwrap()
+                .logical_count(), 1);
+    }
+
+    #[test]
+    fn replay_cache_hierarchy() {
+        let h = replay(KVM_SAPPHIRE_RAPIDS, CacheHierarchy::get);
+
+        let sizes: [(u8, CacheType, u64, u16); 4] = [
+            (1, CacheType::Data,        48 << 10,   12),
+            (1, CacheType::Instruction, 32 << 10,   8),
+            (2, CacheType::Unified,     2 << 20,    16),
+            (3, CacheType::Unified,     105 << 20,  15),
+        ];
+        assert_eq!(h.caches().len(), sizes.len());
+        for (c, &(level, kind, size, ways)) in h.caches().iter()
+                .zip(sizes.iter()) {
+            assert_eq!((c.level(), c.kind(), c.size(), c.ways()),
+                    (level, kind, size, ways));
+            assert_eq!(c.line_size(), 64);
+        }
+        assert!(h.tlbs().is_empty());
+        assert_eq!(h.prefetch(), Some(64));
+    }
+
+    #[test]
+    fn capture_reports_truncation() {
+        let mock = Mock::new();
+        mock.set_cpuid(0, 0, Info { eax: 1, ebx: 0, ecx: 0, edx: 0 });
+        let s = with_backend(&mock, CpuidSnapshot::capture);
+        assert_eq!(s.len(), 3); // Leaves 0, 1 and 0x8000_0000.
+        assert!(!s.truncated());
+
+        let mock = Mock::new();
+        mock.set_cpuid(0, 0, Info { eax: 0xFF, ebx: 0, ecx: 0, edx: 0 });
+        let s = with_backend(&mock, CpuidSnapshot::capture);
+        assert_eq!(s.len(), SNAPSHOT_CAPACITY);
+        assert!(s.truncated());
+    }
+}
@@ -0,0 +1,352 @@
+use super::*;
+
+/// Max count of topology levels that can be enumerated.
+pub const MAX_TOPOLOGY_LEVELS: usize = 8;
+
+/// Type of topology level as reported in ECX[15:8] of leaves 0x0B and 0x1F.
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TopologyLevelType {
+    Smt     = 1,
+    Core    = 2,
+    Module  = 3,
+    Tile    = 4,
+    Die     = 5,
+}
+
+/// CPUID leaf topology information was obtained from.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TopologySource {
+
+    /// V2 extended topology leaf 0x1F.
+    Leaf1F,
+
+    /// Extended topology leaf 0x0B.
+    Leaf0B,
+
+    /// AMD extended APIC ID leaf 0x8000_001E with core count from
+    /// leaf 0x8000_0008.
+    AmdExtended,
+
+    /// Leaf 1 with core count from leaf 4 or 0x8000_0008. Only 8-bit
+    /// initial APIC ID is available.
+    Legacy,
+}
+
+/// Single level of processor topology.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TopologyLevel {
+    kind            : TopologyLevelType,
+    shift           : u8,
+    logical_count   : u16,
+}
+
+/// APIC ID split into topology domains.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ApicIdParts {
+
+    /// Package ID.
+    pub package : u32,
+
+    /// Core ID within the package. Includes module, tile and die bits.
+    pub core    : u32,
+
+    /// Thread ID within the core.
+    pub thread  : u32,
+}
+
+/// Processor topology as seen by the processor that enumerated it.
+#[derive(Clone, Copy)]
+pub struct CpuTopology {
+    source      : TopologySource,
+    x2apic_id   : u32,
+    levels      : [TopologyLevel; MAX_TOPOLOGY_LEVELS],
+    level_count : usize,
+}
+
+impl TopologyLevelType {
+
+    /// Decode level type. None is returned for invalid and unknown types.
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        use self::TopologyLevelType::*;
+        match raw {
+            1 => Some(Smt),
+            2 => Some(Core),
+            3 => Some(Module),
+            4 => Some(Tile),
+            5 => Some(Die),
+            _ => None,
+        }
+    }
+}
+
+impl TopologyLevel {
+
+    pub fn new(kind: TopologyLevelType, shift: u8, logical_count: u16)
+            -> Self {
+        TopologyLevel {
+            kind,
+            shift,
+            logical_count,
+        }
+    }
+
+    pub fn kind(&self) -> TopologyLevelType {
+        self.kind
+    }
+
+    /// Count of bits to shift APIC ID right to get ID of the next level.
+    pub fn shift(&self) -> u8 {
+        self.shift
+    }
+
+    /// Count of logical processors at this level. This is informational
+    /// only and may not match the count of enabled processors.
+    pub fn logical_count(&self) -> u16 {
+        self.logical_count
+    }
+}
+
+impl CpuTopology {
+
+    /// Enumerate topology of the current processor. Leaf 0x1F is preferred
+    /// over 0x0B, then AMD leaves are used and finally leaf 1.
+    pub fn get() -> Self {
+        let max_basic = VendorString::get().max_value();
+        let max_ext = IntelExtended::get().max_value();
+
+        if max_basic >= 0x1F {
+            if let Some(t) = Self::from_extended_leaf(0x1F) {
+                return t;
+            }
+        }
+        if max_basic >= 0x0B {
+            if let Some(t) = Self::from_extended_leaf(0x0B) {
+                return t;
+            }
+        }
+
+        let features = CpuFeatures::get();
+        if max_ext >= 0x8000_001E && features.has(CpuFeature::TopoExt) {
+            return Self::from_amd_leaves();
+        }
+
+        Self::from_legacy_leaves(max_basic, max_ext)
+    }
+
+    /// Create topology from given levels ordered from the lowest one. Levels
+    /// after `MAX_TOPOLOGY_LEVELS` are ignored.
+    pub fn new(source: TopologySource, x2apic_id: u32,
+            levels: &[TopologyLevel]) -> Self {
+        let mut t = CpuTopology {
+            source,
+            x2apic_id,
+            levels      : [TopologyLevel::new(TopologyLevelType::Smt, 0, 1);
+                    MAX_TOPOLOGY_LEVELS],
+            level_count : 0,
+        };
+
+        for l in levels.iter().take(MAX_TOPOLOGY_LEVELS) {
+            t.levels[t.level_count] = *l;
+            t.level_count += 1;
+        }
+        t
+    }
+
+    fn from_extended_leaf(leaf: u32) -> Option<Self> {
+        let mut levels = [TopologyLevel::new(TopologyLevelType::Smt, 0, 1);
+                MAX_TOPOLOGY_LEVELS];
+        let mut count = 0;
+        let mut x2apic_id = 0;
+
+        for subleaf in 0..MAX_TOPOLOGY_LEVELS as u32 {
+            let i = Info::get_by_code_ecx(leaf, subleaf);
+            let raw_type = (i.ecx >> 8) as u8;
+            if raw_type == 0 || i.ebx & 0xFFFF == 0 {
+                break;
+            }
+
+            x2apic_id = i.edx;
+
+            // Unknown level types are still counted in the shift of the
+            // next known level so they can be skipped safely.
+            if let Some(kind) = TopologyLevelType::from_raw(raw_type) {
+                levels[count] = TopologyLevel::new(kind,
+                        (i.eax & 0x1F) as u8, i.ebx as u16);
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        let source = if leaf == 0x1F {
+            TopologySource::Leaf1F
+        } else {
+            TopologySource::Leaf0B
+        };
+        Some(Self::new(source, x2apic_id, &levels[..count]))
+    }
+
+    fn from_amd_leaves() -> Self {
+        let ext = Info::get_by_code(0x8000_001E);
+        let threads = ((ext.ebx >> 8) & 0xFF) + 1;
+        let smt_shift = ceil_log2(threads);
+        let pkg_shift = Self::amd_core_id_size().max(smt_shift);
+
+        let levels = [
+            TopologyLevel::new(TopologyLevelType::Smt, smt_shift as u8,
+                    threads as u16),
+            TopologyLevel::new(TopologyLevelType::Core, pkg_shift as u8,
+                    Self::amd_thread_count() as u16),
+        ];
+        Self::new(TopologySource::AmdExtended, ext.eax, &levels)
+    }
+
+    fn from_legacy_leaves(max_basic: u32, max_ext: u32) -> Self {
+        let f = Features::get();
+        let id = f.initial_apic_id() as u32;
+
+        if !CpuFeatures::get().has(CpuFeature::Htt) {
+            let levels = [
+                TopologyLevel::new(TopologyLevelType::Smt, 0, 1),
+                TopologyLevel::new(TopologyLevelType::Core, 0, 1),
+            ];
+            return Self::new(TopologySource::Legacy, id, &levels);
+        }
+
+        let logical = (f.max_addressable_ids() as u32).max(1);
+        let vendor = Vendor::from_bytes(&VendorString::get().bytes());
+        let amd = vendor == Vendor::Amd || vendor == Vendor::Hygon;
+
+        let (cores, pkg_shift) = if amd && max_ext >= 0x8000_0008 {
+            let size = Self::amd_core_id_size();
+            (Self::amd_thread_count(), size.max(ceil_log2(logical)))
+        } else if !amd && max_basic >= 4 {
+            let cores = (Info::get_by_code_ecx(4, 0).eax >> 26) + 1;
+            (cores, ceil_log2(logical))
+        } else {
+            (1, ceil_log2(logical))
+        };
+
+        // AMD reports logical processor count here and leaves SMT to
+        // leaf 0x8000_001E, so cores are assumed single threaded.
+        let threads_shift = if amd {
+            0
+        } else {
+            ceil_log2(logical / cores.max(1))
+        };
+
+        let levels = [
+            TopologyLevel::new(TopologyLevelType::Smt, threads_shift as u8,
+                    (1 << threads_shift) as u16),
+            TopologyLevel::new(TopologyLevelType::Core, pkg_shift as u8,
+                    logical as u16),
+        ];
+        Self::new(TopologySource::Legacy, id, &levels)
+    }
+
+    /// Count of APIC ID bits used for core ID in the package as reported
+    /// by leaf 0x8000_0008.
+    fn amd_core_id_size() -> u32 {
+        let ecx = Info::get_by_code(0x8000_0008).ecx;
+        match (ecx >> 12) & 0xF {
+            0       => ceil_log2((ecx & 0xFF) + 1),
+            size    => size,
+        }
+    }
+
+    /// Count of logical processors in the package as reported by leaf
+    /// 0x8000_0008.
+    fn amd_thread_count() -> u32 {
+        (Info::get_by_code(0x8000_0008).ecx & 0xFF) + 1
+    }
+
+    /// Leaf used to enumerate the topology.
+    pub fn source(&self) -> TopologySource {
+        self.source
+    }
+
+    /// x2APIC ID of the processor that enumerated the topology. For
+    /// legacy source this is 8-bit initial APIC ID.
+    pub fn x2apic_id(&self) -> u32 {
+        self.x2apic_id
+    }
+
+    /// Levels ordered from the lowest one. The shift of the last level
+    /// gives the package ID.
+    pub fn levels(&self) -> &[TopologyLevel] {
+        &self.levels[..self.level_count]
+    }
+
+    /// Level of given type if it is enumerated.
+    pub fn level(&self, kind: TopologyLevelType) -> Option<TopologyLevel> {
+        self.levels().iter().find(|l| l.kind == kind).cloned()
+    }
+
+    /// Count of APIC ID bits used for thread ID in the core.
+    pub fn smt_shift(&self) -> u8 {
+        self.level(TopologyLevelType::Smt).map(|l| l.shift).unwrap_or(0)
+    }
+
+    /// Count of APIC ID bits used below the package ID.
+    pub fn package_shift(&self) -> u8 {
+        self.levels().last().map(|l| l.shift).unwrap_or(0)
+    }
+
+    /// Shift of APIC ID that gives ID of the domain of given level type,
+    /// e.g. die ID within package for `Die`. None if the level is not
+    /// enumerated.
+    pub fn domain_shift(&self, kind: TopologyLevelType) -> Option<u8> {
+        let levels = self.levels();
+        let index = levels.iter().position(|l| l.kind == kind)?;
+        Some(if index == 0 { 0 } else { levels[index - 1].shift })
+    }
+
+    /// Split given APIC ID into package, core and thread IDs.
+    pub fn decompose(&self, apic_id: u32) -> ApicIdParts {
+        let smt = self.smt_shift() as u32;
+        let pkg = self.package_shift() as u32;
+
+        ApicIdParts {
+            package : shr(apic_id, pkg),
+            core    : shr(apic_id & mask(pkg), smt),
+            thread  : apic_id & mask(smt),
+        }
+    }
+
+    /// ID of the domain of given level type that contains processor with
+    /// given APIC ID, relative to the package.
+    pub fn domain_id(&self, apic_id: u32, kind: TopologyLevelType)
+            -> Option<u32> {
+        let levels = self.levels();
+        let index = levels.iter().position(|l| l.kind == kind)?;
+        let low = self.domain_shift(kind)? as u32;
+        let high = levels[index].shift as u32;
+        Some(shr(apic_id & mask(high), low))
+    }
+
+    /// APIC ID of the processor that enumerated the topology split into
+    /// package, core and thread IDs.
+    pub fn current(&self) -> ApicIdParts {
+        self.decompose(self.x2apic_id)
+    }
+}
+
+/// Smallest count of bits that can hold `n` distinct values.
+fn ceil_log2(n: u32) -> u32 {
+    if n <= 1 {
+        0
+    } else {
+        32 - (n - 1).leading_zeros()
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    if bits >= 32 { !0 } else { (1 << bits) - 1 }
+}
+
+fn shr(val: u32, bits: u32) -> u32 {
+    if bits >= 32 { 0 } else { val >> bits }
+}
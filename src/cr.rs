@@ -1,4 +1,6 @@
+use core::arch::asm;
 use xsave::Mask as XsaveMask;
+use hw::{Backend, ControlReg};
 
 /// Trait means that structure represents a register in a processor.
 /// Changes are not immediately commited to the real register. This
@@ -7,12 +9,22 @@ use xsave::Mask as XsaveMask;
 pub trait Reg : Sized {
 
     /// Read the register and create the struct to represent the value.
+    ///
+    /// # Safety
+    /// Register must be accessible at the current privilege level.
     unsafe fn read() -> Self;
 
     /// Save the changes to real register.
+    ///
+    /// # Safety
+    /// Stored value must be valid for the register and keep the system
+    /// consistent.
     unsafe fn save(&self);
 
     /// Re-read the real register and overwrite current data in the structure.
+    ///
+    /// # Safety
+    /// Register must be accessible at the current privilege level.
     unsafe fn reset(&mut self) {
         *self = Self::read();
     }
@@ -41,24 +53,15 @@ pub struct Xcr0 {
 impl Reg for Cr3 {
 
     unsafe fn read() -> Self {
-        let data: u64;
-        asm!(
-            "mov    rax, cr3"
-        :   "={rax}" (data)
-        ::: "intel"
-        );
+        let data = ::hw::backend().read_cr(ControlReg::Cr3);
 
         Cr3 {
-            data: data
+            data
         }
     }
 
     unsafe fn save(&self) {
-        asm!(
-            "mov    cr3, rax"
-        ::  "{rax}" (self.data)
-        ::  "intel"
-        );
+        ::hw::backend().write_cr(ControlReg::Cr3, self.data);
     }
 
     unsafe fn reset(&mut self) {
@@ -78,6 +81,8 @@ impl Cr3 {
         self.data & (1 << 4) != 0
     }
 
+    /// # Safety
+    /// Changes caching of paging structures once saved.
     pub unsafe fn set_pwt(&mut self, val: bool) {
         let a = self.data & !(1 << 3);
         self.data = if val {
@@ -87,6 +92,8 @@ impl Cr3 {
         };
     }
 
+    /// # Safety
+    /// Changes caching of paging structures once saved.
     pub unsafe fn set_pcd(&mut self, val: bool) {
         let a = self.data & !(1 << 4);
         self.data = if val {
@@ -101,31 +108,30 @@ impl Cr3 {
         (self.data & !0x7FF) as usize
     }
 
+    /// # Safety
+    /// Address must point to valid PML4 table, aligned to 4 KiB, once
+    /// saved.
     pub unsafe fn set_addr(&mut self, addr: usize) {
         let a = self.data & 0x7FF;
         self.data = a + addr as u64;
     }
+
+    /// Raw register value including flags and PCID.
+    pub fn raw(&self) -> u64 {
+        self.data
+    }
 }
 
 impl Reg for Cr4 {
 
     unsafe fn read() -> Self {
-        let data: u64;
-        asm!(
-            "mov    rax, cr4"
-        :   "={rax}" (data)
-        ::: "intel"
-        );
+        let data = ::hw::backend().read_cr(ControlReg::Cr4);
 
         Cr4 { data }
     }
 
     unsafe fn save(&self) {
-        asm!(
-            "mov    cr4, rax"
-        ::  "{rax}" (self.data)
-        ::  "intel"
-        );
+        ::hw::backend().write_cr(ControlReg::Cr4, self.data);
     }
 }
 
@@ -146,12 +152,9 @@ macro_rules! impl_cr4_fn {
             self.data &= !Self::$cons;
         }
     );
-
-    ($cons:ident, $get:ident, $set:ident, $unset:ident) => {
-        impl_cr4_fn!($cons, $get, $set, $unset, "");
-    }
 }
 
+#[allow(dead_code)]
 impl Cr4 {
 
     const VME           : u64 = 1 << 0x00;
@@ -176,14 +179,17 @@ impl Cr4 {
 
     impl_cr4_fn!(
             VME                 , vme                   ,
-            enable_vme          , disable_vme           );
+            enable_vme          , disable_vme           ,
+            "Virtual-8086 mode extensions.");
     impl_cr4_fn!(
             PVI                 , pvi                   ,
-            enable_pvi          , disable_pvi           );
+            enable_pvi          , disable_pvi           ,
+            "Protected-mode virtual interrupts.");
     // TODO
     impl_cr4_fn!(
             OSXSAVE             , osxsave               ,
-            enable_osxsave      , disable_osxsave       );
+            enable_osxsave      , disable_osxsave       ,
+            "XSAVE and processor extended states enable.");
 }
 
 impl Xcr0 {
@@ -209,23 +215,51 @@ impl Xcr0 {
 impl Reg for Xcr0 {
 
     unsafe fn read() -> Self {
-        let val: u64;
-
+        let (a, d): (u32, u32);
         asm!(
-            "xgetbv"
-        :   "={rax}" (val)
-        :   "{rcx}" (0)
-        :: "volatile"
+            "xgetbv",
+            in("ecx") 0,
+            out("eax") a,
+            out("edx") d,
         );
+        let val = a as u64 | ((d as u64) << 32);
 
         Xcr0 { val }
     }
 
     unsafe fn save(&self) {
         asm!(
-            "xsetbv"
-        ::  "{rax}" (self.val), "{rcx}" (0)
-        ::  "volatile"
+            "xsetbv",
+            in("ecx") 0,
+            in("eax") self.val as u32,
+            in("edx") (self.val >> 32) as u32,
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    #[test]
+    fn cr4_update_reads_and_writes_register() {
+        let mock = Mock::new();
+        mock.set_cr(ControlReg::Cr4, Cr4::PAE | Cr4::VME);
+
+        with_backend(&mock, || unsafe {
+            let mut cr4 = Cr4::read();
+            cr4.enable_osxsave();
+            cr4.disable_vme();
+            cr4.save();
+        });
+
+        assert!(mock.log_eq(&[
+            CrRead  (ControlReg::Cr4, 0x0000_0021),
+            CrWrite (ControlReg::Cr4, 0x0004_0020),
+        ]), "{:?}", &*mock.log());
+        assert_eq!(mock.cr(ControlReg::Cr4), Cr4::PAE | Cr4::OSXSAVE);
+    }
+}
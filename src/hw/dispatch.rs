@@ -0,0 +1,90 @@
+use host_std::cell::Cell;
+use host_std::mem;
+use cpuid::Info as CpuidInfo;
+use super::{Backend, ControlReg, NATIVE};
+
+host_std::thread_local! {
+    static INSTALLED: Cell<*const (dyn Backend + 'static)>
+            = Cell::new(&NATIVE);
+}
+
+/// Backend that forwards all accesses to the one installed for the
+/// current thread.
+pub struct Dispatch;
+
+pub static DISPATCH: Dispatch = Dispatch;
+
+/// Restores previously installed backend when scope ends, even on panic.
+struct Restore(*const (dyn Backend + 'static));
+
+impl Drop for Restore {
+
+    fn drop(&mut self) {
+        INSTALLED.with(|b| b.set(self.0));
+    }
+}
+
+/// Install given backend for all hardware accesses of the current thread.
+pub fn set_backend(backend: &'static dyn Backend) {
+    INSTALLED.with(|b| b.set(backend));
+}
+
+/// Install back `Native` backend for the current thread.
+pub fn reset_backend() {
+    INSTALLED.with(|b| b.set(&NATIVE));
+}
+
+/// Run given function with the backend installed for the current thread.
+/// Previous backend is installed back when the function returns.
+pub fn with_backend<R, F: FnOnce() -> R>(backend: &dyn Backend, f: F) -> R {
+    // Backend is only reachable while it is borrowed by this call.
+    let ptr: *const (dyn Backend + 'static) = unsafe {
+        mem::transmute(backend as *const dyn Backend)
+    };
+    let _restore = Restore(INSTALLED.with(|b| b.replace(ptr)));
+    f()
+}
+
+macro_rules! forward {
+    ($($name:ident ($($arg:ident : $ty:ty),*) -> $ret:ty;)*) => {
+        $(
+        fn $name(&self, $($arg: $ty),*) -> $ret {
+            INSTALLED.with(|b| unsafe { (*b.get()).$name($($arg),*) })
+        }
+        )*
+    };
+}
+
+macro_rules! forward_unsafe {
+    ($($name:ident ($($arg:ident : $ty:ty),*) -> $ret:ty;)*) => {
+        $(
+        unsafe fn $name(&self, $($arg: $ty),*) -> $ret {
+            INSTALLED.with(|b| (*b.get()).$name($($arg),*))
+        }
+        )*
+    };
+}
+
+impl Backend for Dispatch {
+
+    forward! {
+        port_in_u8      (port: u16)                 -> u8;
+        port_in_u16     (port: u16)                 -> u16;
+        port_in_u32     (port: u16)                 -> u32;
+        port_out_u8     (port: u16, data: u8)       -> ();
+        port_out_u16    (port: u16, data: u16)      -> ();
+        port_out_u32    (port: u16, data: u32)      -> ();
+        cpuid           (leaf: u32, subleaf: u32)   -> CpuidInfo;
+        rdtsc           ()                          -> u64;
+        mfence          ()                          -> ();
+    }
+
+    forward_unsafe! {
+        rdmsr           (id: u32)                   -> u64;
+        wrmsr           (id: u32, val: u64)         -> ();
+        read_cr         (cr: ControlReg)            -> u64;
+        write_cr        (cr: ControlReg, val: u64)  -> ();
+        mmio_read32     (addr: usize)               -> u32;
+        mmio_write32    (addr: usize, val: u32)     -> ();
+    }
+}
@@ -0,0 +1,387 @@
+use core::cell::{Ref, RefCell};
+use cpuid::Info as CpuidInfo;
+use super::{Backend, ControlReg};
+
+/// Max count of accesses the mock can record.
+pub const LOG_CAPACITY: usize = 512;
+
+/// Max count of scripted port input or MMIO read values.
+const SCRIPT_CAPACITY: usize = 64;
+
+/// Max count of distinct ports, MSRs, MMIO addresses and CPUID leaves
+/// the mock can hold values for.
+const TABLE_CAPACITY: usize = 128;
+
+/// Single hardware access recorded by the mock.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Access {
+    PortIn8     (u16, u8),
+    PortIn16    (u16, u16),
+    PortIn32    (u16, u32),
+    PortOut8    (u16, u8),
+    PortOut16   (u16, u16),
+    PortOut32   (u16, u32),
+    MsrRead     (u32, u64),
+    MsrWrite    (u32, u64),
+    CrRead      (ControlReg, u64),
+    CrWrite     (ControlReg, u64),
+    Cpuid       (u32, u32),
+    Rdtsc       (u64),
+    Mfence,
+    MmioRead    (usize, u32),
+    MmioWrite   (usize, u32),
+}
+
+/// Fixed size key-value table.
+struct Table<K: Copy + PartialEq, V: Copy> {
+    entries : [Option<(K, V)>; TABLE_CAPACITY],
+}
+
+struct State {
+    log         : [Option<Access>; LOG_CAPACITY],
+    log_len     : usize,
+
+    port_script : [Option<(u16, u32)>; SCRIPT_CAPACITY],
+    mmio_script : [Option<(usize, u32)>; SCRIPT_CAPACITY],
+
+    ports       : Table<u16, u32>,
+    msrs        : Table<u32, u64>,
+    mmio        : Table<usize, u32>,
+    cpuid       : Table<(u32, u32), CpuidInfo>,
+    crs         : [u64; 16],
+
+    tsc         : u64,
+    tsc_step    : u64,
+}
+
+/// Backend that records every access and serves reads from values
+/// preset by the test.
+///
+/// Port and MMIO reads return scripted values first (in the order they
+/// were pushed for given port or address), then the last value written.
+/// MSR and control registers behave like plain memory. CPUID returns preset
+/// leaves or zeros. Unknown values read as zero.
+pub struct Mock {
+    state   : RefCell<State>,
+}
+
+impl<K: Copy + PartialEq, V: Copy> Table<K, V> {
+
+    fn new() -> Self {
+        Table { entries : [None; TABLE_CAPACITY] }
+    }
+
+    fn get(&self, key: K) -> Option<V> {
+        for e in self.entries.iter() {
+            if let Some((k, v)) = *e {
+                if k == key {
+                    return Some(v);
+                }
+            }
+        }
+        None
+    }
+
+    fn set(&mut self, key: K, val: V) {
+        let mut free = None;
+        for (i, e) in self.entries.iter_mut().enumerate() {
+            match *e {
+                Some((k, _)) if k == key => {
+                    *e = Some((key, val));
+                    return;
+                },
+                None if free.is_none() => free = Some(i),
+                _ => ()
+            }
+        }
+
+        match free {
+            Some(i) => self.entries[i] = Some((key, val)),
+            None    => panic!("mock value table is full")
+        }
+    }
+}
+
+impl State {
+
+    fn record(&mut self, access: Access) {
+        if self.log_len == LOG_CAPACITY {
+            panic!("mock access log is full");
+        }
+        self.log[self.log_len] = Some(access);
+        self.log_len += 1;
+    }
+
+    fn port_in(&mut self, port: u16) -> u32 {
+        match take_scripted(&mut self.port_script, port) {
+            Some(v) => v,
+            None    => self.ports.get(port).unwrap_or(0),
+        }
+    }
+
+    fn mmio_read(&mut self, addr: usize) -> u32 {
+        match take_scripted(&mut self.mmio_script, addr) {
+            Some(v) => v,
+            None    => self.mmio.get(addr).unwrap_or(0),
+        }
+    }
+}
+
+/// Remove first scripted value for given key and return it.
+fn take_scripted<K: Copy + PartialEq>(script: &mut [Option<(K, u32)>],
+        key: K) -> Option<u32> {
+    for e in script.iter_mut() {
+        if let Some((k, v)) = *e {
+            if k == key {
+                *e = None;
+                return Some(v);
+            }
+        }
+    }
+    None
+}
+
+/// Add value to the first free slot of the script.
+fn push_scripted<K: Copy>(script: &mut [Option<(K, u32)>], key: K,
+        val: u32) -> bool {
+    for e in script.iter_mut() {
+        if e.is_none() {
+            *e = Some((key, val));
+            return true;
+        }
+    }
+    false
+}
+
+impl Mock {
+
+    /// Create mock with empty log and no preset values.
+    pub fn new() -> Self {
+        let state = State {
+            log         : [None; LOG_CAPACITY],
+            log_len     : 0,
+
+            port_script : [None; SCRIPT_CAPACITY],
+            mmio_script : [None; SCRIPT_CAPACITY],
+
+            ports       : Table::new(),
+            msrs        : Table::new(),
+            mmio        : Table::new(),
+            cpuid       : Table::new(),
+            crs         : [0; 16],
+
+            tsc         : 0,
+            tsc_step    : 0,
+        };
+
+        Mock { state : RefCell::new(state) }
+    }
+
+    /// Recorded accesses in the order they happened.
+    pub fn log(&self) -> Ref<'_, [Option<Access>]> {
+        Ref::map(self.state.borrow(), |s| &s.log[..s.log_len])
+    }
+
+    /// Count of recorded accesses.
+    pub fn log_len(&self) -> usize {
+        self.state.borrow().log_len
+    }
+
+    /// Recorded access by its index.
+    pub fn access(&self, index: usize) -> Option<Access> {
+        let state = self.state.borrow();
+        if index < state.log_len {
+            state.log[index]
+        } else {
+            None
+        }
+    }
+
+    /// Check whether recorded accesses are exactly the given ones.
+    pub fn log_eq(&self, expected: &[Access]) -> bool {
+        let log = self.log();
+        log.len() == expected.len()
+                && log.iter().zip(expected.iter()).all(|(a, b)| *a == Some(*b))
+    }
+
+    /// Forget all recorded accesses. Preset values are kept.
+    pub fn clear_log(&self) {
+        let mut state = self.state.borrow_mut();
+        state.log = [None; LOG_CAPACITY];
+        state.log_len = 0;
+    }
+
+    /// Add value to be returned by one of the next reads of given port.
+    pub fn push_port_in(&self, port: u16, val: u32) {
+        let mut state = self.state.borrow_mut();
+        if !push_scripted(&mut state.port_script, port, val) {
+            panic!("mock port script is full");
+        }
+    }
+
+    /// Last value written to the port.
+    pub fn port(&self, port: u16) -> Option<u32> {
+        self.state.borrow().ports.get(port)
+    }
+
+    /// Preset value of MSR.
+    pub fn set_msr(&self, id: u32, val: u64) {
+        self.state.borrow_mut().msrs.set(id, val)
+    }
+
+    /// Current value of MSR.
+    pub fn msr(&self, id: u32) -> Option<u64> {
+        self.state.borrow().msrs.get(id)
+    }
+
+    /// Preset value of control register.
+    pub fn set_cr(&self, cr: ControlReg, val: u64) {
+        self.state.borrow_mut().crs[cr as usize] = val;
+    }
+
+    /// Current value of control register.
+    pub fn cr(&self, cr: ControlReg) -> u64 {
+        self.state.borrow().crs[cr as usize]
+    }
+
+    /// Preset value of memory mapped register.
+    pub fn set_mmio(&self, addr: usize, val: u32) {
+        self.state.borrow_mut().mmio.set(addr, val)
+    }
+
+    /// Add value to be returned by one of the next reads of given memory
+    /// mapped register. Useful for index/data register pairs where the
+    /// same address reads different registers.
+    pub fn push_mmio_read(&self, addr: usize, val: u32) {
+        let mut state = self.state.borrow_mut();
+        if !push_scripted(&mut state.mmio_script, addr, val) {
+            panic!("mock MMIO script is full");
+        }
+    }
+
+    /// Current value of memory mapped register.
+    pub fn mmio(&self, addr: usize) -> Option<u32> {
+        self.state.borrow().mmio.get(addr)
+    }
+
+    /// Preset Time Stamp Counter value and the amount it is incremented
+    /// by after each read.
+    pub fn set_tsc(&self, val: u64, step: u64) {
+        let mut state = self.state.borrow_mut();
+        state.tsc = val;
+        state.tsc_step = step;
+    }
+
+    /// Preset CPUID result for given leaf and subleaf.
+    pub fn set_cpuid(&self, leaf: u32, subleaf: u32, info: CpuidInfo) {
+        self.state.borrow_mut().cpuid.set((leaf, subleaf), info)
+    }
+}
+
+impl Default for Mock {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for Mock {
+
+    fn port_in_u8(&self, port: u16) -> u8 {
+        let mut state = self.state.borrow_mut();
+        let val = state.port_in(port) as u8;
+        state.record(Access::PortIn8(port, val));
+        val
+    }
+
+    fn port_in_u16(&self, port: u16) -> u16 {
+        let mut state = self.state.borrow_mut();
+        let val = state.port_in(port) as u16;
+        state.record(Access::PortIn16(port, val));
+        val
+    }
+
+    fn port_in_u32(&self, port: u16) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let val = state.port_in(port);
+        state.record(Access::PortIn32(port, val));
+        val
+    }
+
+    fn port_out_u8(&self, port: u16, data: u8) {
+        let mut state = self.state.borrow_mut();
+        state.ports.set(port, data as u32);
+        state.record(Access::PortOut8(port, data));
+    }
+
+    fn port_out_u16(&self, port: u16, data: u16) {
+        let mut state = self.state.borrow_mut();
+        state.ports.set(port, data as u32);
+        state.record(Access::PortOut16(port, data));
+    }
+
+    fn port_out_u32(&self, port: u16, data: u32) {
+        let mut state = self.state.borrow_mut();
+        state.ports.set(port, data);
+        state.record(Access::PortOut32(port, data));
+    }
+
+    unsafe fn rdmsr(&self, id: u32) -> u64 {
+        let mut state = self.state.borrow_mut();
+        let val = state.msrs.get(id).unwrap_or(0);
+        state.record(Access::MsrRead(id, val));
+        val
+    }
+
+    unsafe fn wrmsr(&self, id: u32, val: u64) {
+        let mut state = self.state.borrow_mut();
+        state.msrs.set(id, val);
+        state.record(Access::MsrWrite(id, val));
+    }
+
+    unsafe fn read_cr(&self, cr: ControlReg) -> u64 {
+        let mut state = self.state.borrow_mut();
+        let val = state.crs[cr as usize];
+        state.record(Access::CrRead(cr, val));
+        val
+    }
+
+    unsafe fn write_cr(&self, cr: ControlReg, val: u64) {
+        let mut state = self.state.borrow_mut();
+        state.crs[cr as usize] = val;
+        state.record(Access::CrWrite(cr, val));
+    }
+
+    fn cpuid(&self, leaf: u32, subleaf: u32) -> CpuidInfo {
+        let mut state = self.state.borrow_mut();
+        let zero = CpuidInfo { eax: 0, ebx: 0, ecx: 0, edx: 0 };
+        let val = state.cpuid.get((leaf, subleaf)).unwrap_or(zero);
+        state.record(Access::Cpuid(leaf, subleaf));
+        val
+    }
+
+    fn rdtsc(&self) -> u64 {
+        let mut state = self.state.borrow_mut();
+        let val = state.tsc;
+        state.tsc = val.wrapping_add(state.tsc_step);
+        state.record(Access::Rdtsc(val));
+        val
+    }
+
+    fn mfence(&self) {
+        self.state.borrow_mut().record(Access::Mfence);
+    }
+
+    unsafe fn mmio_read32(&self, addr: usize) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let val = state.mmio_read(addr);
+        state.record(Access::MmioRead(addr, val));
+        val
+    }
+
+    unsafe fn mmio_write32(&self, addr: usize, val: u32) {
+        let mut state = self.state.borrow_mut();
+        state.mmio.set(addr, val);
+        state.record(Access::MmioWrite(addr, val));
+    }
+}
@@ -0,0 +1,288 @@
+use core::arch::asm;
+use cpuid::Info as CpuidInfo;
+
+/// Recording and scriptable backend for tests on the host.
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+/// Control register selector used by the backend.
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ControlReg {
+    Cr0 = 0,
+    Cr2 = 2,
+    Cr3 = 3,
+    Cr4 = 4,
+    Cr8 = 8,
+}
+
+/// Hardware access backend. All privileged operations of the crate
+/// (port I/O, MSRs, control registers, CPUID, TSC and APIC MMIO) go through
+/// `backend()`. It is `Native` which executes the real instructions unless
+/// the crate is built for tests or with `mock` feature, in which case
+/// other backend can be installed for the current thread.
+pub trait Backend {
+
+    fn port_in_u8(&self, port: u16) -> u8;
+
+    fn port_in_u16(&self, port: u16) -> u16;
+
+    fn port_in_u32(&self, port: u16) -> u32;
+
+    fn port_out_u8(&self, port: u16, data: u8);
+
+    fn port_out_u16(&self, port: u16, data: u16);
+
+    fn port_out_u32(&self, port: u16, data: u32);
+
+    /// Read MSR with given ID.
+    ///
+    /// # Safety
+    /// MSR must exist, otherwise General Protection fault is raised.
+    unsafe fn rdmsr(&self, id: u32) -> u64;
+
+    /// Write MSR with given ID.
+    ///
+    /// # Safety
+    /// MSR must exist and the value must be valid for it.
+    unsafe fn wrmsr(&self, id: u32, val: u64);
+
+    /// Read control register.
+    ///
+    /// # Safety
+    /// Must be executed in ring 0.
+    unsafe fn read_cr(&self, cr: ControlReg) -> u64;
+
+    /// Write control register.
+    ///
+    /// # Safety
+    /// Changing control registers may break the whole system.
+    unsafe fn write_cr(&self, cr: ControlReg, val: u64);
+
+    /// Run CPUID with given leaf in EAX and subleaf in ECX.
+    fn cpuid(&self, leaf: u32, subleaf: u32) -> CpuidInfo;
+
+    /// Read Time Stamp Counter.
+    fn rdtsc(&self) -> u64;
+
+    /// Serialize all memory loads and stores with MFENCE. Is needed e.g.
+    /// between xAPIC MMIO and MSR writes, which are not ordered.
+    fn mfence(&self);
+
+    /// Read 32-bit memory mapped register.
+    ///
+    /// # Safety
+    /// Address must point to valid memory mapped register.
+    unsafe fn mmio_read32(&self, addr: usize) -> u32;
+
+    /// Write 32-bit memory mapped register.
+    ///
+    /// # Safety
+    /// Address must point to valid memory mapped register.
+    unsafe fn mmio_write32(&self, addr: usize, val: u32);
+}
+
+/// Backend that executes real instructions.
+pub struct Native;
+
+static NATIVE: Native = Native;
+
+/// Backend that all hardware accesses of the crate go through. Without
+/// `mock` feature this is always `Native` so accesses are direct calls.
+#[cfg(not(any(test, feature = "mock")))]
+pub type Current = Native;
+
+/// Backend that all hardware accesses of the crate go through. Without
+/// `mock` feature this is always `Native` so accesses are direct calls.
+#[cfg(any(test, feature = "mock"))]
+pub type Current = self::dispatch::Dispatch;
+
+/// Module with per-thread backend switching for tests.
+#[cfg(any(test, feature = "mock"))]
+mod dispatch;
+#[cfg(any(test, feature = "mock"))]
+pub use self::dispatch::{set_backend, reset_backend, with_backend};
+
+/// Currently installed hardware access backend.
+#[cfg(not(any(test, feature = "mock")))]
+#[inline(always)]
+pub fn backend() -> &'static Current {
+    &NATIVE
+}
+
+/// Currently installed hardware access backend.
+#[cfg(any(test, feature = "mock"))]
+#[inline(always)]
+pub fn backend() -> &'static Current {
+    &self::dispatch::DISPATCH
+}
+
+impl Backend for Native {
+
+    #[inline(always)]
+    fn port_in_u8(&self, port: u16) -> u8 {
+        let result;
+        unsafe { asm!(
+            "in     al, dx",
+            out("al") result,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags),
+        ); }
+        result
+    }
+
+    #[inline(always)]
+    fn port_in_u16(&self, port: u16) -> u16 {
+        let result;
+        unsafe { asm!(
+            "in     ax, dx",
+            out("ax") result,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags),
+        ); }
+        result
+    }
+
+    #[inline(always)]
+    fn port_in_u32(&self, port: u16) -> u32 {
+        let result;
+        unsafe { asm!(
+            "in     eax, dx",
+            out("eax") result,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags),
+        ); }
+        result
+    }
+
+    #[inline(always)]
+    fn port_out_u8(&self, port: u16, data: u8) {
+        unsafe { asm!(
+            "out    dx, al",
+            in("al") data,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags),
+        ); }
+    }
+
+    #[inline(always)]
+    fn port_out_u16(&self, port: u16, data: u16) {
+        unsafe { asm!(
+            "out    dx, ax",
+            in("ax") data,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags),
+        ); }
+    }
+
+    #[inline(always)]
+    fn port_out_u32(&self, port: u16, data: u32) {
+        unsafe { asm!(
+            "out    dx, eax",
+            in("eax") data,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags),
+        ); }
+    }
+
+    #[inline(always)]
+    unsafe fn rdmsr(&self, id: u32) -> u64 {
+        let (a, d): (u32, u32);
+        asm!(
+            "rdmsr",
+            out("eax") a,
+            out("edx") d,
+            in("ecx") id,
+            options(nostack, preserves_flags),
+        );
+
+        a as u64 | ((d as u64) << 32)
+    }
+
+    #[inline(always)]
+    unsafe fn wrmsr(&self, id: u32, val: u64) {
+        let a = val as u32;
+        let d = (val >> 32) as u32;
+        asm!(
+            "wrmsr",
+            in("eax") a,
+            in("edx") d,
+            in("ecx") id,
+            options(nostack, preserves_flags),
+        );
+    }
+
+    unsafe fn read_cr(&self, cr: ControlReg) -> u64 {
+        use self::ControlReg::*;
+
+        let data: u64;
+        match cr {
+            Cr0 => asm!("mov    {}, cr0", out(reg) data, options(nomem)),
+            Cr2 => asm!("mov    {}, cr2", out(reg) data, options(nomem)),
+            Cr3 => asm!("mov    {}, cr3", out(reg) data, options(nomem)),
+            Cr4 => asm!("mov    {}, cr4", out(reg) data, options(nomem)),
+            Cr8 => asm!("mov    {}, cr8", out(reg) data, options(nomem)),
+        }
+        data
+    }
+
+    unsafe fn write_cr(&self, cr: ControlReg, val: u64) {
+        use self::ControlReg::*;
+
+        match cr {
+            Cr0 => asm!("mov    cr0, {}", in(reg) val),
+            Cr2 => asm!("mov    cr2, {}", in(reg) val),
+            Cr3 => asm!("mov    cr3, {}", in(reg) val),
+            Cr4 => asm!("mov    cr4, {}", in(reg) val),
+            Cr8 => asm!("mov    cr8, {}", in(reg) val),
+        }
+    }
+
+    #[inline(always)]
+    fn cpuid(&self, leaf: u32, subleaf: u32) -> CpuidInfo {
+        // RBX is reserved by LLVM so it is saved in other register.
+        let (a, b, c, d);
+
+        unsafe { asm!(
+            "mov    {0:r}, rbx",
+            "cpuid",
+            "xchg   {0:r}, rbx",
+            out(reg) b,
+            inout("eax") leaf => a,
+            inout("ecx") subleaf => c,
+            out("edx") d,
+            options(nomem, nostack, preserves_flags),
+        ); }
+
+        CpuidInfo { eax:a, ebx:b, ecx:c, edx:d }
+    }
+
+    #[inline(always)]
+    fn rdtsc(&self) -> u64 {
+        let (a, d): (u32, u32);
+
+        unsafe { asm!(
+            "rdtsc",
+            out("eax") a,
+            out("edx") d,
+            options(nomem, nostack, preserves_flags),
+        ); }
+
+        a as u64 | ((d as u64) << 32)
+    }
+
+    #[inline(always)]
+    fn mfence(&self) {
+        unsafe { asm!("mfence", options(nostack, preserves_flags)); }
+    }
+
+    #[inline(always)]
+    unsafe fn mmio_read32(&self, addr: usize) -> u32 {
+        ::core::ptr::read_volatile(addr as *const u32)
+    }
+
+    #[inline(always)]
+    unsafe fn mmio_write32(&self, addr: usize, val: u32) {
+        ::core::ptr::write_volatile(addr as *mut u32, val)
+    }
+}
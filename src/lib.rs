@@ -2,12 +2,12 @@
 #![crate_type = "rlib"]
 #![crate_name = "asm_x86_64"]
 
-#![feature(asm)]
-#![feature(no_core)]
-
 #[macro_use]
 extern crate new_bitflags;
 
+#[cfg(any(test, feature = "mock"))]
+extern crate std as host_std;
+
 /// This module simulates 'std' library for extern crates.
 mod std {
 pub use core::*;
@@ -25,6 +25,9 @@ pub mod mem;
 /// Module that contains operations related to Model Specific Registers.
 pub mod msr;
 
+/// Hardware access backend used by all privileged operations.
+pub mod hw;
+
 /// Functions to send data through the processor ports.
 pub mod port;
 
@@ -50,5 +53,17 @@ pub mod cr;
 /// Programmable Interval Timer.
 pub mod pit;
 
+/// PC speaker driven by PIT channel 2.
+pub mod speaker;
+
+/// TSC and local APIC timer frequency calibration.
+pub mod calibration;
+
+/// Application processor bootstrap.
+pub mod smp;
+
+/// Paravirtual clocks and shared areas of KVM and Hyper-V.
+pub mod pvclock;
+
 /// XSAVE instruction module.
 pub mod xsave;
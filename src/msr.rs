@@ -1,6 +1,9 @@
 use xsave::Mask as XsaveMask;
+use core::sync::atomic::{AtomicU32, Ordering};
+use hw::Backend;
 
 /// Info read from MSR.
+#[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Info {
     pub eax     : u32,
@@ -13,64 +16,86 @@ impl Info {
     /// some MSRs may not be defined and so this call will cause
     /// General Protection fault. Ensure that MSR with given ID actually
     /// exists.
+    ///
+    /// # Safety
+    /// MSR with given ID must exist.
     pub unsafe fn read_by_id(id: u32) -> Info {
-        let (a, d);
-        asm!(
-            "rdmsr"
-            : "={eax}"(a), "={edx}"(d)
-            : "{ecx}"(id)
-        );
-
-        Info { eax:a, edx:d }
+        let val = ::hw::backend().rdmsr(id);
+        Info { eax:val as u32, edx:(val >> 32) as u32 }
     }
 
+    /// # Safety
+    /// MSR with given ID must exist and the value must be valid for it.
     pub unsafe fn write_by_id(&self, id: u32) {
-        asm!(
-            "wrmsr"
-            : // No output
-            : "{eax}"(self.eax), "{edx}"(self.edx), "{ecx}"(id)
-        );
+        let val = self.eax as u64 | ((self.edx as u64) << 32);
+        ::hw::backend().wrmsr(id, val);
     }
 
     /// See 'read_by_id'. Note, that this function generally must not be used.
     /// It is more appropriate to use relevant 'read' function in the structure
     /// that represents the desired MSR.
+    ///
+    /// # Safety
+    /// MSR must exist.
     pub unsafe fn read(msr: Msr) -> Info {
         Self::read_by_id(msr as u32)
     }
 
+    /// # Safety
+    /// MSR must exist and the value must be valid for it.
     pub unsafe fn write(&self, msr: Msr) {
         Self::write_by_id(self, msr as u32)
     }
 }
 
+/// Read Time Stamp Counter with RDTSC instruction.
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    ::hw::backend().rdtsc()
+}
+
 /// Model Specific Register ID list.
 #[repr(u32)]
 pub enum Msr {
     ApicBase        = 0x01B,
     TscDeadline     = 0x6E0,
     Xss             = 0xDA0,
+
+    KvmSystemTime   = 0x4B56_4D01,
+    KvmStealTime    = 0x4B56_4D03,
+    KvmPvEoiEn      = 0x4B56_4D04,
+
+    HvGuestOsId     = 0x4000_0000,
+    HvHypercall     = 0x4000_0001,
+    HvVpIndex       = 0x4000_0002,
+    HvTimeRefCount  = 0x4000_0020,
+    HvReferenceTsc  = 0x4000_0021,
+    HvTscFrequency  = 0x4000_0022,
+    HvApicFrequency = 0x4000_0023,
+    HvEoi           = 0x4000_0070,
 }
 
 macro_rules! derive_info {
     ($x:ident) => (
+        #[repr(C)]
         #[derive(Clone, Copy)]
         pub struct $x {
             eax     : u32,
             edx     : u32,
         }
 
-        impl Into<Info> for $x {
+        impl From<$x> for Info {
 
-            fn into(self) -> Info {
-                Info { eax: self.eax, edx: self.edx }
+            fn from(v: $x) -> Info {
+                Info { eax: v.eax, edx: v.edx }
             }
         }
 
         impl AsRef<Info> for $x {
 
             fn as_ref(&self) -> &Info {
-                unsafe { ::core::mem::transmute_copy(self) }
+                // Both structures have the same layout.
+                unsafe { &*(self as *const Self as *const Info) }
             }
         }
 
@@ -79,12 +104,17 @@ macro_rules! derive_info {
             /// Read this given MSR. Note that if it is not defined
             /// in the processor, General Protection fault will be
             /// rised. You need to ensure that processor supports this MSR.
+            ///
+            /// # Safety
+            /// Processor must support this MSR.
             pub unsafe fn read() -> Self {
                 let info = Info::read(Msr::$x);
                 // Convert the Info structure to correspond to given MSR.
-                ::core::mem::transmute(info)
+                ::core::mem::transmute::<Info, Self>(info)
             }
 
+            /// # Safety
+            /// Processor must support this MSR and the value must be valid.
             pub unsafe fn write(&self)  {
                 let info: &Info = self.as_ref();
                 info.write(Msr::$x);
@@ -96,47 +126,120 @@ macro_rules! derive_info {
 derive_info!(ApicBase);
 derive_info!(TscDeadline);
 derive_info!(Xss);
+derive_info!(KvmSystemTime);
+derive_info!(KvmStealTime);
+derive_info!(KvmPvEoiEn);
+derive_info!(HvGuestOsId);
+derive_info!(HvHypercall);
+derive_info!(HvVpIndex);
+derive_info!(HvTimeRefCount);
+derive_info!(HvReferenceTsc);
+derive_info!(HvTscFrequency);
+derive_info!(HvApicFrequency);
+derive_info!(HvEoi);
+
+/// Implement accessors of MSR that holds guest physical address of
+/// a shared memory area with given alignment and enable flag in bit 0.
+macro_rules! pv_area_impl {
+    ($x:ident, $align:expr) => (
+        impl $x {
+
+            /// Required alignment of the area.
+            pub const ALIGN: u64 = $align;
+
+            const ENABLE: u32 = 1 << 0;
+
+            /// Physical address of the area.
+            pub fn address(&self) -> u64 {
+                let val = self.eax as u64 | ((self.edx as u64) << 32);
+                val & !(Self::ALIGN - 1)
+            }
+
+            pub fn enabled(&self) -> bool {
+                self.eax & Self::ENABLE != 0
+            }
+
+            /// Set physical address of the area and enable it on next
+            /// write. Returns false if the address is not aligned.
+            pub fn enable(&mut self, addr: u64) -> bool {
+                if addr & (Self::ALIGN - 1) != 0 {
+                    return false;
+                }
+                self.eax = (addr >> 00) as u32 | Self::ENABLE;
+                self.edx = (addr >> 32) as u32;
+                true
+            }
+
+            /// Disable the area on next write.
+            pub fn disable(&mut self) {
+                self.eax &= !Self::ENABLE;
+            }
+        }
+    );
+}
+
+pv_area_impl!(KvmSystemTime, 4);
+pv_area_impl!(KvmStealTime, 64);
+pv_area_impl!(KvmPvEoiEn, 4);
+pv_area_impl!(HvHypercall, 4096);
+pv_area_impl!(HvReferenceTsc, 4096);
 
 impl ApicBase {
 
+    const BSP           : u32 = 1 << 8;
+    const EXTD          : u32 = 1 << 10;
+    const EN            : u32 = 1 << 11;
+
+    /// Mask of APIC base address bits in the MSR.
+    const BASE_MASK     : u64 = 0x000F_FFFF_FFFF_F000;
+
     /// Whether this processor (core) is a Bootstrap Processor.
     pub fn bsp(&self) -> bool {
-        self.edx & 0b0000_0000_0000_0000_0000_0001_0000_0000 != 0
+        self.eax & Self::BSP != 0
     }
 
     pub fn x2apic_enabled(&self) -> bool {
-        self.edx & 0b0000_0000_0000_0000_0000_0100_0000_0000 != 0
+        self.eax & Self::EXTD != 0
+    }
+
+    /// Switch local APIC to x2APIC mode on next write. APIC must be
+    /// globally enabled too, so this sets the enable flag as well.
+    ///
+    /// # Safety
+    /// Caller must ensure x2APIC is supported. Once enabled, x2APIC mode
+    /// can only be left by disabling the APIC globally.
+    pub unsafe fn x2apic_enable(&mut self) {
+        self.eax |= Self::EXTD | Self::EN;
     }
 
     pub fn apic_global_enabled(&self) -> bool {
-        self.edx & 0b0000_0000_0000_0000_0000_1000_0000_0000 != 0
+        self.eax & Self::EN != 0
     }
 
     pub fn apic_global_enable(&mut self) {
-        self.edx |= 0b0000_0000_0000_0000_0000_1000_0000_0000;
+        self.eax |= Self::EN;
     }
 
+    /// Disable APIC globally. This also leaves x2APIC mode.
     pub fn apic_global_disable(&mut self) {
-        self.edx &= 0b1111_1111_1111_1111_1111_0111_1111_1111;
+        self.eax &= !(Self::EN | Self::EXTD);
     }
 
     pub fn apic_base(&self) -> u64 {
         let rdx = self.edx as u64;
         let rax = self.eax as u64;
-        ((rdx & 0xFFFFF000) >> 12) | (rax << 20)
+        (rax | (rdx << 32)) & Self::BASE_MASK
     }
 
-     pub fn set_apic_base(&mut self, base: u64) {
-         let d = (base & 0xFFFFF000) as u32;
-         let a = (base >> 20)        as u32;
+    pub fn set_apic_base(&mut self, base: u64) {
+        let base = base & Self::BASE_MASK;
 
-         // Clean corresponding bits before assigning them new values.
-         self.eax = 0;
-         self.edx &= 0x00000FFF;
+        // Clean corresponding bits before assigning them new values.
+        self.eax &= 0x0000_0FFF;
 
-         self.eax = a;
-         self.edx = d;
-     }
+        self.eax |= base as u32;
+        self.edx  = (base >> 32) as u32;
+    }
 }
 
 impl TscDeadline {
@@ -150,7 +253,7 @@ impl TscDeadline {
 
     /// Set timestamp.
     pub fn set(&mut self, timestamp: u64) {
-        self.eax = (timestamp >> 00) as u32;
+        self.eax = timestamp as u32;
         self.edx = (timestamp >> 32) as u32;
     }
 
@@ -161,7 +264,7 @@ impl TscDeadline {
 
     /// Current MSR value.
     pub fn value(&self) -> u64 {
-        self.eax as u64 + (self.edx as u64) << 32
+        self.eax as u64 | ((self.edx as u64) << 32)
     }
 }
 
@@ -174,7 +277,7 @@ impl Xss {
     /// cause system error when it contains invalid value.
     pub unsafe fn set_mask(&mut self, mask: XsaveMask) {
         let val: u64 = mask.into();
-        self.eax = (val >> 00) as u32;
+        self.eax = val as u32;
         self.edx = (val >> 32) as u32;
     }
 
@@ -190,3 +293,70 @@ impl Xss {
         XsaveMask::from(val)
     }
 }
+
+impl KvmPvEoiEn {
+
+    /// Try to complete end of interrupt through the paravirtual EOI flag
+    /// registered with this MSR. Returns true if the hypervisor allowed
+    /// to skip the EOI, otherwise EOI must be sent to local APIC.
+    pub fn try_eoi(flag: &AtomicU32) -> bool {
+        flag.fetch_and(!1, Ordering::SeqCst) & 1 != 0
+    }
+}
+
+impl HvGuestOsId {
+
+    /// Guest OS identity value. Must be set before hypercall page
+    /// is enabled.
+    pub fn value(&self) -> u64 {
+        self.eax as u64 | ((self.edx as u64) << 32)
+    }
+
+    pub fn set(&mut self, id: u64) {
+        self.eax = id as u32;
+        self.edx = (id >> 32) as u32;
+    }
+}
+
+impl HvVpIndex {
+
+    /// Index of current virtual processor used in hypercalls.
+    pub fn index(&self) -> u32 {
+        self.eax
+    }
+}
+
+impl HvTimeRefCount {
+
+    /// Partition reference time in 100 ns units.
+    pub fn value(&self) -> u64 {
+        self.eax as u64 | ((self.edx as u64) << 32)
+    }
+}
+
+impl HvTscFrequency {
+
+    pub fn hz(&self) -> u64 {
+        self.eax as u64 | ((self.edx as u64) << 32)
+    }
+}
+
+impl HvApicFrequency {
+
+    /// Local APIC timer frequency in Hz.
+    pub fn hz(&self) -> u64 {
+        self.eax as u64 | ((self.edx as u64) << 32)
+    }
+}
+
+impl HvEoi {
+
+    /// Signal end of interrupt to local APIC through Hyper-V MSR. It is
+    /// faster than MMIO access in xAPIC mode.
+    ///
+    /// # Safety
+    /// Hyper-V APIC MSRs must be available.
+    pub unsafe fn signal() {
+        Info { eax: 0, edx: 0 }.write(Msr::HvEoi)
+    }
+}
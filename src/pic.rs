@@ -1,7 +1,387 @@
+use port::Port;
+use apic::TriggerMode;
+
+/// Master PIC command port.
+const MASTER_CMD: u16 = 0x20;
+
+/// Master PIC data port.
+const MASTER_DAT: u16 = 0x21;
+
+/// Slave PIC command port.
+const SLAVE_CMD: u16 = 0xA0;
+
+/// Slave PIC data port.
+const SLAVE_DAT: u16 = 0xA1;
+
+/// Edge/Level Control Register for master PIC IRQs.
+const ELCR_MASTER: u16 = 0x4D0;
+
+/// Edge/Level Control Register for slave PIC IRQs.
+const ELCR_SLAVE: u16 = 0x4D1;
+
+/// IRQs that are always edge triggered: timer, keyboard, cascade,
+/// RTC and FPU.
+const ELCR_EDGE_ONLY: u16 = (1 << 0) | (1 << 1) | (1 << 2) | (1 << 8)
+        | (1 << 13);
+
+/// Master PIC input the slave PIC is cascaded to.
+const CASCADE_IRQ: u8 = 2;
+
+/// Non-specific EOI command (OCW2).
+const OCW2_EOI: u8 = 0x20;
+
+/// Specific EOI command (OCW2). Lower 3 bits select IRQ level.
+const OCW2_SPECIFIC_EOI: u8 = 0x60;
+
+/// OCW3 command to read Interrupt Request Register on next read.
+const OCW3_READ_IRR: u8 = 0x0A;
+
+/// OCW3 command to read In-Service Register on next read.
+const OCW3_READ_ISR: u8 = 0x0B;
+
 /// PIC interface.
 pub struct Pic {
 }
 
+/// Options of PIC initialization sequence that are sent in ICW4.
+#[derive(Default, Clone, Copy)]
+pub struct InitMode {
+
+    /// Automatically send EOI on interrupt acknowledge.
+    pub auto_eoi    : bool,
+
+    /// Buffered mode. Master/slave role is then selected by ICW4 instead
+    /// of SP/EN pin.
+    pub buffered    : bool,
+}
+
+/// Initialization Command Word 1. Is sent to command port and starts
+/// initialization sequence.
+#[derive(Clone, Copy)]
+pub struct Icw1 {
+    val     : u8,
+}
+
+/// Initialization Command Word 2. Holds interrupt vector offset.
+#[derive(Clone, Copy)]
+pub struct Icw2 {
+    val     : u8,
+}
+
+/// Initialization Command Word 3. For master PIC it is a bit mask of
+/// inputs with slave PICs attached. For slave PIC it is its cascade
+/// identity.
+#[derive(Clone, Copy)]
+pub struct Icw3 {
+    val     : u8,
+}
+
+/// Initialization Command Word 4. Holds operating mode options.
+#[derive(Clone, Copy)]
+pub struct Icw4 {
+    val     : u8,
+}
+
+/// Initialization sequence of cascaded master and slave PICs.
+#[derive(Clone, Copy)]
+pub struct InitSequence {
+    pub icw1        : Icw1,
+
+    pub master_icw2 : Icw2,
+    pub slave_icw2  : Icw2,
+
+    pub master_icw3 : Icw3,
+    pub slave_icw3  : Icw3,
+
+    pub master_icw4 : Icw4,
+    pub slave_icw4  : Icw4,
+}
+
+/// Port writes of an initialization sequence. Length depends on whether
+/// slave PIC and ICW4 are used.
+#[derive(Clone, Copy)]
+pub struct PortWrites {
+    writes      : [(u16, u8); 8],
+    len         : usize,
+}
+
+/// Set or clear given bits in the value.
+fn with_bit(val: u8, bit: u8, v: bool) -> u8 {
+    if v {
+        val | bit
+    } else {
+        val & !bit
+    }
+}
+
+impl InitMode {
+
+    /// ICW4 value for this mode for master or slave PIC.
+    pub fn icw4(&self, master: bool) -> Icw4 {
+        let icw4 = Icw4::new().with_auto_eoi(self.auto_eoi);
+
+        match (self.buffered, master) {
+            (false, _    ) => icw4,
+            (true , true ) => icw4.with_buffered_master(),
+            (true , false) => icw4.with_buffered_slave(),
+        }
+    }
+}
+
+impl Default for Icw1 {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Icw1 {
+
+    const ICW4      : u8 = 1 << 0;
+    const SINGLE    : u8 = 1 << 1;
+    const LEVEL     : u8 = 1 << 3;
+    const INIT      : u8 = 1 << 4;
+
+    /// ICW1 for cascade mode, edge triggered inputs, with ICW4 to follow.
+    pub fn new() -> Self {
+        Icw1 { val : Self::INIT | Self::ICW4 }
+    }
+
+    /// Whether ICW4 will be sent.
+    pub fn icw4_needed(&self) -> bool {
+        self.val & Self::ICW4 != 0
+    }
+
+    /// Set whether ICW4 will be sent.
+    pub fn with_icw4_needed(mut self, v: bool) -> Self {
+        self.val = with_bit(self.val, Self::ICW4, v);
+        self
+    }
+
+    /// Whether single PIC is used without cascading. ICW3 is not sent
+    /// in this mode.
+    pub fn single(&self) -> bool {
+        self.val & Self::SINGLE != 0
+    }
+
+    /// Set whether single PIC is used without cascading.
+    pub fn with_single(mut self, v: bool) -> Self {
+        self.val = with_bit(self.val, Self::SINGLE, v);
+        self
+    }
+
+    /// Whether inputs are level triggered.
+    pub fn level_triggered(&self) -> bool {
+        self.val & Self::LEVEL != 0
+    }
+
+    /// Set whether inputs are level triggered.
+    pub fn with_level_triggered(mut self, v: bool) -> Self {
+        self.val = with_bit(self.val, Self::LEVEL, v);
+        self
+    }
+}
+
+impl Icw2 {
+
+    /// ICW2 with given vector offset. In 8086 mode lower 3 bits are
+    /// taken from IRQ number, so None is returned if the offset is not
+    /// a multiple of 8.
+    pub fn new(offset: u8) -> Option<Self> {
+        if offset & 0x07 != 0 {
+            None
+        } else {
+            Some(Icw2 { val : offset })
+        }
+    }
+
+    /// Interrupt vector offset.
+    pub fn offset(&self) -> u8 {
+        self.val
+    }
+}
+
+impl Icw3 {
+
+    /// ICW3 for master PIC with slave PICs attached to inputs set in
+    /// given mask.
+    pub fn master(slaves: u8) -> Self {
+        Icw3 { val : slaves }
+    }
+
+    /// ICW3 for slave PIC attached to given master input.
+    pub fn slave(cascade_id: u8) -> Self {
+        Icw3 { val : cascade_id & 0b111 }
+    }
+}
+
+impl Default for Icw4 {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Icw4 {
+
+    const X86       : u8 = 1 << 0;
+    const AUTO_EOI  : u8 = 1 << 1;
+    const BUF_MST   : u8 = 1 << 2;
+    const BUFFERED  : u8 = 1 << 3;
+    const SFNM      : u8 = 1 << 4;
+
+    /// ICW4 for 8086 mode with normal EOI and non-buffered mode.
+    pub fn new() -> Self {
+        Icw4 { val : Self::X86 }
+    }
+
+    /// Whether EOI is sent automatically on interrupt acknowledge.
+    pub fn auto_eoi(&self) -> bool {
+        self.val & Self::AUTO_EOI != 0
+    }
+
+    /// Set whether EOI is sent automatically on interrupt acknowledge.
+    pub fn with_auto_eoi(mut self, v: bool) -> Self {
+        self.val = with_bit(self.val, Self::AUTO_EOI, v);
+        self
+    }
+
+    /// Whether buffered mode is on.
+    pub fn buffered(&self) -> bool {
+        self.val & Self::BUFFERED != 0
+    }
+
+    /// Whether PIC acts as master in buffered mode.
+    pub fn buffered_master(&self) -> bool {
+        self.val & Self::BUF_MST != 0
+    }
+
+    /// Enable buffered mode for master PIC.
+    pub fn with_buffered_master(mut self) -> Self {
+        self.val |= Self::BUFFERED | Self::BUF_MST;
+        self
+    }
+
+    /// Enable buffered mode for slave PIC.
+    pub fn with_buffered_slave(mut self) -> Self {
+        self.val = self.val & !Self::BUF_MST | Self::BUFFERED;
+        self
+    }
+
+    /// Disable buffered mode.
+    pub fn with_unbuffered(mut self) -> Self {
+        self.val &= !(Self::BUFFERED | Self::BUF_MST);
+        self
+    }
+
+    /// Whether special fully nested mode is on.
+    pub fn special_fully_nested(&self) -> bool {
+        self.val & Self::SFNM != 0
+    }
+
+    /// Set whether special fully nested mode is on.
+    pub fn with_special_fully_nested(mut self, v: bool) -> Self {
+        self.val = with_bit(self.val, Self::SFNM, v);
+        self
+    }
+}
+
+macro_rules! impl_icw_into {
+    ($($x:ident),*) => {$(
+        impl From<$x> for u8 {
+
+            fn from(v: $x) -> u8 {
+                v.val
+            }
+        }
+    )*};
+}
+
+impl_icw_into!(Icw1, Icw2, Icw3, Icw4);
+
+impl InitSequence {
+
+    /// Sequence for standard PC setup: slave PIC is cascaded to master
+    /// IRQ2, vectors start at given offsets. None is returned if an offset
+    /// is not a multiple of 8.
+    pub fn new(offset1: u8, offset2: u8) -> Option<Self> {
+        Some(InitSequence {
+            icw1        : Icw1::new(),
+
+            master_icw2 : Icw2::new(offset1)?,
+            slave_icw2  : Icw2::new(offset2)?,
+
+            master_icw3 : Icw3::master(1 << CASCADE_IRQ),
+            slave_icw3  : Icw3::slave(CASCADE_IRQ),
+
+            master_icw4 : Icw4::new(),
+            slave_icw4  : Icw4::new(),
+        })
+    }
+
+    /// Set ICW4 of both PICs according to given mode.
+    pub fn with_mode(mut self, mode: InitMode) -> Self {
+        self.master_icw4 = mode.icw4(true);
+        self.slave_icw4  = mode.icw4(false);
+        self
+    }
+
+    /// Port writes of this sequence in the order they are sent: ICW1
+    /// goes to command ports, all the following words go to data ports.
+    /// In single mode slave PIC is not written and ICW3 is skipped. ICW4
+    /// is skipped when ICW1 does not request it.
+    pub fn port_writes(&self) -> PortWrites {
+        let mut w = PortWrites { writes: [(0, 0); 8], len: 0 };
+        let cascade = !self.icw1.single();
+
+        w.push(MASTER_CMD, self.icw1.into());
+        if cascade {
+            w.push(SLAVE_CMD, self.icw1.into());
+        }
+        w.push(MASTER_DAT, self.master_icw2.into());
+        if cascade {
+            w.push(SLAVE_DAT, self.slave_icw2.into());
+            w.push(MASTER_DAT, self.master_icw3.into());
+            w.push(SLAVE_DAT, self.slave_icw3.into());
+        }
+        if self.icw1.icw4_needed() {
+            w.push(MASTER_DAT, self.master_icw4.into());
+            if cascade {
+                w.push(SLAVE_DAT, self.slave_icw4.into());
+            }
+        }
+        w
+    }
+
+    /// Send this sequence to the PICs. Masks are not preserved: after
+    /// initialization PICs have all IRQs unmasked.
+    pub fn send(&self) {
+        for &(port, val) in self.port_writes().writes() {
+            Port::from(port).out_u8(val);
+        }
+    }
+}
+
+impl PortWrites {
+
+    fn push(&mut self, port: u16, val: u8) {
+        self.writes[self.len] = (port, val);
+        self.len += 1;
+    }
+
+    /// Port and value pairs in the order they are sent.
+    pub fn writes(&self) -> &[(u16, u8)] {
+        &self.writes[..self.len]
+    }
+}
+
+impl Default for Pic {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Pic {
 
     /// Create new interface to PIC.
@@ -11,55 +391,370 @@ impl Pic {
 
     /// Disable PIC.
     pub fn disable(&self) {
-        use port::Port;
+        self.set_mask(0xFFFF);
+    }
 
-        let cmd = 0xFF; // Disable command
-        let slv = 0xA1; // Slave PIC data port.
-        let mst = 0x21; // Master PIC data port.
+    /// Re-initializes and remaps PIC.
+    /// offset1 and offset2 are new interrupt vectors offsets. Returns false
+    /// and leaves PIC untouched if an offset is not a multiple of 8.
+    pub fn remap(&self, offset1: u8, offset2: u8) -> bool {
+        self.remap_with_mode(offset1, offset2, InitMode::default())
+    }
 
-        let slv = Port::from(slv as u16);
-        let mst = Port::from(mst as u16);
+    /// Re-initializes and remaps PIC with given ICW4 options.
+    /// offset1 and offset2 are new interrupt vectors offsets. Returns false
+    /// and leaves PIC untouched if an offset is not a multiple of 8.
+    pub fn remap_with_mode(&self, offset1: u8, offset2: u8, mode: InitMode)
+            -> bool {
+        match InitSequence::new(offset1, offset2) {
+            Some(seq)   => {
+                self.init(&seq.with_mode(mode));
+                true
+            },
+            None        => false,
+        }
+    }
 
-        // Send disable commands.
-        slv.out_u8(cmd);
-        mst.out_u8(cmd);
+    /// Re-initializes PIC with given sequence. IRQ masks are restored
+    /// after initialization.
+    pub fn init(&self, seq: &InitSequence) {
+        let mask = self.mask_value();
+        seq.send();
+        self.set_mask(mask);
     }
 
-    /// Re-initializes and remaps PIC.
-    /// offset1 and offset2 are new interrupt vectors offsets.
-    pub fn remap(&self, offset1: u8, offset2: u8) {
-        use port::Port;
+    /// Current IRQ mask of both PICs. Bits 0-7 are master IRQs and
+    /// bits 8-15 are slave IRQs. Set bit means IRQ is masked.
+    pub fn mask_value(&self) -> u16 {
+        let mst = Port::from(MASTER_DAT).in_u8() as u16;
+        let slv = Port::from(SLAVE_DAT).in_u8() as u16;
+        mst | (slv << 8)
+    }
+
+    /// Set IRQ mask of both PICs. See `mask_value`.
+    pub fn set_mask(&self, mask: u16) {
+        Port::from(MASTER_DAT).out_u8(mask as u8);
+        Port::from(SLAVE_DAT ).out_u8((mask >> 8) as u8);
+    }
+
+    /// Data port of the PIC that serves given IRQ and the IRQ bit in it.
+    fn irq_port(irq: u8) -> (Port, u8) {
+        if irq < 8 {
+            (Port::from(MASTER_DAT), 1 << irq)
+        } else {
+            (Port::from(SLAVE_DAT), 1 << (irq - 8))
+        }
+    }
+
+    /// Mask given IRQ. IRQ numbers above 15 are ignored.
+    pub fn mask(&self, irq: u8) {
+        if irq > 15 {
+            return;
+        }
+
+        let (port, bit) = Self::irq_port(irq);
+        let val = port.in_u8() | bit;
+        port.out_u8(val);
+    }
+
+    /// Unmask given IRQ. IRQ numbers above 15 are ignored. Note that
+    /// slave IRQs are only delivered when cascade IRQ2 is unmasked too.
+    pub fn unmask(&self, irq: u8) {
+        if irq > 15 {
+            return;
+        }
+
+        let (port, bit) = Self::irq_port(irq);
+        let val = port.in_u8() & !bit;
+        port.out_u8(val);
+    }
+
+    /// Whether given IRQ is masked. None if the IRQ number is above 15.
+    pub fn is_masked(&self, irq: u8) -> Option<bool> {
+        if irq > 15 {
+            return None;
+        }
+        Some(self.mask_value() & (1 << irq) != 0)
+    }
+
+    /// Send non-specific EOI to master PIC.
+    pub fn master_eoi(&self) {
+        Port::from(MASTER_CMD).out_u8(OCW2_EOI);
+    }
 
-        // Interface ports.
-        let pic1cmd = Port::from(0x20u16);
-        let pic2cmd = Port::from(0xA0u16);
-        let pic1dat = Port::from(0x21u16);
-        let pic2dat = Port::from(0xA1u16);
+    /// Send non-specific EOI to slave PIC.
+    pub fn slave_eoi(&self) {
+        Port::from(SLAVE_CMD).out_u8(OCW2_EOI);
+    }
+
+    /// Send non-specific EOI for given IRQ. For slave IRQs both
+    /// slave and master PICs are acknowledged. IRQ numbers above 15 are
+    /// ignored.
+    pub fn eoi(&self, irq: u8) {
+        if irq > 15 {
+            return;
+        }
+
+        if irq >= 8 {
+            self.slave_eoi();
+        }
+        self.master_eoi();
+    }
+
+    /// Send specific EOI for given IRQ. For slave IRQs specific EOI of
+    /// cascade IRQ is sent to master PIC as well. IRQ numbers above 15
+    /// are ignored.
+    pub fn specific_eoi(&self, irq: u8) {
+        if irq > 15 {
+            return;
+        }
+
+        if irq >= 8 {
+            Port::from(SLAVE_CMD).out_u8(OCW2_SPECIFIC_EOI | (irq - 8));
+            Port::from(MASTER_CMD).out_u8(OCW2_SPECIFIC_EOI | CASCADE_IRQ);
+        } else {
+            Port::from(MASTER_CMD).out_u8(OCW2_SPECIFIC_EOI | irq);
+        }
+    }
+
+    /// Send OCW3 to both PICs and read the selected register.
+    fn read_ocw3(&self, ocw3: u8) -> u16 {
+        let mst = Port::from(MASTER_CMD);
+        let slv = Port::from(SLAVE_CMD);
+
+        mst.out_u8(ocw3);
+        slv.out_u8(ocw3);
+
+        let lo = mst.in_u8() as u16;
+        let hi = slv.in_u8() as u16;
+        lo | (hi << 8)
+    }
+
+    /// In-Service Register of both PICs. Bits 8-15 are slave IRQs.
+    pub fn isr(&self) -> u16 {
+        self.read_ocw3(OCW3_READ_ISR)
+    }
+
+    /// Interrupt Request Register of both PICs. Bits 8-15 are slave IRQs.
+    pub fn irr(&self) -> u16 {
+        self.read_ocw3(OCW3_READ_IRR)
+    }
+
+    /// Edge/Level Control Register of both PICs. Bits 8-15 are slave
+    /// IRQs. Set bit means IRQ is level triggered.
+    pub fn elcr(&self) -> u16 {
+        let mst = Port::from(ELCR_MASTER).in_u8() as u16;
+        let slv = Port::from(ELCR_SLAVE).in_u8() as u16;
+        mst | (slv << 8)
+    }
+
+    /// Set Edge/Level Control Register of both PICs. Bits of IRQs 0, 1,
+    /// 2, 8 and 13 are cleared as these IRQs must be edge triggered.
+    pub fn set_elcr(&self, elcr: u16) {
+        let elcr = elcr & !ELCR_EDGE_ONLY;
+        Port::from(ELCR_MASTER).out_u8(elcr as u8);
+        Port::from(ELCR_SLAVE ).out_u8((elcr >> 8) as u8);
+    }
+
+    /// Trigger mode of given IRQ. None if the IRQ number is above 15.
+    pub fn trigger_mode(&self, irq: u8) -> Option<TriggerMode> {
+        if irq > 15 {
+            return None;
+        }
+
+        if self.elcr() & (1 << irq) != 0 {
+            Some(TriggerMode::LevelSensitive)
+        } else {
+            Some(TriggerMode::EdgeSensitive)
+        }
+    }
+
+    /// Set trigger mode of given IRQ. Returns false if the IRQ number
+    /// is above 15 or the IRQ can't be level triggered (IRQs 0, 1, 2,
+    /// 8 and 13), in which case nothing is changed.
+    pub fn set_trigger_mode(&self, irq: u8, mode: TriggerMode) -> bool {
+        if irq > 15 {
+            return false;
+        }
+
+        let bit = 1 << irq;
+        let elcr = self.elcr();
+        let elcr = match mode {
+            TriggerMode::EdgeSensitive  => elcr & !bit,
+            TriggerMode::LevelSensitive => {
+                if ELCR_EDGE_ONLY & bit != 0 {
+                    return false;
+                }
+                elcr | bit
+            }
+        };
+
+        self.set_elcr(elcr);
+        true
+    }
 
-        // Backup masks.
-        let a1 = pic1dat.in_u8();
-        let a2 = pic2dat.in_u8();
+    /// Check whether IRQ7 or IRQ15 is spurious. PIC signals spurious
+    /// interrupt on its lowest priority input when the request is gone
+    /// before it is acknowledged, in which case the ISR bit is not set.
+    /// Other IRQs are never considered spurious.
+    pub fn is_spurious(&self, irq: u8) -> bool {
+        match irq {
+            7 | 15  => self.isr() & (1 << irq) == 0,
+            _       => false
+        }
+    }
 
-        // Initialization command (cascade mode).
-        let init_cmd = 0x01 + 0x10;
+    /// Check the IRQ for being spurious and handle it. Returns true if
+    /// the IRQ is spurious and must be neither handled nor acknowledged.
+    /// For spurious IRQ15 master PIC has seen a real cascade interrupt
+    /// so it is acknowledged here.
+    pub fn handle_spurious(&self, irq: u8) -> bool {
+        if !self.is_spurious(irq) {
+            return false;
+        }
 
-        // Start Initialization sequence.
-        pic1cmd.out_u8(init_cmd);
-        pic2cmd.out_u8(init_cmd);
+        if irq == 15 {
+            self.master_eoi();
+        }
+        true
+    }
+}
 
-        // Master and slave interrupt vector offsets.
-        pic1cmd.out_u8(offset1);
-        pic2cmd.out_u8(offset2);
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
 
-        pic1cmd.out_u8(4); // Tell Master PIC IRQ2 is slave.
-        pic2cmd.out_u8(2); // Tell Slave its cascade identity.
+    #[test]
+    fn remap_sends_icws_and_restores_mask() {
+        let mock = Mock::new();
+        mock.push_port_in(MASTER_DAT, 0xFB);
+        mock.push_port_in(SLAVE_DAT, 0xFF);
+
+        assert!(with_backend(&mock, || Pic::new().remap(0x20, 0x28)));
+
+        assert!(mock.log_eq(&[
+            PortIn8 (0x21, 0xFB),
+            PortIn8 (0xA1, 0xFF),
+            PortOut8(0x20, 0x11),
+            PortOut8(0xA0, 0x11),
+            PortOut8(0x21, 0x20),
+            PortOut8(0xA1, 0x28),
+            PortOut8(0x21, 0x04),
+            PortOut8(0xA1, 0x02),
+            PortOut8(0x21, 0x01),
+            PortOut8(0xA1, 0x01),
+            PortOut8(0x21, 0xFB),
+            PortOut8(0xA1, 0xFF),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn init_sequence_port_writes() {
+        let mode = InitMode { auto_eoi : true, buffered : true };
+        let seq = InitSequence::new(0x30, 0x38).unwrap().with_mode(mode);
+
+        assert_eq!(seq.port_writes().writes(), &[
+            (0x20, 0x11), // ICW1: edge triggered, cascade, ICW4 needed.
+            (0xA0, 0x11),
+            (0x21, 0x30), // ICW2: vector offsets.
+            (0xA1, 0x38),
+            (0x21, 0x04), // ICW3: slave on master IRQ2.
+            (0xA1, 0x02), // ICW3: slave cascade identity.
+            (0x21, 0x0F), // ICW4: 8086, auto EOI, buffered master.
+            (0xA1, 0x0B), // ICW4: 8086, auto EOI, buffered slave.
+        ]);
+    }
+
+    #[test]
+    fn single_mode_skips_slave_and_icw3() {
+        let mut seq = InitSequence::new(0x20, 0x28).unwrap();
+        seq.icw1 = seq.icw1.with_single(true);
+
+        assert_eq!(seq.port_writes().writes(), &[
+            (0x20, 0x13), // ICW1: edge triggered, single, ICW4 needed.
+            (0x21, 0x20), // ICW2: vector offset.
+            (0x21, 0x01), // ICW4: 8086.
+        ]);
+    }
+
+    #[test]
+    fn icw4_skipped_when_not_needed() {
+        let mut seq = InitSequence::new(0x20, 0x28).unwrap();
+        seq.icw1 = seq.icw1.with_icw4_needed(false);
+
+        assert_eq!(seq.port_writes().writes(), &[
+            (0x20, 0x10), // ICW1: edge triggered, cascade, no ICW4.
+            (0xA0, 0x10),
+            (0x21, 0x20), // ICW2: vector offsets.
+            (0xA1, 0x28),
+            (0x21, 0x04), // ICW3: slave on master IRQ2.
+            (0xA1, 0x02),
+        ]);
+
+        let mock = Mock::new();
+        seq.icw1 = seq.icw1.with_single(true);
+        with_backend(&mock, || seq.send());
+        assert!(mock.log_eq(&[
+            PortOut8(0x20, 0x12),
+            PortOut8(0x21, 0x20),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn init_restores_mask_after_icw4() {
+        let mock = Mock::new();
+        mock.push_port_in(MASTER_DAT, 0xB8);
+        mock.push_port_in(SLAVE_DAT, 0x8E);
+
+        let seq = InitSequence::new(0x40, 0x48).unwrap();
+        with_backend(&mock, || Pic::new().init(&seq));
+
+        let log = mock.log();
+        assert_eq!(log.len(), 12);
+        assert_eq!(log[0], Some(PortIn8(0x21, 0xB8)));
+        assert_eq!(log[1], Some(PortIn8(0xA1, 0x8E)));
+        for (i, &(port, val)) in seq.port_writes().writes().iter()
+                .enumerate() {
+            assert_eq!(log[2 + i], Some(PortOut8(port, val)));
+        }
+        assert_eq!(log[10], Some(PortOut8(0x21, 0xB8)));
+        assert_eq!(log[11], Some(PortOut8(0xA1, 0x8E)));
+    }
+
+    #[test]
+    fn invalid_irq_and_offset_touch_no_ports() {
+        let mock = Mock::new();
+
+        with_backend(&mock, || {
+            let pic = Pic::new();
+            assert_eq!(pic.is_masked(16), None);
+            assert!(pic.trigger_mode(16).is_none());
+            pic.eoi(16);
+            pic.specific_eoi(0xFF);
+            assert!(!pic.remap(0x21, 0x28));
+            assert!(!pic.remap(0x20, 0x2C));
+        });
+
+        assert_eq!(mock.log_len(), 0);
+        assert!(Icw2::new(0x24).is_none());
+        assert_eq!(Icw2::new(0x28).unwrap().offset(), 0x28);
+    }
 
-        // Set 8086 mode.
-        pic1cmd.out_u8(1);
-        pic2cmd.out_u8(1);
+    #[test]
+    fn slave_irq_queries() {
+        let mock = Mock::new();
+        mock.push_port_in(MASTER_DAT, 0xFB);
+        mock.push_port_in(SLAVE_DAT, 0x80);
+        mock.push_port_in(ELCR_MASTER, 0x00);
+        mock.push_port_in(ELCR_SLAVE, 0x0E);
 
-        // Restore masks.
-        pic1dat.out_u8(a1);
-        pic2dat.out_u8(a1);
+        with_backend(&mock, || {
+            let pic = Pic::new();
+            assert_eq!(pic.is_masked(15), Some(true));
+            assert!(pic.trigger_mode(11) == Some(TriggerMode::LevelSensitive));
+        });
     }
 }
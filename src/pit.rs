@@ -4,6 +4,27 @@ const CMD_REG: u16 = 0x43;
 /// Base port number for channels. Chan0 port is 0x40, Chan1 port is 0x41 etc.
 const CH_BASE: u16 = 0x40;
 
+/// System control port B. Holds channel 2 gate and speaker enable bits.
+const PORT_B: u16 = 0x61;
+
+/// Channel 2 gate bit in port B.
+const PORT_B_CH2_GATE: u8 = 1 << 0;
+
+/// Speaker data enable bit in port B.
+const PORT_B_SPEAKER: u8 = 1 << 1;
+
+/// Frequency of PIT oscillator in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// Max count value of a channel. Is set by zero reload value.
+pub const MAX_COUNT: u32 = 0x1_0000;
+
+/// Min count value of rate generator and square wave modes. Count 1 is
+/// illegal in these modes.
+pub const MIN_PERIODIC_COUNT: u32 = 2;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
 /// The channel of PIT.
 #[repr(u8)]
 #[derive(Clone, Copy)]
@@ -58,6 +79,7 @@ pub struct Pit {
 
 /// Status byte read from corresponding channel port.
 #[repr(packed)]
+#[derive(Clone, Copy)]
 pub struct StatusByte {
     val     : u8,
 }
@@ -68,11 +90,50 @@ pub struct Command {
     val     : u8,
 }
 
+/// Read-back command builder. Latches count and/or status of several
+/// channels at once.
+#[derive(Clone, Copy)]
+pub struct ReadBack {
+    val     : u8,
+}
+
+/// Data latched by read-back command for a single channel.
+#[derive(Clone, Copy)]
+pub struct ChannelReadBack {
+    pub status  : Option<StatusByte>,
+    pub count   : Option<u16>,
+}
+
+/// Data latched by read-back command for all selected channels.
+#[derive(Clone, Copy)]
+pub struct ReadBackResult {
+    channels    : [Option<ChannelReadBack>; 3],
+}
+
+/// Channel count value computed for a target frequency or period.
+#[derive(Clone, Copy)]
+pub struct Divisor {
+    count   : u32,
+}
+
 /// Port for PIT command register.
 fn cmd_port() -> ::port::Port {
     ::port::Port::number(CMD_REG)
 }
 
+/// Port for system control port B.
+fn port_b() -> ::port::Port {
+    ::port::Port::number(PORT_B)
+}
+
+/// Count of PIT ticks in given amount of nanoseconds, rounded to nearest.
+fn ns_to_ticks(ns: u64) -> u64 {
+    let base = BASE_FREQUENCY as u64;
+    let secs = ns / NS_PER_SEC;
+    let rest = ns % NS_PER_SEC;
+    secs * base + (rest * base + NS_PER_SEC / 2) / NS_PER_SEC
+}
+
 impl Channel {
 
     /// Port for this channel.
@@ -124,7 +185,7 @@ impl Channel {
         let port = self.port();
 
         // Send lo and hi bytes.
-        port.out_u8((c >> 0) as _);
+        port.out_u8(c as _);
         port.out_u8((c >> 8) as _);
     }
 
@@ -144,7 +205,7 @@ impl Channel {
 macro_rules! pit_ch_impl {
     ($channel:ident, $ch:ident,
             $pending:ident, $set_access:ident, $set_operating:ident,
-            $set_reload:ident,
+            $set_reload:ident, $set_divisor:ident,
             $commit_settings:ident, $commit_reload:ident,
             $commit_all:ident,
             $reload_count:ident, $pending_reload_count:ident,
@@ -167,6 +228,11 @@ macro_rules! pit_ch_impl {
         self.$pending.reload = value;
     }
 
+    /// Set reload count value from given divisor.
+    pub fn $set_divisor(&mut self, div: Divisor) {
+        self.$pending.reload = div.reload();
+    }
+
     /// Commit pending settings to the channel.
     pub fn $commit_settings(&mut self) {
         use self::Channel::$channel;
@@ -264,6 +330,8 @@ impl Pit {
     /// Caller must understand that this interface will hold data that may
     /// not correspond to current PIT settings. This may lead to misbehaviour
     /// and caller can update PIT settings if needed.
+    ///
+    /// Prefer `new` which reads current settings from the PIT.
     pub unsafe fn new_no_sync() -> Self {
         use self::AccessMode::LoHiByte;
         use self::OperatingMode::RateGenerator;
@@ -282,16 +350,49 @@ impl Pit {
         };
 
         Pit {
-            ch0 : ch0,
-            ch2 : ch2,
+            ch0,
+            ch2,
 
             ch0_pending : ch0,
             ch2_pending : ch2,
         }
     }
 
+    /// Create new PIT interface with access and operating modes of the
+    /// channels read from the PIT with read-back command.
+    ///
+    /// Reload values can't be read back from the PIT, so they are set
+    /// to zero in this interface and must be set before committing
+    /// reload counts.
+    ///
+    /// # Safety
+    /// Caller must ensure that no other code accesses the PIT in the
+    /// meantime.
+    pub unsafe fn new() -> Self {
+        use self::Channel::*;
+
+        let mut pit = Self::new_no_sync();
+
+        let rb = ReadBack::new().with_status()
+                .with_channel(Channel0).with_channel(Channel2);
+        let res = pit.read_back(rb);
+
+        let sync = |info: &mut ChannelInfo, ch: Channel| {
+            let status = res.channel(ch).unwrap().status.unwrap();
+            info.access     = status.access_mode_or_lohi();
+            info.operating  = status.operating_mode();
+            info.reload     = 0;
+        };
+        sync(&mut pit.ch0, Channel0);
+        sync(&mut pit.ch2, Channel2);
+
+        pit.ch0_pending = pit.ch0;
+        pit.ch2_pending = pit.ch2;
+        pit
+    }
+
     pit_ch_impl!(Channel0, ch0, ch0_pending, ch0_set_access,
-            ch0_set_operating, ch0_set_reload,
+            ch0_set_operating, ch0_set_reload, ch0_set_divisor,
             ch0_commit_settings, ch0_commit_reload,
             ch0_commit_all,
             ch0_reload_count, ch0_pending_reload_count,
@@ -299,13 +400,398 @@ impl Pit {
             ch0_operating, ch0_pending_operating
             );
     pit_ch_impl!(Channel2, ch2, ch2_pending, ch2_set_access,
-            ch2_set_operating, ch2_set_reload,
+            ch2_set_operating, ch2_set_reload, ch2_set_divisor,
             ch2_commit_settings, ch2_commit_reload,
             ch2_commit_all,
             ch2_reload_count, ch2_pending_reload_count,
             ch2_access, ch2_pending_access,
             ch2_operating, ch2_pending_operating
             );
+
+    /// Whether channel 2 gate input is high.
+    pub fn ch2_gate(&self) -> bool {
+        port_b().in_u8() & PORT_B_CH2_GATE != 0
+    }
+
+    /// Set channel 2 gate input. Counting of channel 2 is only performed
+    /// while gate is high. Speaker output is disabled by this function.
+    pub fn set_ch2_gate(&mut self, high: bool) {
+        let port = port_b();
+        let val = port.in_u8() & !(PORT_B_CH2_GATE | PORT_B_SPEAKER);
+        let val = if high { val | PORT_B_CH2_GATE } else { val };
+        port.out_u8(val);
+    }
+
+    /// Whether channel 2 output is routed to PC speaker. Both channel 2
+    /// gate and speaker data bits must be set for this.
+    pub fn speaker_enabled(&self) -> bool {
+        let mask = PORT_B_CH2_GATE | PORT_B_SPEAKER;
+        port_b().in_u8() & mask == mask
+    }
+
+    /// Route channel 2 output to PC speaker or disconnect it. Channel 2
+    /// gate is set or cleared together with speaker data bit.
+    pub fn set_speaker(&mut self, on: bool) {
+        let mask = PORT_B_CH2_GATE | PORT_B_SPEAKER;
+        let port = port_b();
+        let val = port.in_u8() & !mask;
+        let val = if on { val | mask } else { val };
+        port.out_u8(val);
+    }
+
+    /// Status byte of channel 2 read by a read-back command.
+    fn ch2_status(&self) -> StatusByte {
+        use self::Channel::Channel2;
+
+        let rb = ReadBack::new().with_status().with_channel(Channel2);
+        self.read_back(rb).channel(Channel2).unwrap().status.unwrap()
+    }
+
+    /// Cached access mode of given channel. Channel 1 is not cached and
+    /// is assumed to be in lo/hi mode.
+    fn cached_access(&self, ch: Channel) -> AccessMode {
+        use self::Channel::*;
+
+        match ch {
+            Channel0 => self.ch0.access,
+            Channel1 => AccessMode::LoHiByte,
+            Channel2 => self.ch2.access,
+        }
+    }
+
+    /// Issue read-back command and read latched data of all selected
+    /// channels. Count is read according to access mode from the latched
+    /// status or, if status is not latched, from the cached channel
+    /// settings.
+    pub fn read_back(&self, rb: ReadBack) -> ReadBackResult {
+        use self::AccessMode::*;
+        use self::Channel::*;
+
+        let mut result = ReadBackResult { channels : [None; 3] };
+
+        unsafe { Command { val : rb.into() }.send(); }
+
+        for &ch in [Channel0, Channel1, Channel2].iter() {
+            if !rb.selects(ch) {
+                continue;
+            }
+
+            let status = if rb.latches_status() {
+                Some(unsafe { StatusByte::read_from(ch) })
+            } else {
+                None
+            };
+
+            let access = match status {
+                Some(s) => s.access_mode_or_lohi(),
+                None    => self.cached_access(ch),
+            };
+
+            let count = if rb.latches_count() {
+                let port = ch.port();
+                Some(match access {
+                    LoByteOnly  => port.in_u8() as u16,
+                    HiByteOnly  => (port.in_u8() as u16) << 8,
+                    LoHiByte    => {
+                        let lo = port.in_u8() as u16;
+                        let hi = port.in_u8() as u16;
+                        lo | (hi << 8)
+                    }
+                })
+            } else {
+                None
+            };
+
+            result.channels[ch as usize] = Some(ChannelReadBack {
+                status,
+                count,
+            });
+        }
+
+        result
+    }
+
+    /// Start one-shot countdown of given divisor on channel 2. Channel is
+    /// switched to interrupt on terminal count mode and its output goes
+    /// high when the count expires, which can be checked with
+    /// `ch2_one_shot_expired`. Speaker output is disabled.
+    pub fn ch2_start_one_shot(&mut self, div: Divisor) {
+        self.set_ch2_gate(false);
+
+        self.ch2_set_access(AccessMode::LoHiByte);
+        self.ch2_set_operating(OperatingMode::InterruptOnTerminalCount);
+        self.ch2_set_divisor(div);
+        self.ch2_commit_all();
+
+        self.set_ch2_gate(true);
+    }
+
+    /// Whether one-shot countdown started by `ch2_start_one_shot` has
+    /// expired.
+    pub fn ch2_one_shot_expired(&self) -> bool {
+        self.ch2_status().output_pin_state()
+    }
+
+    /// Busy-wait given amount of PIT ticks using channel 2. Delays longer
+    /// than max channel count are performed in several countdowns.
+    pub fn delay_ticks(&mut self, ticks: u64) {
+        let mut left = ticks;
+        while left > 0 {
+            let count = if left > MAX_COUNT as u64 {
+                MAX_COUNT
+            } else {
+                left as u32
+            };
+
+            self.ch2_start_one_shot(Divisor { count });
+            while !self.ch2_one_shot_expired() {}
+
+            left -= count as u64;
+        }
+        self.set_ch2_gate(false);
+    }
+
+    /// Busy-wait given amount of PIT ticks by polling channel 0 count.
+    /// Channel 2 is not touched so it can be used meanwhile, for example
+    /// to drive PC speaker.
+    ///
+    /// Channel 0 must run in rate generator or square wave mode with
+    /// lo/hi access. Its reload value does not need to be known: when the
+    /// counter wraps only the ticks down to zero are counted, so the delay
+    /// may be slightly longer than requested but never shorter. The wait
+    /// is correct only if the counter is polled more often than once per
+    /// channel 0 period, so long interrupts must not happen meanwhile.
+    pub fn ch0_delay_ticks(&self, ticks: u64) {
+        use self::OperatingMode::*;
+
+        // In square wave mode counter is decremented by two.
+        let step = match self.ch0.operating {
+            SquareWaveGenerator | SquareWaveGenerator2 => 2,
+            _ => 1
+        };
+
+        let count = || unsafe { Channel::Channel0.current_count() as u64 };
+
+        let target = ticks.saturating_mul(step);
+        let mut elapsed = 0;
+        let mut prev = count();
+        while elapsed < target {
+            let cur = count();
+            elapsed += if cur <= prev {
+                prev - cur
+            } else {
+                // Counter reached zero and was reloaded.
+                prev
+            };
+            prev = cur;
+        }
+    }
+
+    /// Busy-wait given amount of nanoseconds by polling channel 0. See
+    /// `ch0_delay_ticks`.
+    pub fn ch0_delay_ns(&self, ns: u64) {
+        self.ch0_delay_ticks(ns_to_ticks(ns))
+    }
+
+    /// Busy-wait given amount of microseconds by polling channel 0.
+    pub fn ch0_delay_us(&self, us: u64) {
+        self.ch0_delay_ns(us.saturating_mul(1000))
+    }
+
+    /// Busy-wait given amount of milliseconds by polling channel 0.
+    pub fn ch0_delay_ms(&self, ms: u64) {
+        self.ch0_delay_ns(ms.saturating_mul(1_000_000))
+    }
+
+    /// Busy-wait given amount of nanoseconds using channel 2. Precision
+    /// is limited by PIT tick which is about 838 ns.
+    pub fn delay_ns(&mut self, ns: u64) {
+        self.delay_ticks(ns_to_ticks(ns))
+    }
+
+    /// Busy-wait given amount of microseconds using channel 2.
+    pub fn delay_us(&mut self, us: u64) {
+        self.delay_ns(us.saturating_mul(1000))
+    }
+
+    /// Busy-wait given amount of milliseconds using channel 2.
+    pub fn delay_ms(&mut self, ms: u64) {
+        self.delay_ns(ms.saturating_mul(1_000_000))
+    }
+}
+
+impl Default for ReadBack {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReadBack {
+
+    const NO_COUNT  : u8 = 1 << 5;
+    const NO_STATUS : u8 = 1 << 4;
+
+    /// Read-back command that selects no channels and latches nothing.
+    pub fn new() -> Self {
+        ReadBack { val : 0b1100_0000 | Self::NO_COUNT | Self::NO_STATUS }
+    }
+
+    /// Latch count of selected channels.
+    pub fn with_count(mut self) -> Self {
+        self.val &= !Self::NO_COUNT;
+        self
+    }
+
+    /// Latch status of selected channels.
+    pub fn with_status(mut self) -> Self {
+        self.val &= !Self::NO_STATUS;
+        self
+    }
+
+    /// Select given channel.
+    pub fn with_channel(mut self, ch: Channel) -> Self {
+        self.val |= 1 << (ch as u8 + 1);
+        self
+    }
+
+    /// Whether count is latched.
+    pub fn latches_count(&self) -> bool {
+        self.val & Self::NO_COUNT == 0
+    }
+
+    /// Whether status is latched.
+    pub fn latches_status(&self) -> bool {
+        self.val & Self::NO_STATUS == 0
+    }
+
+    /// Whether given channel is selected.
+    pub fn selects(&self, ch: Channel) -> bool {
+        self.val & (1 << (ch as u8 + 1)) != 0
+    }
+}
+
+impl From<ReadBack> for u8 {
+
+    fn from(v: ReadBack) -> u8 {
+        v.val
+    }
+}
+
+impl ReadBackResult {
+
+    /// Latched data of given channel. None if channel was not selected.
+    pub fn channel(&self, ch: Channel) -> Option<ChannelReadBack> {
+        self.channels[ch as usize]
+    }
+}
+
+impl Divisor {
+
+    /// Create divisor from raw count value. Count must be in range from 1
+    /// to `MAX_COUNT` inclusive. Count 1 is only valid for one-shot and
+    /// strobe modes, see `valid_for`.
+    pub fn from_count(count: u32) -> Option<Self> {
+        if count == 0 || count > MAX_COUNT {
+            None
+        } else {
+            Some(Divisor { count })
+        }
+    }
+
+    /// Divisor for rate generator and square wave modes that gives the
+    /// closest rate to given frequency in Hz. None is returned if
+    /// frequency can't be achieved: it is above half of base frequency
+    /// or below about 18.2 Hz.
+    pub fn from_frequency(hz: u32) -> Option<Self> {
+        if hz == 0 || hz > BASE_FREQUENCY {
+            return None;
+        }
+
+        let count = (BASE_FREQUENCY + hz / 2) / hz;
+        Self::from_periodic_count(count as u64)
+    }
+
+    /// Divisor for rate generator and square wave modes that gives the
+    /// closest period to given one in nanoseconds. None is returned if
+    /// period is shorter than two PIT ticks or longer than max count,
+    /// which is about 54.9 ms.
+    pub fn from_period_ns(ns: u64) -> Option<Self> {
+        Self::from_periodic_count(ns_to_ticks(ns))
+    }
+
+    fn from_periodic_count(count: u64) -> Option<Self> {
+        if count < MIN_PERIODIC_COUNT as u64 || count > MAX_COUNT as u64 {
+            None
+        } else {
+            Some(Divisor { count : count as u32 })
+        }
+    }
+
+    /// Whether this divisor can be used in given operating mode.
+    pub fn valid_for(&self, mode: OperatingMode) -> bool {
+        use self::OperatingMode::*;
+
+        match mode {
+            RateGenerator | RateGenerator2 | SquareWaveGenerator
+                    | SquareWaveGenerator2 => {
+                self.count >= MIN_PERIODIC_COUNT
+            },
+            _ => true,
+        }
+    }
+
+    /// Divisor that gives the closest period to given one in microseconds.
+    pub fn from_period_us(us: u64) -> Option<Self> {
+        Self::from_period_ns(us.saturating_mul(1000))
+    }
+
+    /// Count value of this divisor.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Reload value to be written to the channel. Max count is
+    /// represented with zero.
+    pub fn reload(&self) -> u16 {
+        self.count as u16
+    }
+
+    /// Actually achieved frequency in millihertz.
+    pub fn frequency_millihz(&self) -> u64 {
+        let base = BASE_FREQUENCY as u64 * 1000;
+        let count = self.count as u64;
+        (base + count / 2) / count
+    }
+
+    /// Actually achieved frequency in Hz, rounded to nearest.
+    pub fn frequency(&self) -> u32 {
+        ((self.frequency_millihz() + 500) / 1000) as u32
+    }
+
+    /// Actually achieved period in nanoseconds, rounded to nearest.
+    pub fn period_ns(&self) -> u64 {
+        let base = BASE_FREQUENCY as u64;
+        (self.count as u64 * NS_PER_SEC + base / 2) / base
+    }
+
+    /// Error of achieved frequency relative to given target frequency
+    /// in parts per million. Positive when achieved rate is higher.
+    pub fn frequency_error_ppm(&self, hz: u32) -> i64 {
+        if hz == 0 {
+            return 0;
+        }
+
+        let target = hz as i64 * 1000;
+        let actual = self.frequency_millihz() as i64;
+        (actual - target) * 1_000_000 / target
+    }
+
+    /// Error of achieved period relative to given target period in
+    /// nanoseconds. Positive when achieved period is longer.
+    pub fn period_error_ns(&self, ns: u64) -> i64 {
+        self.period_ns() as i64 - ns as i64
+    }
 }
 
 impl StatusByte {
@@ -319,7 +805,7 @@ impl StatusByte {
     /// following reads.
     pub unsafe fn read_from(chan: Channel) -> Self {
         let val = chan.port().in_u8();
-        StatusByte { val:val }
+        StatusByte { val }
     }
 
     pub fn output_pin_state(&self) -> bool {
@@ -333,13 +819,23 @@ impl StatusByte {
     pub fn access_mode(&self) -> AccessMode {
         let val = self.val & 0b0011_0000;
         let val = val >> 4;
-        unsafe { ::core::mem::transmute(val) }
+        unsafe { ::core::mem::transmute::<u8, AccessMode>(val) }
+    }
+
+    /// Access mode. Channel that was never programmed reports zero
+    /// access mode which is returned as lo/hi mode.
+    fn access_mode_or_lohi(&self) -> AccessMode {
+        if self.val & 0b0011_0000 == 0 {
+            AccessMode::LoHiByte
+        } else {
+            self.access_mode()
+        }
     }
 
     pub fn operating_mode(&self) -> OperatingMode {
         let val = self.val & 0b0000_1110;
         let val = val >> 1;
-        unsafe { ::core::mem::transmute(val) }
+        unsafe { ::core::mem::transmute::<u8, OperatingMode>(val) }
     }
 
     pub fn is_bcd_mode(&self) -> bool {
@@ -351,10 +847,10 @@ impl StatusByte {
     }
 }
 
-impl Into<u8> for StatusByte {
+impl From<StatusByte> for u8 {
 
-    fn into(self) -> u8 {
-        self.val
+    fn from(v: StatusByte) -> u8 {
+        v.val
     }
 }
 
@@ -380,7 +876,7 @@ impl Command {
                     None    => 0b00 // Latch count value command.
                 }                           << 4)
                 | ((op as u8)               << 1)
-                | (if bcd { 1 } else { 0 }  << 0)
+                | if bcd { 1 } else { 0 }
             }
         }
     }
@@ -403,7 +899,7 @@ impl Command {
         if val == 0b11 {
             None // Read back command has no channel
         } else { unsafe {
-            Some(::core::mem::transmute(val))
+            Some(::core::mem::transmute::<u8, Channel>(val))
         }}
     }
 
@@ -413,14 +909,14 @@ impl Command {
         if val == 0 {
             None // Latch count value command.
         } else { unsafe {
-            Some(::core::mem::transmute(val))
+            Some(::core::mem::transmute::<u8, AccessMode>(val))
         }}
     }
 
     pub fn operating_mode(&self) -> OperatingMode {
         let val = self.val & 0b0000_1110;
         let val = val >> 1;
-        unsafe { ::core::mem::transmute(val) }
+        unsafe { ::core::mem::transmute::<u8, OperatingMode>(val) }
     }
 
     pub fn is_bcd_mode(&self) -> bool {
@@ -442,9 +938,82 @@ impl Command {
     }
 }
 
-impl Into<u8> for Command {
+impl From<Command> for u8 {
+
+    fn from(v: Command) -> u8 {
+        v.val
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hw::mock::Access::*;
+    use hw::mock::Mock;
+    use hw::with_backend;
+    use super::*;
+
+    #[test]
+    fn ch0_commit_all_sends_command_and_reload() {
+        let mock = Mock::new();
+
+        with_backend(&mock, || {
+            let mut pit = unsafe { Pit::new_no_sync() };
+            pit.ch0_set_access(AccessMode::LoHiByte);
+            pit.ch0_set_operating(OperatingMode::RateGenerator);
+            pit.ch0_set_divisor(Divisor::from_count(1193).unwrap());
+            pit.ch0_commit_all();
+
+            assert_eq!(pit.ch0_reload_count(), 1193);
+        });
+
+        assert!(mock.log_eq(&[
+            PortOut8(0x43, 0x34),
+            PortOut8(0x40, 0xA9),
+            PortOut8(0x40, 0x04),
+        ]), "{:?}", &*mock.log());
+    }
+
+    #[test]
+    fn ch0_delay_ticks_does_not_need_reload() {
+        let mock = Mock::new();
+
+        // Channel 0 runs with reload of 1000 which is not known to the
+        // interface. Counter wraps between the second and third reads.
+        for &count in [300u16, 100, 950, 700].iter() {
+            mock.push_port_in(0x40, (count & 0xFF) as u32);
+            mock.push_port_in(0x40, (count >> 8) as u32);
+        }
 
-    fn into(self) -> u8 {
-        self.val
+        with_backend(&mock, || {
+            let pit = unsafe { Pit::new_no_sync() };
+            pit.ch0_delay_ticks(500);
+        });
+
+        // 200 ticks before the wrap, at least 100 across it and 250
+        // after it.
+        let latches = mock.log().iter()
+                .filter(|a| **a == Some(PortOut8(0x43, 0x00)))
+                .count();
+        assert_eq!(latches, 4);
+    }
+
+    #[test]
+    fn divisor_limits() {
+        assert!(Divisor::from_frequency(0).is_none());
+        assert!(Divisor::from_frequency(BASE_FREQUENCY).is_none());
+        assert!(Divisor::from_frequency(BASE_FREQUENCY + 1).is_none());
+        assert!(Divisor::from_frequency(u32::MAX).is_none());
+        assert!(Divisor::from_frequency(18).is_none());
+        assert_eq!(Divisor::from_frequency(BASE_FREQUENCY / 2).unwrap()
+                .count(), 2);
+        assert_eq!(Divisor::from_frequency(1000).unwrap().count(), 1193);
+
+        assert!(Divisor::from_period_ns(838).is_none());
+        assert_eq!(Divisor::from_period_ns(1676).unwrap().count(), 2);
+
+        let one = Divisor::from_count(1).unwrap();
+        assert!(one.valid_for(OperatingMode::InterruptOnTerminalCount));
+        assert!(!one.valid_for(OperatingMode::RateGenerator));
+        assert!(!one.valid_for(OperatingMode::SquareWaveGenerator2));
     }
 }
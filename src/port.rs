@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use hw::Backend;
+
 /// Port to receive or send data through.
 pub struct Port {
     p   : u16
@@ -7,7 +9,7 @@ pub struct Port {
 
 impl From<u16> for Port {
 
-    fn from(p: u16) -> Port { Port { p:p } }
+    fn from(p: u16) -> Port { Port { p } }
 }
 
 impl From<i16> for Port {
@@ -18,16 +20,11 @@ impl From<i16> for Port {
 impl Port {
 
     /// Create port with given ID.
-    pub fn number(p: u16) -> Self { Port { p:p } }
+    pub fn number(p: u16) -> Self { Port { p } }
 
     #[inline(always)]
     pub fn out_u8(&self, data: u8) {
-        unsafe { asm!(
-            "out    dx, al"
-            : // No output
-            : "{al}"(data), "{dx}"(self.p)
-            :: "intel"
-        ); }
+        ::hw::backend().port_out_u8(self.p, data)
     }
 
     #[inline(always)]
@@ -37,12 +34,7 @@ impl Port {
 
     #[inline(always)]
     pub fn out_u16(&self, data: u16) {
-        unsafe { asm!(
-            "out    dx, ax"
-            : // No output
-            : "{ax}"(data), "{dx}"(self.p)
-            :: "intel"
-        ); }
+        ::hw::backend().port_out_u16(self.p, data)
     }
 
     #[inline(always)]
@@ -52,12 +44,7 @@ impl Port {
 
     #[inline(always)]
     pub fn out_u32(&self, data: u32) {
-        unsafe { asm!(
-            "out    dx, eax"
-            : // No output
-            : "{eax}"(data), "{dx}"(self.p)
-            :: "intel"
-        ); }
+        ::hw::backend().port_out_u32(self.p, data)
     }
 
     #[inline(always)]
@@ -67,14 +54,7 @@ impl Port {
 
     #[inline(always)]
     pub fn in_u8(&self) -> u8 {
-        let result;
-        unsafe { asm!(
-            "in     al, dx"
-            : "={al}"(result)
-            : "{dx}"(self.p)
-            :: "intel"
-        ); }
-        result
+        ::hw::backend().port_in_u8(self.p)
     }
 
     #[inline(always)]
@@ -84,14 +64,7 @@ impl Port {
 
     #[inline(always)]
     pub fn in_u16(&self) -> u16 {
-        let result;
-        unsafe { asm!(
-            "in     ax, dx"
-            : "={ax}"(result)
-            : "{dx}"(self.p)
-            :: "intel"
-        ); }
-        result
+        ::hw::backend().port_in_u16(self.p)
     }
 
     #[inline(always)]
@@ -101,14 +74,7 @@ impl Port {
 
     #[inline(always)]
     pub fn in_u32(&self) -> u32 {
-        let result;
-        unsafe { asm!(
-            "in     eax, dx"
-            : "={eax}"(result)
-            : "{dx}"(self.p)
-            :: "intel"
-        ); }
-        result
+        ::hw::backend().port_in_u32(self.p)
     }
 
     #[inline(always)]
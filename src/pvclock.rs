@@ -0,0 +1,264 @@
+use core::sync::atomic::{fence, Ordering};
+use core::sync::atomic::{AtomicI64, AtomicI8, AtomicU32, AtomicU64, AtomicU8};
+
+// Fields that hypervisor updates concurrently are atomics: it is not sound
+// to read memory that changes under a shared reference otherwise. They
+// have the same layout as plain integers.
+
+/// kvmclock time information shared with KVM. Registered with
+/// `msr::KvmSystemTime`.
+#[repr(C)]
+#[derive(Default)]
+pub struct PvClockTimeInfo {
+    version             : AtomicU32,
+    _pad0               : u32,
+    tsc_timestamp       : AtomicU64,
+    system_time         : AtomicU64,
+    tsc_to_system_mul   : AtomicU32,
+    tsc_shift           : AtomicI8,
+    flags               : AtomicU8,
+    _pad1               : [u8; 2],
+}
+
+/// Hyper-V partition reference TSC page. Registered with
+/// `msr::HvReferenceTsc`.
+#[repr(C)]
+#[derive(Default)]
+pub struct HvReferenceTscPage {
+    sequence            : AtomicU32,
+    _resv               : u32,
+    scale               : AtomicU64,
+    offset              : AtomicI64,
+}
+
+/// KVM steal time area. Registered with `msr::KvmStealTime`.
+#[repr(C)]
+pub struct KvmStealTime {
+    steal               : AtomicU64,
+    version             : AtomicU32,
+    flags               : AtomicU32,
+    preempted           : AtomicU8,
+    _pad0               : [u8; 3],
+    _pad1               : [u32; 11],
+}
+
+/// Consistent copy of kvmclock time information.
+#[derive(Clone, Copy)]
+struct TimeInfo {
+    tsc_timestamp       : u64,
+    system_time         : u64,
+    tsc_to_system_mul   : u32,
+    tsc_shift           : i8,
+    flags               : u8,
+}
+
+impl PvClockTimeInfo {
+
+    /// Flag that the clock does not go backwards across CPUs.
+    const TSC_STABLE    : u8 = 1 << 0;
+
+    /// Consistent copy of the area which is being updated by hypervisor.
+    fn snapshot(&self) -> TimeInfo {
+        loop {
+            let version = self.version.load(Ordering::Acquire);
+            if version & 1 != 0 {
+                continue;
+            }
+
+            let r = Ordering::Relaxed;
+            let copy = TimeInfo {
+                tsc_timestamp       : self.tsc_timestamp.load(r),
+                system_time         : self.system_time.load(r),
+                tsc_to_system_mul   : self.tsc_to_system_mul.load(r),
+                tsc_shift           : self.tsc_shift.load(r),
+                flags               : self.flags.load(r),
+            };
+
+            fence(Ordering::Acquire);
+            if self.version.load(Ordering::Relaxed) == version {
+                return copy;
+            }
+        }
+    }
+
+    /// Guest system time in nanoseconds for given TSC value.
+    pub fn ns_at(&self, tsc: u64) -> u64 {
+        let c = self.snapshot();
+
+        let delta = tsc.wrapping_sub(c.tsc_timestamp);
+        let shift = c.tsc_shift.unsigned_abs() as u32;
+        let delta = if c.tsc_shift < 0 {
+            delta.checked_shr(shift).unwrap_or(0)
+        } else {
+            delta.checked_shl(shift).unwrap_or(0)
+        };
+        let scaled = (delta as u128 * c.tsc_to_system_mul as u128) >> 32;
+        c.system_time.wrapping_add(scaled as u64)
+    }
+
+    /// Current guest system time in nanoseconds.
+    pub fn ns(&self) -> u64 {
+        self.ns_at(::msr::rdtsc())
+    }
+
+    /// TSC frequency in Hz derived from the scale.
+    pub fn tsc_hz(&self) -> u64 {
+        let c = self.snapshot();
+        if c.tsc_to_system_mul == 0 {
+            return 0;
+        }
+
+        let hz = (1_000_000_000u128 << 32) / c.tsc_to_system_mul as u128;
+        let shift = c.tsc_shift.unsigned_abs() as u32;
+        let hz = if c.tsc_shift < 0 {
+            hz.checked_shl(shift).unwrap_or(0)
+        } else {
+            hz.checked_shr(shift).unwrap_or(0)
+        };
+        hz as u64
+    }
+
+    /// Whether the clock can be used without synchronization across CPUs.
+    pub fn tsc_stable(&self) -> bool {
+        self.snapshot().flags & Self::TSC_STABLE != 0
+    }
+}
+
+impl HvReferenceTscPage {
+
+    /// Reference time in 100 ns units for given TSC value. None if the
+    /// page is invalid now and `msr::HvTimeRefCount` must be used instead.
+    pub fn time_at(&self, tsc: u64) -> Option<u64> {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence == 0 {
+                return None;
+            }
+
+            let scale = self.scale.load(Ordering::Relaxed);
+            let offset = self.offset.load(Ordering::Relaxed);
+
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == sequence {
+                let scaled = (tsc as u128 * scale as u128) >> 64;
+                return Some((scaled as u64).wrapping_add(offset as u64));
+            }
+        }
+    }
+
+    /// Current reference time in 100 ns units.
+    pub fn time(&self) -> Option<u64> {
+        self.time_at(::msr::rdtsc())
+    }
+}
+
+impl KvmStealTime {
+
+    /// Flag that vCPU was preempted.
+    const PREEMPTED     : u8 = 1 << 0;
+
+    /// Flag that vCPU must flush TLB before it runs again.
+    const FLUSH_TLB     : u8 = 1 << 1;
+
+    /// Time in nanoseconds this vCPU was runnable but not running.
+    pub fn steal_ns(&self) -> u64 {
+        loop {
+            let version = self.version.load(Ordering::Acquire);
+            if version & 1 != 0 {
+                continue;
+            }
+
+            let steal = self.steal.load(Ordering::Relaxed);
+
+            fence(Ordering::Acquire);
+            if self.version.load(Ordering::Relaxed) == version {
+                return steal;
+            }
+        }
+    }
+
+    /// Whether the vCPU that owns this area is preempted now. Used to
+    /// defer TLB flushes of preempted vCPUs.
+    pub fn preempted(&self) -> bool {
+        self.preempted.load(Ordering::Acquire) & Self::PREEMPTED != 0
+    }
+
+    /// Ask hypervisor to flush TLB of the vCPU that owns this area if it
+    /// is preempted. Returns false if the vCPU is running and must be
+    /// sent a TLB shootdown IPI instead.
+    pub fn request_tlb_flush(&self) -> bool {
+        let state = &self.preempted;
+        let mut cur = state.load(Ordering::SeqCst);
+        while cur & Self::PREEMPTED != 0 {
+            match state.compare_exchange(cur, cur | Self::FLUSH_TLB,
+                    Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_)       => return true,
+                Err(val)    => cur = val,
+            }
+        }
+        false
+    }
+}
+
+impl Default for KvmStealTime {
+
+    fn default() -> Self {
+        KvmStealTime {
+            steal       : AtomicU64::new(0),
+            version     : AtomicU32::new(0),
+            flags       : AtomicU32::new(0),
+            preempted   : AtomicU8::new(0),
+            _pad0       : [0; 3],
+            _pad1       : [0; 11],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_info(mul: u32, shift: i8) -> PvClockTimeInfo {
+        let info = PvClockTimeInfo::default();
+        info.version.store(2, Ordering::Relaxed);
+        info.tsc_timestamp.store(1000, Ordering::Relaxed);
+        info.system_time.store(5_000, Ordering::Relaxed);
+        info.tsc_to_system_mul.store(mul, Ordering::Relaxed);
+        info.tsc_shift.store(shift, Ordering::Relaxed);
+        info
+    }
+
+    #[test]
+    fn kvmclock_scales_tsc_delta() {
+        // 2 GHz TSC: 0.5 ns per tick.
+        let info = time_info(0x8000_0000, 0);
+        assert_eq!(info.ns_at(3000), 6_000);
+        assert_eq!(info.tsc_hz(), 2_000_000_000);
+
+        let info = time_info(0x8000_0000, -1);
+        assert_eq!(info.ns_at(3000), 5_500);
+        assert_eq!(info.tsc_hz(), 4_000_000_000);
+    }
+
+    #[test]
+    fn kvmclock_extreme_shift_does_not_overflow() {
+        let info = time_info(0x8000_0000, i8::MIN);
+        assert_eq!(info.ns_at(3000), 5_000);
+        assert_eq!(info.tsc_hz(), 0);
+
+        let info = time_info(0x8000_0000, i8::MAX);
+        assert_eq!(info.ns_at(3000), 5_000);
+        assert_eq!(info.tsc_hz(), 0);
+    }
+
+    #[test]
+    fn tlb_flush_is_requested_only_when_preempted() {
+        let st = KvmStealTime::default();
+        assert!(!st.request_tlb_flush());
+
+        st.preempted.store(KvmStealTime::PREEMPTED, Ordering::Relaxed);
+        assert!(st.request_tlb_flush());
+        assert_eq!(st.preempted.load(Ordering::Relaxed),
+                KvmStealTime::PREEMPTED | KvmStealTime::FLUSH_TLB);
+    }
+}
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use core::arch::asm;
+
 /// Macro to create getter/setter functions for segment registers.
 /// First argument always must be a name of a register. Also this
 /// will be the name of getter. The other argument is a name of a setter.
@@ -10,9 +12,8 @@ macro_rules! new {
             let val;
             unsafe {
                 asm!(
-                    "mov $0, $x"
-                    : "=r"(val)
-                    ::: "intel"
+                    concat!("mov    {0:x}, ", stringify!($x)),
+                    out(reg) val,
                 );
             }
             val
@@ -22,28 +23,27 @@ macro_rules! new {
         pub fn $y(val: u16) {
             unsafe {
                 asm!(
-                    "mov $x, $0"
-                    :: "r"(val)
-                    :: "intel"
+                    concat!("mov    ", stringify!($x), ", {0:x}"),
+                    in(reg) val,
                 );
             }
         }
     };
 }
 
-/// Stack segment register.
+// Stack segment register.
 new!(ss, set_ss);
 
-/// Data segment register.
+// Data segment register.
 new!(ds, set_ds);
 
-/// Extra segment register.
+// Extra segment register.
 new!(es, set_es);
 
-/// Extra "F" segment register.
+// Extra "F" segment register.
 new!(fs, set_fs);
 
-/// Extra "G" segment register.
+// Extra "G" segment register.
 new!(gs, set_gs);
 
 /// Task State Segment.
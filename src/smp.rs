@@ -0,0 +1,492 @@
+use apic::{ApStartup, LocalApic};
+use cr::{Cr3, Reg};
+use hw::{Backend, ControlReg};
+use seg::Tss;
+use tables::RegValue;
+
+/// Max count of application processors reported by single bootstrap.
+pub const MAX_APS: usize = 256;
+
+/// Offset of handoff structure from the trampoline start.
+const HANDOFF_OFFSET: usize = 0x200;
+
+/// Offset of acknowledge field in the handoff structure.
+const ACK_OFFSET: usize = 0x90;
+
+/// Offset of 32-bit protected mode code in the trampoline.
+const PM32_OFFSET: u32 = 0x24;
+
+/// Offset of 64-bit code in the trampoline.
+const LONG_MODE_OFFSET: u32 = 0x5E;
+
+/// Temporary GDT selectors used by the trampoline.
+const TEMP_CODE64_SEL   : u16 = 0x08;
+const TEMP_DATA_SEL     : u16 = 0x10;
+const TEMP_CODE32_SEL   : u16 = 0x18;
+
+/// Temporary GDT: null, 64-bit code, data and 32-bit code descriptors.
+const TEMP_GDT: [u64; 4] = [
+    0,
+    0x00AF_9A00_0000_FFFF,
+    0x00CF_9200_0000_FFFF,
+    0x00CF_9A00_0000_FFFF,
+];
+
+const EFER: u32 = 0xC000_0080;
+const EFER_LMA: u32 = 1 << 10;
+
+const CR4_PCIDE: u64 = 1 << 17;
+const CR4_CET: u64 = 1 << 23;
+
+/// Code of the AP trampoline. AP starts in real mode at the beginning
+/// of the page, loads temporary GDT, switches to protected mode and
+/// then to long mode with the registers of BSP taken from handoff
+/// structure. Then it loads the final GDT, IDT, TSS and stack,
+/// acknowledges the start and calls the entry point.
+///
+/// ```text
+/// .code16
+///     cli
+///     cld
+///     mov     %cs, %ax
+///     mov     %ax, %ds
+///     xor     %ebx, %ebx
+///     mov     %ax, %bx
+///     shl     $4, %ebx                    # EBX = trampoline address
+///     lgdtl   0x220                       # temporary GDTR
+///     mov     %cr0, %eax
+///     or      $1, %eax
+///     mov     %eax, %cr0
+///     ljmpl   *0x226                      # to pm32
+/// .code32
+/// pm32:
+///     mov     $0x10, %ax
+///     mov     %ax, %ds
+///     mov     %ax, %es
+///     mov     %ax, %ss
+///     mov     0x238(%ebx), %eax           # CR4 without PCIDE and CET
+///     mov     %eax, %cr4
+///     mov     0x23C(%ebx), %eax           # low 32 bits of CR3
+///     mov     %eax, %cr3
+///     mov     $0xC0000080, %ecx
+///     mov     0x240(%ebx), %eax           # EFER
+///     xor     %edx, %edx
+///     wrmsr
+///     mov     0x234(%ebx), %eax           # CR0, enables paging
+///     mov     %eax, %cr0
+///     ljmp    *0x22C(%ebx)                # to long_mode
+/// .code64
+/// long_mode:
+///     lea     handoff(%rip), %rbx
+///     mov     0x48(%rbx), %rax            # full CR4
+///     mov     %rax, %cr4
+///     mov     0x50(%rbx), %rax            # full CR3
+///     mov     %rax, %cr3
+///     lgdt    0x58(%rbx)
+///     lidt    0x62(%rbx)
+///     mov     0x78(%rbx), %rsp
+///     movzwq  0x6C(%rbx), %rax            # code selector
+///     push    %rax
+///     lea     reload_cs(%rip), %rax
+///     push    %rax
+///     lretq
+/// reload_cs:
+///     mov     0x6E(%rbx), %ax             # data selector
+///     mov     %ax, %ds
+///     mov     %ax, %es
+///     mov     %ax, %ss
+///     mov     0x70(%rbx), %ax             # TSS selector
+///     test    %ax, %ax
+///     jz      1f
+///     ltr     %ax
+/// 1:  mov     0x74(%rbx), %edi            # APIC ID
+///     mov     0x88(%rbx), %rsi            # argument
+///     mov     0x80(%rbx), %rax            # entry point
+///     movl    $1, 0x90(%rbx)              # acknowledge
+///     xor     %ebp, %ebp
+///     call    *%rax
+/// 2:  cli
+///     hlt
+///     jmp     2b
+/// ```
+const TRAMPOLINE: [u8; 200] = [
+    0xfa, 0xfc, 0x8c, 0xc8, 0x8e, 0xd8, 0x66, 0x31,
+    0xdb, 0x89, 0xc3, 0x66, 0xc1, 0xe3, 0x04, 0x66,
+    0x0f, 0x01, 0x16, 0x20, 0x02, 0x0f, 0x20, 0xc0,
+    0x66, 0x83, 0xc8, 0x01, 0x0f, 0x22, 0xc0, 0x66,
+    0xff, 0x2e, 0x26, 0x02, 0x66, 0xb8, 0x10, 0x00,
+    0x8e, 0xd8, 0x8e, 0xc0, 0x8e, 0xd0, 0x8b, 0x83,
+    0x38, 0x02, 0x00, 0x00, 0x0f, 0x22, 0xe0, 0x8b,
+    0x83, 0x3c, 0x02, 0x00, 0x00, 0x0f, 0x22, 0xd8,
+    0xb9, 0x80, 0x00, 0x00, 0xc0, 0x8b, 0x83, 0x40,
+    0x02, 0x00, 0x00, 0x31, 0xd2, 0x0f, 0x30, 0x8b,
+    0x83, 0x34, 0x02, 0x00, 0x00, 0x0f, 0x22, 0xc0,
+    0xff, 0xab, 0x2c, 0x02, 0x00, 0x00, 0x48, 0x8d,
+    0x1d, 0x9b, 0x01, 0x00, 0x00, 0x48, 0x8b, 0x43,
+    0x48, 0x0f, 0x22, 0xe0, 0x48, 0x8b, 0x43, 0x50,
+    0x0f, 0x22, 0xd8, 0x0f, 0x01, 0x53, 0x58, 0x0f,
+    0x01, 0x5b, 0x62, 0x48, 0x8b, 0x63, 0x78, 0x48,
+    0x0f, 0xb7, 0x43, 0x6c, 0x50, 0x48, 0x8d, 0x05,
+    0x03, 0x00, 0x00, 0x00, 0x50, 0x48, 0xcb, 0x66,
+    0x8b, 0x43, 0x6e, 0x8e, 0xd8, 0x8e, 0xc0, 0x8e,
+    0xd0, 0x66, 0x8b, 0x43, 0x70, 0x66, 0x85, 0xc0,
+    0x74, 0x03, 0x0f, 0x00, 0xd8, 0x8b, 0x7b, 0x74,
+    0x48, 0x8b, 0xb3, 0x88, 0x00, 0x00, 0x00, 0x48,
+    0x8b, 0x83, 0x80, 0x00, 0x00, 0x00, 0xc7, 0x83,
+    0x90, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    0x31, 0xed, 0xff, 0xd0, 0xfa, 0xf4, 0xeb, 0xfc,
+];
+
+/// Entry point of application processor. Receives APIC ID of the
+/// processor and the argument given to `ApBootstrap`. Runs on the stack
+/// given in `ApResources` with interrupts disabled.
+pub type ApEntry = extern "C" fn(apic_id: u32, arg: usize) -> !;
+
+/// Data passed from BSP to the trampoline. Layout must match the offsets
+/// used by the trampoline code.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Handoff {
+    temp_gdt        : [u64; 4],     // 0x00
+    temp_gdtr_limit : u16,          // 0x20
+    temp_gdtr_base  : u32,          // 0x22
+    pm32_ptr        : u32,          // 0x26
+    pm32_sel        : u16,          // 0x2A
+    long_mode_ptr   : u32,          // 0x2C
+    long_mode_sel   : u16,          // 0x30
+    _resv0          : u16,
+    cr0             : u32,          // 0x34
+    cr4_early       : u32,          // 0x38
+    cr3_early       : u32,          // 0x3C
+    efer            : u32,          // 0x40
+    _resv1          : u32,
+    cr4             : u64,          // 0x48
+    cr3             : u64,          // 0x50
+    gdtr_limit      : u16,          // 0x58
+    gdtr_base       : u64,          // 0x5A
+    idtr_limit      : u16,          // 0x62
+    idtr_base       : u64,          // 0x64
+    code_sel        : u16,          // 0x6C
+    data_sel        : u16,          // 0x6E
+    tss_sel         : u16,          // 0x70
+    _resv2          : u16,
+    apic_id         : u32,          // 0x74
+    stack_top       : u64,          // 0x78
+    entry           : u64,          // 0x80
+    arg             : u64,          // 0x88
+    ack             : u32,          // 0x90
+    _resv3          : u32,
+}
+
+/// Resources of single application processor.
+#[derive(Clone, Copy)]
+pub struct ApResources {
+    stack_top       : usize,
+    tss_selector    : u16,
+}
+
+/// Application processors bootstrap. Places the trampoline in low memory,
+/// starts listed processors one by one and waits for each of them
+/// to reach 64-bit entry point.
+pub struct ApBootstrap<'a> {
+    apic            : &'a mut LocalApic,
+    startup         : ApStartup,
+
+    cr3             : Cr3,
+    gdtr            : Option<(u64, u16)>,
+    idtr            : (u64, u16),
+    code_sel        : u16,
+    data_sel        : u16,
+
+    entry           : ApEntry,
+    arg             : usize,
+    timeout_us      : u32,
+}
+
+/// Result of application processors bootstrap.
+pub struct ApBootReport {
+    online          : [u32; MAX_APS],
+    online_count    : usize,
+    failed          : [u32; MAX_APS],
+    failed_count    : usize,
+}
+
+impl ApResources {
+
+    /// Resources with given stack. Stack top is aligned down to 16 bytes.
+    pub fn new(stack_top: usize) -> Self {
+        ApResources {
+            stack_top       : stack_top & !0xF,
+            tss_selector    : 0,
+        }
+    }
+
+    /// Load given TSS selector into task register of the processor.
+    /// RSP0 of the TSS is set to the top of given kernel stack, aligned
+    /// down to 16 bytes. It is used on interrupts from user mode and must
+    /// not overlap the stack the processor starts with, otherwise such
+    /// interrupt overwrites frames of the entry point. TSS descriptor in
+    /// the GDT must point to given TSS and must not be busy.
+    pub fn with_tss(mut self, selector: u16, tss: &mut Tss,
+            rsp0_top: usize) -> Self {
+        tss.rsp0 = (rsp0_top & !0xF) as u64;
+        self.tss_selector = selector;
+        self
+    }
+
+    pub fn stack_top(&self) -> usize {
+        self.stack_top
+    }
+
+    pub fn tss_selector(&self) -> u16 {
+        self.tss_selector
+    }
+}
+
+impl<'a> ApBootstrap<'a> {
+
+    /// Create bootstrap that starts application processors at given entry
+    /// point. CR3 of current processor is used by default. If GDT is not
+    /// set then the temporary GDT of the trampoline is kept.
+    ///
+    /// IDT is empty unless set with `with_idt`. Processors run with
+    /// interrupts disabled, but NMI or exception before the entry point
+    /// loads its own IDT triple-faults and resets the processor.
+    ///
+    /// # Safety
+    /// Trampoline page of given startup settings must be identity mapped
+    /// both now and in the address space of used CR3, and must not be
+    /// used for anything else while processors are running in it.
+    pub unsafe fn new(apic: &'a mut LocalApic, startup: ApStartup,
+            entry: ApEntry, arg: usize) -> Self {
+        ApBootstrap {
+            apic,
+            startup,
+
+            cr3             : Cr3::read(),
+            gdtr            : None,
+            idtr            : (0, 0),
+            code_sel        : TEMP_CODE64_SEL,
+            data_sel        : TEMP_DATA_SEL,
+
+            entry,
+            arg,
+            timeout_us      : 200_000,
+        }
+    }
+
+    /// Address space of application processors. PML4 must be located
+    /// below 4 GiB.
+    pub fn with_cr3(mut self, cr3: Cr3) -> Self {
+        self.cr3 = cr3;
+        self
+    }
+
+    /// GDT to be loaded by application processors and selectors of
+    /// 64-bit code and data segments in it.
+    pub fn with_gdt<'r, R>(mut self, gdtr: &R, code_sel: u16,
+            data_sel: u16) -> Self
+            where R: RegValue<'r> {
+        self.gdtr = Some((gdtr.addr(), gdtr.limit()));
+        self.code_sel = code_sel;
+        self.data_sel = data_sel;
+        self
+    }
+
+    /// IDT to be loaded by application processors before they reach the
+    /// entry point. Should handle at least NMI and exceptions.
+    pub fn with_idt<'r, R>(mut self, idtr: &R) -> Self
+            where R: RegValue<'r> {
+        self.idtr = (idtr.addr(), idtr.limit());
+        self
+    }
+
+    /// Time to wait for each processor to reach the entry point.
+    pub fn with_timeout_us(mut self, us: u32) -> Self {
+        self.timeout_us = us;
+        self
+    }
+
+    fn trampoline_addr(&self) -> usize {
+        self.startup.trampoline_addr()
+    }
+
+    /// Handoff with values common for all processors.
+    unsafe fn handoff(&self) -> Handoff {
+        let base = self.trampoline_addr() as u32;
+        let hw = ::hw::backend();
+
+        let cr0 = hw.read_cr(ControlReg::Cr0);
+        let cr4 = hw.read_cr(ControlReg::Cr4);
+        let efer = ::msr::Info::read_by_id(EFER).eax & !EFER_LMA;
+
+        let temp_gdtr_base = base + HANDOFF_OFFSET as u32;
+        let temp_gdtr_limit = (TEMP_GDT.len() * 8 - 1) as u16;
+        let (gdtr_base, gdtr_limit) = self.gdtr.unwrap_or(
+                (temp_gdtr_base as u64, temp_gdtr_limit));
+
+        Handoff {
+            temp_gdt        : TEMP_GDT,
+            temp_gdtr_limit,
+            temp_gdtr_base,
+            pm32_ptr        : base + PM32_OFFSET,
+            pm32_sel        : TEMP_CODE32_SEL,
+            long_mode_ptr   : base + LONG_MODE_OFFSET,
+            long_mode_sel   : TEMP_CODE64_SEL,
+            _resv0          : 0,
+            cr0             : cr0 as u32,
+            cr4_early       : (cr4 & !(CR4_PCIDE | CR4_CET)) as u32,
+            cr3_early       : (self.cr3.raw() & 0xFFFF_F000) as u32,
+            efer,
+            _resv1          : 0,
+            cr4,
+            cr3             : self.cr3.raw(),
+            gdtr_limit,
+            gdtr_base,
+            idtr_limit      : self.idtr.1,
+            idtr_base       : self.idtr.0,
+            code_sel        : self.code_sel,
+            data_sel        : self.data_sel,
+            tss_sel         : 0,
+            _resv2          : 0,
+            apic_id         : 0,
+            stack_top       : 0,
+            entry           : self.entry as usize as u64,
+            arg             : self.arg as u64,
+            ack             : 0,
+            _resv3          : 0,
+        }
+    }
+
+    fn ack(&self) -> bool {
+        let addr = self.trampoline_addr() + HANDOFF_OFFSET + ACK_OFFSET;
+        unsafe { ::core::ptr::read_volatile(addr as *const u32) != 0 }
+    }
+
+    /// Start single processor and wait for it to reach the entry point.
+    unsafe fn start_one<D>(&mut self, handoff: &Handoff, delay_us: &mut D)
+            -> bool
+            where D: FnMut(u32) {
+        let addr = self.trampoline_addr() + HANDOFF_OFFSET;
+        ::core::ptr::write_volatile(addr as *mut Handoff, *handoff);
+
+        // Handoff must be visible before the IPI. WRMSR to x2APIC ICR is
+        // not serializing.
+        ::core::sync::atomic::fence(::core::sync::atomic::Ordering::SeqCst);
+
+        let id = handoff.apic_id;
+        if !self.apic.start_ap(id, &self.startup, &mut *delay_us) {
+            return false;
+        }
+
+        let mut waited = 0;
+        while !self.ack() {
+            if waited >= self.timeout_us {
+                return false;
+            }
+            delay_us(10);
+            waited += 10;
+        }
+        true
+    }
+
+    /// Start processors with given APIC IDs. ID of current processor is
+    /// skipped. Resources for each processor are requested from given
+    /// function and processor is not started if None is returned. Delays
+    /// are performed by given function that receives the delay in
+    /// microseconds.
+    ///
+    /// When a processor fails to acknowledge its start in time it may
+    /// still be reading the handoff structure, so the remaining
+    /// processors are not started and are reported as failed.
+    ///
+    /// None is returned and nothing is started if PML4 lies above 4 GiB.
+    ///
+    /// # Safety
+    /// Listed processors must be in wait-for-SIPI state. Entry point,
+    /// GDT, IDT and page tables must be valid for them.
+    pub unsafe fn start<F, D>(&mut self, apic_ids: &[u32], mut resources: F,
+            mut delay_us: D) -> Option<ApBootReport>
+            where F: FnMut(u32) -> Option<ApResources>, D: FnMut(u32) {
+        if self.cr3.raw() > 0xFFFF_FFFF {
+            return None;
+        }
+
+        ::core::ptr::copy_nonoverlapping(TRAMPOLINE.as_ptr(),
+                self.trampoline_addr() as *mut u8, TRAMPOLINE.len());
+
+        let mut report = ApBootReport::new();
+        let mut handoff = self.handoff();
+        let bsp = self.apic.apic_id();
+        let mut stopped = false;
+
+        for &id in apic_ids.iter() {
+            if id == bsp {
+                continue;
+            }
+
+            let res = if stopped { None } else { resources(id) };
+            let res = match res {
+                Some(r) => r,
+                None    => {
+                    report.push_failed(id);
+                    continue;
+                }
+            };
+
+            handoff.apic_id = id;
+            handoff.stack_top = res.stack_top as u64;
+            handoff.tss_sel = res.tss_selector;
+
+            if self.start_one(&handoff, &mut delay_us) {
+                report.push_online(id);
+            } else {
+                report.push_failed(id);
+                stopped = true;
+            }
+        }
+        Some(report)
+    }
+}
+
+impl ApBootReport {
+
+    fn new() -> Self {
+        ApBootReport {
+            online          : [0; MAX_APS],
+            online_count    : 0,
+            failed          : [0; MAX_APS],
+            failed_count    : 0,
+        }
+    }
+
+    fn push_online(&mut self, id: u32) {
+        if self.online_count < MAX_APS {
+            self.online[self.online_count] = id;
+            self.online_count += 1;
+        }
+    }
+
+    fn push_failed(&mut self, id: u32) {
+        if self.failed_count < MAX_APS {
+            self.failed[self.failed_count] = id;
+            self.failed_count += 1;
+        }
+    }
+
+    /// APIC IDs of processors that reached the entry point.
+    pub fn online(&self) -> &[u32] {
+        &self.online[..self.online_count]
+    }
+
+    /// APIC IDs of processors that were not started or did not reach
+    /// the entry point in time.
+    pub fn failed(&self) -> &[u32] {
+        &self.failed[..self.failed_count]
+    }
+
+    /// Whether processor with given APIC ID came online.
+    pub fn is_online(&self, apic_id: u32) -> bool {
+        self.online().contains(&apic_id)
+    }
+}
@@ -0,0 +1,126 @@
+use pit::{Divisor, OperatingMode, AccessMode, Pit};
+
+/// Frequency of beeps sent by `Speaker::beep_code`.
+const BEEP_FREQUENCY: u32 = 1000;
+
+/// Duration of a beep and of a pause between beeps in milliseconds.
+const BEEP_DURATION_MS: u32 = 150;
+
+/// Pause after a beep code is finished in milliseconds.
+const BEEP_CODE_PAUSE_MS: u32 = 600;
+
+/// PC speaker interface. Tone is generated by PIT channel 2 in square
+/// wave mode. Durations are measured by polling PIT channel 0 which must
+/// be running in periodic mode (see `Pit::ch0_delay_ms`). Its reload
+/// value does not need to be known to the `Pit`.
+pub struct Speaker<'a> {
+    pit     : &'a mut Pit,
+}
+
+/// Single note of a melody.
+#[derive(Clone, Copy)]
+pub struct Note {
+
+    /// Tone frequency in Hz. Zero means rest.
+    pub frequency   : u32,
+
+    /// Duration in milliseconds.
+    pub duration_ms : u32,
+}
+
+impl Note {
+
+    /// Note with given frequency and duration.
+    pub fn new(frequency: u32, duration_ms: u32) -> Self {
+        Note {
+            frequency,
+            duration_ms,
+        }
+    }
+
+    /// Silence of given duration.
+    pub fn rest(duration_ms: u32) -> Self {
+        Self::new(0, duration_ms)
+    }
+}
+
+impl<'a> Speaker<'a> {
+
+    /// Create speaker interface that uses given PIT. Speaker is not
+    /// enabled until `enable` or `play` is called.
+    pub fn new(pit: &'a mut Pit) -> Self {
+        Speaker { pit }
+    }
+
+    /// Program channel 2 to generate square wave of given frequency.
+    /// Returns divisor that was set, which tells the actually achieved
+    /// frequency, or None if frequency is out of PIT range.
+    pub fn set_frequency(&mut self, hz: u32) -> Option<Divisor> {
+        let div = Divisor::from_frequency(hz)?;
+
+        self.pit.ch2_set_access(AccessMode::LoHiByte);
+        self.pit.ch2_set_operating(OperatingMode::SquareWaveGenerator);
+        self.pit.ch2_set_divisor(div);
+        self.pit.ch2_commit_all();
+
+        Some(div)
+    }
+
+    /// Connect channel 2 output to the speaker.
+    pub fn enable(&mut self) {
+        self.pit.set_speaker(true);
+    }
+
+    /// Disconnect channel 2 output from the speaker.
+    pub fn disable(&mut self) {
+        self.pit.set_speaker(false);
+    }
+
+    /// Whether speaker is currently sounding.
+    pub fn is_enabled(&self) -> bool {
+        self.pit.speaker_enabled()
+    }
+
+    /// Busy-wait given amount of milliseconds. Channel 2 is busy with
+    /// the tone so channel 0 is polled.
+    fn wait_ms(&self, ms: u32) {
+        self.pit.ch0_delay_ms(ms as u64);
+    }
+
+    /// Play tone of given frequency for given duration. Zero frequency
+    /// plays silence. Returns false if frequency is out of PIT range, in
+    /// which case nothing is played.
+    pub fn play(&mut self, hz: u32, duration_ms: u32) -> bool {
+        if hz == 0 {
+            self.disable();
+        } else if self.set_frequency(hz).is_some() {
+            self.enable();
+        } else {
+            return false;
+        }
+
+        self.wait_ms(duration_ms);
+        self.disable();
+        true
+    }
+
+    /// Play given notes one after another. Notes with frequency out
+    /// of PIT range are played as rests.
+    pub fn play_notes(&mut self, notes: &[Note]) {
+        for note in notes.iter() {
+            if !self.play(note.frequency, note.duration_ms) {
+                self.wait_ms(note.duration_ms);
+            }
+        }
+    }
+
+    /// Beep given amount of times followed by a longer pause. Is used to
+    /// report error codes when no other output is available.
+    pub fn beep_code(&mut self, count: u32) {
+        for _ in 0..count {
+            self.play(BEEP_FREQUENCY, BEEP_DURATION_MS);
+            self.wait_ms(BEEP_DURATION_MS);
+        }
+        self.wait_ms(BEEP_CODE_PAUSE_MS);
+    }
+}
@@ -93,6 +93,8 @@ impl CallGateDescriptor {
         self.flags
     }
 
+    /// # Safety
+    /// Flags are not checked and may make the descriptor invalid.
     pub unsafe fn set_flags(&mut self, flags: u16) {
         self.flags = flags;
     }
@@ -114,10 +116,14 @@ macro_rules! impl_tss_ldt {
                 (self.flags0, self.flags1)
             }
 
+            /// # Safety
+            /// Flags are not checked and may make the descriptor invalid.
             pub unsafe fn set_flags0(&mut self, flags: u16) {
                 self.flags0 = flags;
             }
 
+            /// # Safety
+            /// Flags are not checked and may make the descriptor invalid.
             pub unsafe fn set_flags1(&mut self, flags: u8) {
                 self.flags1 = flags;
             }
@@ -144,6 +150,8 @@ macro_rules! impl_tss_ldt {
                 ((self.base2 as u64) << 0x18)
             }
 
+            /// # Safety
+            /// Segment must be valid at given base address.
             pub unsafe fn set_base(&mut self, base: u64) {
                 self.base0 = ((base & 0x00000000_0000FFFF) >> 0x00) as _;
                 self.base1 = ((base & 0x00000000_00FF0000) >> 0x10) as _;
@@ -154,6 +162,8 @@ macro_rules! impl_tss_ldt {
                 self.limit
             }
 
+            /// # Safety
+            /// Segment must be valid with given limit.
             pub unsafe fn set_limit(&mut self, limit: u16) {
                 self.limit = limit;
             }
@@ -6,6 +6,7 @@ pub use self::ent::*;
 
 /// GDT controller. All operations on GDT are made through methods of this
 /// controller.
+#[allow(dead_code)]
 pub struct GdtCtrl {
     limit   : u16,
     addr    : u64,
@@ -138,8 +138,11 @@ pub trait IdtGate: Entry {
     fn offset_fields(&self) -> (u16, u16, u32);
 
     /// Set the address of the function that handles the interrupt.
+    ///
+    /// # Safety
+    /// Offset must point to valid interrupt handler.
     unsafe fn set_offset(&mut self, offset: u64) {
-        let a = (offset >> 00) & 0xFFFF;
+        let a = offset & 0xFFFF;
         let b = (offset >> 16) & 0xFFFF;
         let c = (offset >> 32) & 0xFFFFFFFF;
 
@@ -147,11 +150,16 @@ pub trait IdtGate: Entry {
     }
 
     /// Set fields with offset as they are stored in descriptor.
+    ///
+    /// # Safety
+    /// Offset must point to valid interrupt handler.
     unsafe fn set_offset_fields(&mut self, offset: (u16, u16, u32));
 
     /// Segment Selector.
     fn segsel(&self) -> u16;
 
+    /// # Safety
+    /// Selector must point to valid code segment descriptor.
     unsafe fn set_segsel(&mut self, ss: u16);
 
     /// Interrupt Stack Table.
@@ -159,6 +167,8 @@ pub trait IdtGate: Entry {
         unimplemented!()
     }
 
+    /// # Safety
+    /// Stack with given index must be set in TSS.
     unsafe fn set_ist(&mut self, ist: Ist) {
         let v = ist as u16;
         let a = self.flags() & !0x03;
@@ -172,6 +182,9 @@ pub trait IdtGate: Entry {
     }
 
     /// Change present flag value.
+    ///
+    /// # Safety
+    /// Gate must be fully configured before it is marked present.
     unsafe fn set_present(&mut self, v: bool) {
         let a = self.flags() & !(1 << 15);
         self.set_flags(
@@ -195,6 +208,8 @@ pub trait IdtGate: Entry {
         }
     }
 
+    /// # Safety
+    /// Privilege level must allow only intended code to use the gate.
     unsafe fn set_dpl(&mut self, dpl: Dpl) {
         let v = dpl as u16;
         let v = v << 13;
@@ -218,6 +233,9 @@ pub trait IdtGate: Entry {
 
     /// Set all flags with given value. Does not check if value is correct nor
     /// change any of it's bit. Even if some bits must be zero (but are set).
+    ///
+    /// # Safety
+    /// Flags must be valid for the gate.
     unsafe fn set_flags(&mut self, f: u16);
 }
 
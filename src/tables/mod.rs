@@ -85,6 +85,9 @@ pub trait RegValue<'a>: Sized {
     type HandleType: Table<'a>;
 
     /// Write current value to appropriate DTR.
+    ///
+    /// # Safety
+    /// Table must be valid and stay in memory while it is loaded.
     unsafe fn write(&self);
 
     /// Read current value from appropriate DTR.
@@ -103,9 +106,15 @@ pub trait RegValue<'a>: Sized {
     fn limit(&self) -> u16;
 
     /// Set address of DT.
+    ///
+    /// # Safety
+    /// Address must point to valid table before the value is written.
     unsafe fn set_addr(&mut self, addr: u64);
 
     /// Set limit of DT.
+    ///
+    /// # Safety
+    /// Limit must not cover memory outside of the table.
     unsafe fn set_limit(&mut self, limit: u16);
 
     /// Consume DTR value and get DT handle.
@@ -160,13 +169,15 @@ impl From<u16> for DescriptorType {
     fn from(v: u16) -> Self {
         use self::DescriptorType::*;
 
-        if v == Ldt             as _ { Ldt              } else
-        if v == TssAvailable    as _ { TssAvailable     } else
-        if v == TssBusy         as _ { TssBusy          } else
-        if v == CallGate        as _ { CallGate         } else
-        if v == InterruptGate   as _ { InterruptGate    } else
-        if v == TrapGate        as _ { TrapGate         } else
-        { Reserved }
+        match v {
+            v if v == Ldt           as u16  => Ldt,
+            v if v == TssAvailable  as u16  => TssAvailable,
+            v if v == TssBusy       as u16  => TssBusy,
+            v if v == CallGate      as u16  => CallGate,
+            v if v == InterruptGate as u16  => InterruptGate,
+            v if v == TrapGate      as u16  => TrapGate,
+            _                               => Reserved,
+        }
     }
 }
 
@@ -184,6 +195,9 @@ impl DescriptorType {
 
     /// Convert number to DescriptorType without checking if the value
     /// is a valid enum variant.
+    ///
+    /// # Safety
+    /// Value must be a valid variant.
     pub unsafe fn fast_from(v: u16) -> Self {
         ::core::mem::transmute(v)
     }
@@ -197,12 +211,18 @@ pub trait DtLimit<'a>: Table<'a> {
 
     /// Set limit to given value. Function does not check if given limit
     /// is of a valid value.
+    ///
+    /// # Safety
+    /// Limit must not cover memory outside of the table.
     unsafe fn set_limit(&mut self, limit: u16);
 
     /// Set entry count of entry table. This count is converted
     /// to apropriate limit value and is set in the handle. This
     /// function does not check if element count does not exceed
     /// valid value.
+    ///
+    /// # Safety
+    /// Count must not cover memory outside of the table.
     unsafe fn set_limit_by_entry_count(&mut self, count: u16) {
         self.set_limit(Self::limit_from_index(count));
     }
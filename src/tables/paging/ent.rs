@@ -1,4 +1,5 @@
 use super::{Entry, EntryVariant};
+use cpuid::AddressWidths;
 
 /// Page Table entry. Page table level 1 entry. Maps 4KiB page.
 #[repr(packed)]
@@ -17,6 +18,7 @@ pub struct P2EMap {
 /// Page Directory entry. Page table level 2 entry. References P1 table.
 #[repr(packed)]
 #[derive(Clone, Copy)]
+#[derive(Default)]
 pub struct P2ERef {
     data: u64
 }
@@ -59,14 +61,6 @@ impl Default for P2EMap {
     }
 }
 
-impl Default for P2ERef {
-
-    fn default() -> Self {
-        P2ERef {
-            data : 0 // PS is off.
-        }
-    }
-}
 
 pub enum P1EVariant<'a> {
     P1E(&'a mut P1E)
@@ -97,32 +91,32 @@ impl<'a> EntryVariant for P3EVariant<'a> {
 impl<'a> EntryVariant for P4EVariant<'a> {
 }
 
-new_bitflags! {
-    pub flags PageFlag: u64 {
-        const present   = 1 << 0x00;
-        const rw        = 1 << 0x01;
-        const us        = 1 << 0x02;
-        const pwt       = 1 << 0x03;
-        const pcd       = 1 << 0x04;
-        const accessed  = 1 << 0x05;
-        const dirty     = 1 << 0x06;
-        const pat       = 1 << 0x07;
-        const ps        = 1 << 0x07;
-        const global    = 1 << 0x08;
-        const xd        = 1 << 0x3F;
-
-        const p1addr    = 0x0007FFFFFFFFF800;
-        const p2addrmap = 0x0007FFFFFFFFF800;
-        const p2addrref = 0x0007FFFFFFFFF000;
-        const p3addr    = 0x0007FFFFFFFFF800;
-        const p4addr    = 0x0007FFFFFFFFF800;
+// `new_bitflags!` implements `PartialEq::ne` by hand, the module keeps
+// the allowance to the generated code only.
+#[allow(clippy::partialeq_ne_impl)]
+mod flags {
+    new_bitflags! {
+        pub flags PageFlag: u64 {
+            const present   = 1 << 0x00;
+            const rw        = 1 << 0x01;
+            const us        = 1 << 0x02;
+            const pwt       = 1 << 0x03;
+            const pcd       = 1 << 0x04;
+            const accessed  = 1 << 0x05;
+            const dirty     = 1 << 0x06;
+            const pat       = 1 << 0x07;
+            const ps        = 1 << 0x07;
+            const global    = 1 << 0x08;
+            const xd        = 1 << 0x3F;
+        }
     }
 }
+pub use self::flags::PageFlag;
 
-impl Into<u64> for PageFlag {
+impl From<PageFlag> for u64 {
 
-    fn into(self) -> u64 {
-        unsafe { ::core::mem::transmute(self) }
+    fn from(val: PageFlag) -> Self {
+        unsafe { ::core::mem::transmute(val) }
     }
 }
 
@@ -220,3 +214,186 @@ _impl!(P2EMap);
 _impl!(P2ERef);
 _impl!(P3E);
 _impl!(P4E);
+
+/// Implement physical address accessors of the entry. Masks are derived
+/// from `AddressWidths` so addresses beyond MAXPHYADDR are rejected.
+macro_rules! addr_impl {
+    ($name:ident) => (
+        impl $name {
+
+            /// Physical address this entry points to.
+            pub fn address(&self, widths: &AddressWidths) -> u64 {
+                self.data & widths.entry_addr_mask(self.addr_align_bits())
+            }
+
+            /// Set physical address this entry points to. Returns false and
+            /// leaves the entry unchanged if the address is not aligned or
+            /// exceeds MAXPHYADDR.
+            ///
+            /// # Safety
+            /// Changing paging tables may violate memory consistency.
+            pub unsafe fn set_address(&mut self, addr: u64,
+                    widths: &AddressWidths) -> bool {
+                let mask = widths.entry_addr_mask(self.addr_align_bits());
+                if addr & !mask != 0 {
+                    return false;
+                }
+                self.data = (self.data & !mask) | addr;
+                true
+            }
+
+            /// Whether reserved address bits above MAXPHYADDR and below
+            /// the page alignment of mapping entries are clear.
+            pub fn is_valid(&self, widths: &AddressWidths) -> bool {
+                let reserved = widths.entry_reserved_mask()
+                        | self.low_reserved_mask();
+                self.data & reserved == 0
+            }
+        }
+    );
+}
+
+/// Reserved bits between PAT bit 12 and the address of large page.
+const P2_MAP_RESERVED: u64 = 0x0000_0000_001F_E000; // Bits 20:13.
+const P3_MAP_RESERVED: u64 = 0x0000_0000_3FFF_E000; // Bits 29:13.
+
+impl P1E {
+    fn addr_align_bits(&self) -> u8 { 12 }
+    fn low_reserved_mask(&self) -> u64 { 0 }
+}
+
+impl P2EMap {
+    fn addr_align_bits(&self) -> u8 { 21 }
+    fn low_reserved_mask(&self) -> u64 { P2_MAP_RESERVED }
+}
+
+impl P2ERef {
+    fn addr_align_bits(&self) -> u8 { 12 }
+    fn low_reserved_mask(&self) -> u64 { 0 }
+}
+
+impl P3E {
+
+    fn addr_align_bits(&self) -> u8 {
+        if self.is_map() { 30 } else { 12 }
+    }
+
+    fn low_reserved_mask(&self) -> u64 {
+        if self.is_map() { P3_MAP_RESERVED } else { 0 }
+    }
+
+    /// Whether this entry maps 1 GiB page rather than references
+    /// P2 table.
+    pub fn is_map(&self) -> bool {
+        let ps: u64 = PageFlag::ps().into();
+        self.data & ps != 0
+    }
+
+    /// Make this entry map 1 GiB page at given physical address. Returns
+    /// false and leaves the entry unchanged if 1 GiB pages are not
+    /// supported, the address is not aligned or exceeds MAXPHYADDR.
+    ///
+    /// # Safety
+    /// Changing paging tables may violate memory consistency.
+    pub unsafe fn set_map_address(&mut self, addr: u64,
+            widths: &AddressWidths) -> bool {
+        let mask = widths.entry_addr_mask(30);
+        if !widths.page_1g_supported() || addr & !mask != 0 {
+            return false;
+        }
+
+        let ps: u64 = PageFlag::ps().into();
+        let old = widths.entry_addr_mask(12);
+        self.data = (self.data & !old) | addr | ps;
+        true
+    }
+}
+
+impl P4E {
+    fn addr_align_bits(&self) -> u8 { 12 }
+    fn low_reserved_mask(&self) -> u64 { 0 }
+}
+
+addr_impl!(P1E);
+addr_impl!(P2EMap);
+addr_impl!(P2ERef);
+addr_impl!(P3E);
+addr_impl!(P4E);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn widths() -> AddressWidths {
+        AddressWidths::new(39, 48, false, true)
+    }
+
+    #[test]
+    fn set_address_rejects_beyond_maxphyaddr_and_unaligned() {
+        let w = widths();
+        let mut e = P1E::default();
+        unsafe {
+            assert!(!e.set_address(1 << 39, &w));
+            assert!(!e.set_address(0x1000 | 0x800, &w));
+        }
+        assert_eq!(e.address(&w), 0);
+
+        let mut e = P2EMap::default();
+        unsafe {
+            assert!(!e.set_address(0x10_0000, &w)); // Only 1 MiB aligned.
+        }
+        assert_eq!(e.address(&w), 0);
+
+        let mut e = P3E::default();
+        unsafe {
+            assert!(!e.set_map_address(0x20_0000, &w));
+            let no_1g = AddressWidths::new(39, 48, false, false);
+            assert!(!e.set_map_address(0x4000_0000, &no_1g));
+        }
+        assert!(!e.is_map());
+    }
+
+    #[test]
+    fn set_address_accepts_top_of_physical_space() {
+        let w = widths();
+        let top = (1u64 << 39) - 0x1000;
+
+        let mut e = P1E::default();
+        unsafe {
+            e.data_or(PageFlag::present() | PageFlag::rw());
+            assert!(e.set_address(top, &w));
+        }
+        assert_eq!(e.address(&w), top);
+        assert_eq!({ e.data }, top | 0b11);
+        assert!(e.is_valid(&w));
+
+        let mut e = P3E::default();
+        unsafe {
+            assert!(e.set_map_address((1u64 << 39) - 0x4000_0000, &w));
+        }
+        assert!(e.is_map());
+        assert_eq!(e.address(&w), (1u64 << 39) - 0x4000_0000);
+        assert!(e.is_valid(&w));
+    }
+
+    #[test]
+    fn is_valid_checks_reserved_bits() {
+        let w = widths();
+
+        let e = P1E { data: 1 << 39 };
+        assert!(!e.is_valid(&w));
+        let e = P1E { data: 1 << 13 };
+        assert!(e.is_valid(&w));
+
+        // PAT bit 12 is allowed, bits 20:13 are reserved.
+        let ps: u64 = PageFlag::ps().into();
+        assert!(P2EMap { data: ps | 1 << 12 }.is_valid(&w));
+        assert!(!P2EMap { data: ps | 1 << 13 }.is_valid(&w));
+        assert!(!P2EMap { data: ps | 1 << 20 }.is_valid(&w));
+        assert!(P2ERef { data: 1 << 13 }.is_valid(&w));
+
+        assert!(P3E { data: ps | 1 << 12 }.is_valid(&w));
+        assert!(!P3E { data: ps | 1 << 29 }.is_valid(&w));
+        assert!(P3E { data: 1 << 29 }.is_valid(&w));
+    }
+}
@@ -53,7 +53,7 @@ macro_rules! new_handle {
             /// Create given handle by pointing out actual entry in memory.
             pub fn from_raw_addr(addr: u64) -> Self {
                 $name {
-                    addr: addr
+                    addr
                 }
             }
         }
@@ -83,7 +83,7 @@ impl<'a> EntryHandle<'a> for P2EHandle {
 
         let is_map = |data: u64| -> bool {
             let val: u64 = PageFlag::ps().into();
-            val & data == 1
+            val & data != 0
         };
 
         unsafe {
@@ -102,6 +102,8 @@ impl P2EHandle {
     ///
     /// # Safety
     /// Changing paging tables may violate memory consistency.
+    // Handle points to the table memory, not into itself.
+    #[allow(clippy::mut_from_ref)]
     pub unsafe fn set_ref(&self, e: P2ERef) -> &mut P2ERef {
         let ptr = self.addr as *const P2ERef as *mut _;
         *ptr = e;
@@ -112,6 +114,8 @@ impl P2EHandle {
     ///
     /// # Safety
     /// Changing paging tables may violate memory consistency.
+    // Handle points to the table memory, not into itself.
+    #[allow(clippy::mut_from_ref)]
     pub unsafe fn set_map(&self, e: P2EMap) -> &mut P2EMap {
         let ptr = self.addr as *const P2EMap as *mut _;
         *ptr = e;
@@ -157,11 +161,12 @@ macro_rules! impl_table {
             }
 
             fn entry_count(&self) -> u16 {
-                self.entries.len() as _ // always 512
+                // Entries of packed table cannot be borrowed.
+                (::core::mem::size_of::<$x>() / 8) as _ // always 512
             }
 
             fn addr(&self) -> u64 {
-                &self.entries[0] as *const _ as _
+                ::core::ptr::addr_of!(self.entries) as *const u8 as _
             }
 
             fn limit_step() -> u16 { 8 }
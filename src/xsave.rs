@@ -1,3 +1,4 @@
+use core::arch::asm;
 pub use msr::Xss as XssMsr;
 pub use cr::Xcr0;
 
@@ -83,10 +84,10 @@ impl Mask {
             can be executed even with the flag unset.");
 }
 
-impl Into<u64> for Mask {
+impl From<Mask> for u64 {
 
-    fn into(self) -> u64 {
-        self.val
+    fn from(v: Mask) -> u64 {
+        v.val
     }
 }
 
@@ -100,15 +101,18 @@ impl From<u64> for Mask {
 macro_rules! impl_xsave {
     ($name:ident, $ins:expr, $doc:tt) => {
         #[doc=$doc]
+        ///
+        /// # Safety
+        /// Area must be valid, 64-byte aligned and large enough for the
+        /// components in the mask. XSAVE must be enabled in CR4.
         pub unsafe fn $name(xarea: u64, mask: Mask) {
             let eax = (mask.val      ) as u32;
             let edx = (mask.val >> 32) as u32;
             asm!(
-                concat!($ins, " [$0]")
-                :
-                : "r"(xarea), "{eax}"(eax), "{edx}"(edx)
-                :
-                : "intel"
+                concat!($ins, " [{0}]"),
+                in(reg) xarea,
+                in("eax") eax,
+                in("edx") edx,
             );
         }
     };
@@ -118,16 +122,10 @@ macro_rules! impl_xsave {
     };
 }
 
-/// Call XSAVE instruction and pass given xsave memory area and
-/// set given mask.
-///
-/// Note that XSAVE instruction support must be enabled, memory area
-/// needs to be 64-byte aligned.
 impl_xsave!(xsave, "xsave",
-    "Call XSAVE instruction and pass given xsave memory area and
-    set given mask.
-
-    Note that XSAVE instruction support must be enabled, memory area
+    "Call XSAVE instruction and pass given xsave memory area and \
+    set given mask.\n\n\
+    Note that XSAVE instruction support must be enabled, memory area \
     needs to be 64-byte aligned."
 );
 